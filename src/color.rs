@@ -0,0 +1,30 @@
+/// The standard every source is converted to when a plan's sources don't share one
+pub const NORMALIZED: &str = "bt709";
+
+/// The `colorspace` filter's name for the standard video was encoded with, from its `color_space` tag.
+/// Untagged video is assumed to follow the usual standard for its height, as players do
+pub fn standard(color_space: Option<&str>, height: u32) -> &'static str {
+    match color_space {
+        Some("bt709") => "bt709",
+        Some("bt470bg") => "bt601-6-625",
+        Some("smpte170m") => "bt601-6-525",
+        Some("smpte240m") => "smpte240m",
+        Some("bt2020nc" | "bt2020c") => "bt2020",
+        _ if height <= 480 => "bt601-6-525",
+        _ if height <= 576 => "bt601-6-625",
+        _ => "bt709",
+    }
+}
+
+/// Filters converting video encoded with `standard` to [NORMALIZED], to go ahead of the rest of a source's filters.
+/// The `colorspace` filter can't read every pixel format, e.g. DV's yuv411p, so it's given yuv444p
+pub fn conversion_filter(standard: &str) -> String {
+    format!("format=yuv444p,colorspace=all={NORMALIZED}:iall={standard},")
+}
+
+/// Adds the arguments tagging the output as [NORMALIZED] to an ffmpeg `cmd`
+pub fn tag_output(cmd: &mut tokio::process::Command) {
+    cmd.arg("-colorspace").arg(NORMALIZED);
+    cmd.arg("-color_primaries").arg(NORMALIZED);
+    cmd.arg("-color_trc").arg(NORMALIZED);
+}
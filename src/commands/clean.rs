@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use clap::Args;
+
+use crate::{
+    limits::{self, format_byte_size},
+    logging::{self, LogRetention},
+    path,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct CleanArgs {
+    /// Only remove run directories, logs and cached intermediates last modified longer ago than AGE, e.g. 12h or 7d
+    #[arg(long, value_name = "AGE", value_parser = parse_age)]
+    pub older_than: Option<Duration>,
+
+    /// Remove the oldest logs until the logs directory is at most SIZE, e.g. 500M
+    #[arg(long, value_name = "SIZE", value_parser = limits::parse_byte_size)]
+    pub max_log_size: Option<u64>,
+}
+
+/// Parses an age like `30m`, `12h`, `7d` or `2w`
+pub fn parse_age(given: &str) -> Result<Duration, String> {
+    let given = given.trim();
+    let split = given
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(given.len());
+    let (digits, suffix) = given.split_at(split);
+
+    let seconds = match suffix.trim().to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "" => {
+            return Err(format!(
+                "Missing unit in \"{given}\", expected s, m, h, d or w"
+            ));
+        }
+        other => return Err(format!("Unknown age unit \"{other}\"")),
+    };
+
+    let value = digits
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid age \"{given}\": {e}"))?;
+
+    Ok(Duration::from_secs(value.saturating_mul(seconds)))
+}
+
+/// Removes run directories left behind under the temp root by runs that have exited and cached intermediates,
+/// and prunes old logs when asked to
+pub fn clean(args: CleanArgs) -> anyhow::Result<()> {
+    let (removed, mut reclaimed) = path::remove_run_dirs(args.older_than)?;
+    println!(
+        "Removed {removed} run directories ({}) from {}",
+        format_byte_size(reclaimed),
+        path::tmp_root().display()
+    );
+
+    let (removed, intermediates_reclaimed) = path::remove_cached_intermediates(args.older_than)?;
+    println!(
+        "Removed {removed} cached intermediates ({}) from {}",
+        format_byte_size(intermediates_reclaimed),
+        path::intermediates_root().display()
+    );
+    reclaimed += intermediates_reclaimed;
+
+    if args.older_than.is_some() || args.max_log_size.is_some() {
+        let (removed, log_reclaimed) = logging::prune_logs(LogRetention {
+            max_count: None,
+            max_age: args.older_than,
+            max_size: args.max_log_size,
+        })?;
+        println!(
+            "Removed {removed} log files ({}) from {}",
+            format_byte_size(log_reclaimed),
+            path::logs_root().display()
+        );
+        reclaimed += log_reclaimed;
+    }
+
+    println!("Reclaimed {}", format_byte_size(reclaimed));
+
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+
+#[derive(Debug, Clone, Args)]
+pub struct CompletionsArgs {
+    /// Shell to generate completions for
+    #[arg(value_name = "SHELL")]
+    pub shell: Shell,
+}
+
+/// Writes a completion script for `shell` to stdout
+pub fn completions(args: CompletionsArgs) -> anyhow::Result<()> {
+    let mut command = crate::Args::command();
+    let name = command.get_name().to_string();
+
+    clap_complete::generate(args.shell, &mut command, name, &mut std::io::stdout());
+
+    Ok(())
+}
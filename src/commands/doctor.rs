@@ -0,0 +1,231 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Args;
+use libffmpeg::util::cmd;
+use tokio_util::sync::CancellationToken;
+
+use crate::{commands::BinaryArgs, env, limits::format_byte_size, path, probe};
+
+/// Encoders worth calling out when listing what ffmpeg was built with
+const NOTABLE_ENCODERS: &[&str] = &[
+    "libx264",
+    "libx265",
+    "libsvtav1",
+    "libvpx-vp9",
+    "aac",
+    "libopus",
+    "h264_nvenc",
+    "hevc_nvenc",
+    "h264_qsv",
+    "h264_vaapi",
+    "h264_videotoolbox",
+];
+
+/// Free space below which a directory is reported as running low
+const LOW_DISK_SPACE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Args)]
+pub struct DoctorArgs {
+    #[command(flatten)]
+    pub binaries: BinaryArgs,
+}
+
+#[derive(Default)]
+struct Checks {
+    failed: usize,
+}
+impl Checks {
+    fn pass(&self, name: &str, detail: impl std::fmt::Display) {
+        println!("✓ {name}: {detail}");
+    }
+
+    fn warn(&self, name: &str, detail: impl std::fmt::Display) {
+        println!("⚠ {name}: {detail}");
+    }
+
+    fn fail(&mut self, name: &str, detail: impl std::fmt::Display) {
+        println!("✗ {name}: {detail}");
+        self.failed += 1;
+    }
+}
+
+/// Checks everything a run depends on, printing a line per check
+pub async fn doctor(args: DoctorArgs, ct: CancellationToken) -> anyhow::Result<()> {
+    let mut checks = Checks::default();
+
+    match args.binaries.find() {
+        Ok(()) => {
+            for (bin, path) in [
+                ("ffmpeg", env::get_ffmpeg()),
+                ("ffprobe", env::get_ffprobe()),
+            ] {
+                let path = path
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default();
+                match probe::build_info(bin, ct.clone()).await {
+                    Ok(build) if build.version.is_empty() => {
+                        checks.fail(bin, format!("{bin} -version printed nothing ({path})"))
+                    }
+                    Ok(build) => {
+                        checks.pass(bin, format!("{} ({path})", build.version));
+                        // ffprobe is built alongside ffmpeg, its configuration is the same
+                        if bin == "ffmpeg" {
+                            checks.pass("libraries", build.libraries.join(", "));
+                            checks.pass("configuration", build.configuration.join(" "));
+                        }
+                    }
+                    Err(e) => checks.fail(bin, format!("Failed to run {bin}: {e:#} ({path})")),
+                }
+            }
+
+            match encoders(ct.clone()).await {
+                Ok(encoders) => {
+                    let notable: Vec<_> = NOTABLE_ENCODERS
+                        .iter()
+                        .filter(|notable| encoders.iter().any(|encoder| encoder == *notable))
+                        .copied()
+                        .collect();
+                    checks.pass(
+                        "encoders",
+                        format!(
+                            "{} available, including {}",
+                            encoders.len(),
+                            notable.join(", ")
+                        ),
+                    );
+                }
+                Err(e) => checks.fail("encoders", format!("{e:#}")),
+            }
+
+            match test_encode(ct.clone()).await {
+                Ok(size) => checks.pass(
+                    "test encode",
+                    format!("1s test clip encoded ({})", format_byte_size(size)),
+                ),
+                Err(e) => checks.fail("test encode", format!("{e:#}")),
+            }
+        }
+        Err(e) => checks.fail("binaries", e),
+    }
+
+    for (name, dir) in [
+        ("temp directory", path::tmp_root()),
+        ("log directory", path::logs_root()),
+    ] {
+        if let Err(e) = check_writable(&dir) {
+            checks.fail(name, format!("{} is not writable: {e:#}", dir.display()));
+            continue;
+        }
+
+        match free_space(&dir) {
+            Some(free) if free < LOW_DISK_SPACE => checks.warn(
+                name,
+                format!(
+                    "{} is writable, only {} free",
+                    dir.display(),
+                    format_byte_size(free)
+                ),
+            ),
+            Some(free) => checks.pass(
+                name,
+                format!(
+                    "{} is writable, {} free",
+                    dir.display(),
+                    format_byte_size(free)
+                ),
+            ),
+            None => checks.pass(name, format!("{} is writable", dir.display())),
+        }
+    }
+
+    if checks.failed > 0 {
+        anyhow::bail!("{} checks failed", checks.failed);
+    }
+
+    Ok(())
+}
+
+/// Names of every encoder ffmpeg was built with
+async fn encoders(ct: CancellationToken) -> anyhow::Result<Vec<String>> {
+    let results = cmd::run("ffmpeg", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        cmd.arg("-hide_banner").arg("-encoders");
+    })
+    .await
+    .context("Failed to run ffmpeg")?;
+
+    // Encoders are listed after a ` ------` separator as `<flags> <name> <description>`
+    Ok(results
+        .stdout_lines
+        .iter()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Encodes a tiny generated clip into the temp directory, returning its size
+async fn test_encode(ct: CancellationToken) -> anyhow::Result<u64> {
+    let dir = path::run_tmp_root();
+    let output: PathBuf = dir.join("doctor.mp4");
+
+    let results = cmd::run("ffmpeg", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        cmd.arg("-hide_banner").arg("-v").arg("error");
+        cmd.arg("-f")
+            .arg("lavfi")
+            .arg("-i")
+            .arg("testsrc=duration=1:size=128x72:rate=10");
+        cmd.arg("-f").arg("lavfi").arg("-i").arg("sine=duration=1");
+        cmd.arg("-shortest").arg("-y").arg(&output);
+    })
+    .await
+    .context("Failed to run ffmpeg");
+
+    let size = std::fs::metadata(&output).map(|meta| meta.len());
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        tracing::warn!(path =% dir.display(), error =% e, error_context =? e, "Failed to remove test encode directory");
+    }
+
+    let results = results?;
+    if !results.exit_code.is_some_and(|code| code.success) {
+        anyhow::bail!("ffmpeg exited unsuccessfully");
+    }
+
+    match size {
+        Ok(size) if size > 0 => Ok(size),
+        _ => anyhow::bail!("ffmpeg didn't write any output"),
+    }
+}
+
+fn check_writable(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir).context("Failed to create directory")?;
+
+    let probe = dir.join(".stitch-doctor");
+    std::fs::write(&probe, b"").context("Failed to write file")?;
+    std::fs::remove_file(&probe).context("Failed to remove file")?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs is plain old data, zeroed is a valid value
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: dir is nul terminated and stat is a valid statvfs to write into
+    if unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
@@ -0,0 +1,155 @@
+use clap::Args;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    commands::{BinaryArgs, SpecArgs, TargetFilterArgs},
+    execute::first_resolution,
+    limits::{self, format_byte_size},
+    probe,
+    throughput::ThroughputHistory,
+};
+
+/// Assumed encoding speed as a multiple of realtime when no past run has encoded the same way. A stream copy is bound by disk speed, a re-encode by the CPU
+const COPY_SPEED: f64 = 50.0;
+const FILTER_SPEED: f64 = 1.5;
+
+#[derive(Debug, Clone, Args)]
+pub struct EstimateArgs {
+    #[command(flatten)]
+    pub spec: SpecArgs,
+
+    #[command(flatten)]
+    pub targets: TargetFilterArgs,
+
+    #[command(flatten)]
+    pub binaries: BinaryArgs,
+
+    /// Number of plans assumed to run at once (default: the same as `run`)
+    #[arg(
+        env = "STITCH_JOBS",
+        short = 'j',
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+    )]
+    pub jobs: Option<usize>,
+}
+
+struct TargetEstimate {
+    name: String,
+    source_count: usize,
+    duration_seconds: f64,
+    size_bytes: u64,
+    encode_seconds: f64,
+}
+
+/// Formats seconds as e.g. `1h02m03s`
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{hours}h{minutes:02}m{seconds:02}s")
+    } else if minutes > 0 {
+        format!("{minutes}m{seconds:02}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+/// Probes every source and prints expected durations, output sizes and wall clock time, without encoding anything
+pub async fn estimate(
+    args: EstimateArgs,
+    verbose: bool,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    args.binaries.find()?;
+    let plans = args.targets.apply(args.spec.load(verbose)?)?;
+
+    let jobs = args.jobs.unwrap_or_else(|| {
+        let all_copy = plans.iter().all(|plan| !plan.is_encode());
+        limits::default_process_limit(all_copy)
+    });
+
+    let history = ThroughputHistory::load();
+    let mut estimates = Vec::with_capacity(plans.len());
+    for plan in plans {
+        let mut duration_seconds = 0.0f64;
+        let mut size_bytes = 0u64;
+        for source in plan.sources.iter() {
+            let duration = probe::duration(source, cancellation_token.child_token()).await?;
+            duration_seconds += duration.as_secs_f64();
+            // Concatenating doesn't change the bitrate much, a copy is the sum of its parts and a re-encode close to it
+            size_bytes += std::fs::metadata(&source.path)
+                .map(|meta| meta.len())
+                .unwrap_or_default();
+        }
+
+        let resolution = if plan.is_encode() {
+            first_resolution(&plan, cancellation_token.child_token()).await?
+        } else {
+            None
+        };
+        let speed = history
+            .predict_speed(plan.mode(), resolution)
+            .unwrap_or(if plan.is_encode() {
+                FILTER_SPEED
+            } else {
+                COPY_SPEED
+            });
+
+        estimates.push(TargetEstimate {
+            name: plan.target_path.leaf,
+            source_count: plan.sources.len(),
+            duration_seconds,
+            size_bytes,
+            encode_seconds: duration_seconds / speed,
+        });
+    }
+
+    // Plans take job slots in spec order, each starting on whichever slot frees up first
+    let mut slots = vec![0.0f64; jobs];
+    for estimate in estimates.iter() {
+        if let Some(slot) = slots.iter_mut().min_by(|a, b| a.total_cmp(b)) {
+            *slot += estimate.encode_seconds;
+        }
+    }
+    let wall_seconds = slots.into_iter().fold(0.0, f64::max);
+
+    let name_width = estimates
+        .iter()
+        .map(|estimate| estimate.name.len())
+        .max()
+        .unwrap_or_default()
+        .max("target".len());
+    println!(
+        "{:<name_width$}  {:>7}  {:>10}  {:>11}  {:>10}",
+        "target", "sources", "duration", "size", "encode"
+    );
+    for estimate in estimates.iter() {
+        println!(
+            "{:<name_width$}  {:>7}  {:>10}  {:>11}  {:>10}",
+            estimate.name,
+            estimate.source_count,
+            format_duration(estimate.duration_seconds),
+            format!("~{}", format_byte_size(estimate.size_bytes)),
+            format!("~{}", format_duration(estimate.encode_seconds)),
+        );
+    }
+    println!();
+
+    let total_duration: f64 = estimates.iter().map(|e| e.duration_seconds).sum();
+    let total_size: u64 = estimates.iter().map(|e| e.size_bytes).sum();
+    println!(
+        "{} targets, {} of output, ~{}",
+        estimates.len(),
+        format_duration(total_duration),
+        format_byte_size(total_size)
+    );
+    println!(
+        "Predicted wall clock time with {jobs} jobs: ~{}",
+        format_duration(wall_seconds)
+    );
+
+    Ok(())
+}
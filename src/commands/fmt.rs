@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, bail};
+use clap::Args;
+
+use crate::parse::format_spec;
+
+#[derive(Debug, Clone, Args)]
+pub struct FmtArgs {
+    /// Specification files to format in place
+    #[arg(value_name = "SPEC_FILE", required = true)]
+    pub specs: Vec<PathBuf>,
+
+    /// Don't write anything, fail if any spec isn't formatted, e.g. in CI
+    #[arg(long)]
+    pub check: bool,
+
+    /// Order targets by name instead of keeping the order they're written in
+    #[arg(long)]
+    pub sort: bool,
+}
+
+/// Rewrites each spec in canonical form, or lists the ones that aren't with `--check`
+pub fn fmt(args: FmtArgs) -> anyhow::Result<()> {
+    let mut unformatted = 0;
+    for spec in args.specs.iter() {
+        let contents = std::fs::read_to_string(spec)
+            .with_context(|| format!("Failed to read {}", spec.display()))?;
+        let formatted = format_spec(&contents, args.sort)
+            .with_context(|| format!("Failed to format {}", spec.display()))?;
+
+        if formatted == contents {
+            continue;
+        }
+
+        if args.check {
+            println!("{} is not formatted", spec.display());
+            unformatted += 1;
+        } else {
+            std::fs::write(spec, formatted)
+                .with_context(|| format!("Failed to write {}", spec.display()))?;
+            println!("Formatted {}", spec.display());
+        }
+    }
+
+    if unformatted > 0 {
+        bail!(
+            "{unformatted} of {} specs are not formatted",
+            args.specs.len()
+        );
+    }
+
+    Ok(())
+}
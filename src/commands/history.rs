@@ -0,0 +1,137 @@
+use clap::Args;
+
+use crate::{
+    commands::estimate::format_duration,
+    limits::format_byte_size,
+    parse::date_time_at,
+    runs,
+    theme::{self, Status},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HistoryFormat {
+    /// One row per run, or per build of the target with `--target`
+    #[default]
+    Table,
+    /// The same rows as a JSON array
+    Json,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct HistoryArgs {
+    /// How many runs to show, newest first
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 10)]
+    pub limit: usize,
+
+    /// Only runs where a target failed, or only the failed builds with `--target`
+    #[arg(long)]
+    pub failed: bool,
+
+    /// Every build of one target instead of whole runs, by its name or output path
+    #[arg(long, value_name = "TARGET")]
+    pub target: Option<String>,
+
+    /// How to print the history
+    #[arg(long, value_name = "FORMAT", default_value = "table")]
+    pub format: HistoryFormat,
+}
+
+/// e.g. `2026-10-16 09:41:07` in local time
+fn format_started_at(started_at: i64) -> String {
+    let (date, time) = date_time_at(started_at);
+    format!("{date} {}", time.replace('-', ":"))
+}
+
+/// Prints past runs, or every build of a target, from the run database
+pub fn history(args: HistoryArgs) -> anyhow::Result<()> {
+    let connection = runs::open()?;
+
+    let Some(target) = args.target else {
+        let runs = runs::recent_runs(&connection, args.limit, args.failed)?;
+        if args.format == HistoryFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&runs)?);
+            return Ok(());
+        }
+        if runs.is_empty() {
+            println!("No runs recorded");
+            return Ok(());
+        }
+
+        let id_width = runs
+            .iter()
+            .map(|run| run.run_id.len())
+            .max()
+            .unwrap_or_default();
+        println!(
+            "{:<19}  {:<id_width$}  {:>8}  {:>6}  {:>9}  {:>8}  specs",
+            "started", "run", "finished", "failed", "cancelled", "elapsed"
+        );
+        for run in runs.iter() {
+            let failed = format!("{:>6}", run.failed);
+            println!(
+                "{:<19}  {:<id_width$}  {:>8}  {}  {:>9}  {:>8}  {}",
+                format_started_at(run.started_at),
+                run.run_id,
+                run.finished,
+                if run.failed > 0 {
+                    theme::paint(Status::Failure, &failed)
+                } else {
+                    failed
+                },
+                run.cancelled,
+                format_duration(run.elapsed_seconds),
+                run.specs.join(", "),
+            );
+        }
+        return Ok(());
+    };
+
+    let builds = runs::target_runs(&connection, &target, args.limit, args.failed)?;
+    if args.format == HistoryFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&builds)?);
+        return Ok(());
+    }
+    if builds.is_empty() {
+        println!("No builds of {target} recorded");
+        return Ok(());
+    }
+
+    println!(
+        "{:<19}  {:<9}  {:>8}  {:>10}  {:>11}  run",
+        "started", "status", "elapsed", "duration", "size"
+    );
+    for build in builds.iter() {
+        let status = format!("{:<9}", build.status);
+        let status = match build.status.as_str() {
+            "finished" => theme::paint(Status::Success, &status),
+            "failed" => theme::paint(Status::Failure, &status),
+            "cancelled" => theme::paint(Status::Warning, &status),
+            _ => status,
+        };
+        println!(
+            "{:<19}  {status}  {:>8}  {:>10}  {:>11}  {}",
+            format_started_at(build.started_at),
+            build
+                .elapsed_seconds
+                .map(format_duration)
+                .unwrap_or_default(),
+            build
+                .media_duration_seconds
+                .map(format_duration)
+                .unwrap_or_default(),
+            build
+                .output_size_bytes
+                .map(format_byte_size)
+                .unwrap_or_default(),
+            build.run_id,
+        );
+        if let Some(error) = &build.error {
+            match build.error_code {
+                Some(code) => println!("  [{code}] {error}"),
+                None => println!("  {error}"),
+            }
+        }
+    }
+
+    Ok(())
+}
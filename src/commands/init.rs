@@ -0,0 +1,76 @@
+use std::{io::Write, path::PathBuf};
+
+use anyhow::{Context, bail};
+use clap::Args;
+
+/// Extensions considered video files when listing sources
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mov", "mkv", "webm", "avi", "wmv", "flv", "mpg", "mpeg", "ts", "mts", "m2ts",
+];
+
+#[derive(Debug, Clone, Args)]
+pub struct InitArgs {
+    /// Path to write the new specification file to
+    #[arg(value_name = "SPEC_FILE", default_value = "stitch.stitchspec")]
+    pub spec: PathBuf,
+
+    /// Directory to list source video files from (default: current directory)
+    #[arg(short = 'i', long, value_name = "DIR")]
+    pub sources_dir: Option<PathBuf>,
+
+    /// Name of the target the sources are stitched into
+    #[arg(short, long, value_name = "TARGET", default_value = "output.mp4")]
+    pub target: String,
+
+    /// Overwrite the specification file if it already exists
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+/// Writes a spec stitching every video file in the sources directory, in name order
+pub fn init(args: InitArgs) -> anyhow::Result<()> {
+    let sources_dir = match args.sources_dir {
+        Some(sources_dir) => sources_dir,
+        None => std::env::current_dir().context("Failed to get current directory")?,
+    };
+
+    let mut sources = std::fs::read_dir(&sources_dir)
+        .with_context(|| format!("Failed to read {}", sources_dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .filter(|name| {
+            name.rsplit_once('.').is_some_and(|(_, extension)| {
+                VIDEO_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+            })
+        })
+        .filter(|name| *name != args.target)
+        .collect::<Vec<_>>();
+    sources.sort();
+
+    if sources.is_empty() {
+        bail!("No video files found in {}", sources_dir.display());
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(!args.force)
+        .open(&args.spec)
+        .with_context(|| format!("Failed to create {}", args.spec.display()))?;
+
+    writeln!(file, "{}:", args.target)?;
+    for source in sources.iter() {
+        writeln!(file, "\t{source}")?;
+    }
+
+    println!(
+        "Wrote {} with {} sources for {}",
+        args.spec.display(),
+        sources.len(),
+        args.target
+    );
+
+    Ok(())
+}
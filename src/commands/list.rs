@@ -0,0 +1,230 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use glob::Pattern;
+
+use crate::{
+    commands::{SpecArgs, TargetFilterArgs},
+    execute::shell_quote,
+    parse::{Flag, Plan},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListFormat {
+    /// Each target with its sources indented beneath it
+    #[default]
+    Tree,
+    /// The parsed plans as a JSON array
+    Json,
+    /// A Makefile with a rule per target, depending on its sources and the specs, that runs `stitch run` for it
+    Make,
+    /// A ninja build file with an edge per target, like `make`
+    Ninja,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub spec: SpecArgs,
+
+    #[command(flatten)]
+    pub targets: TargetFilterArgs,
+
+    /// How to print the plans
+    #[arg(long, value_name = "FORMAT", default_value = "tree")]
+    pub format: ListFormat,
+}
+
+/// Files a target is rebuilt after. Image sequences depend on their frames' directory, which changes as frames come and go
+fn dependencies(plan: &Plan, specs: &[PathBuf]) -> Vec<PathBuf> {
+    plan.sources
+        .iter()
+        .map(|source| match source.framerate {
+            Some(_) => source
+                .path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| source.path.clone()),
+            None => source.path.clone(),
+        })
+        .chain(specs.iter().cloned())
+        .collect()
+}
+
+/// `stitch run` arguments building only `plan`'s target
+fn run_args(plan: &Plan, spec_args: &[String]) -> String {
+    spec_args
+        .iter()
+        .map(|arg| shell_quote(arg))
+        .chain([
+            "--only".to_string(),
+            shell_quote(&Pattern::escape(&plan.target_path.leaf)),
+            "--no-progress".to_string(),
+        ])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn make_path(path: &Path) -> String {
+    path.display()
+        .to_string()
+        .replace('$', "$$")
+        .replace(' ', "\\ ")
+        .replace('#', "\\#")
+        .replace(':', "\\:")
+}
+
+fn makefile(plans: &[Plan], specs: &[PathBuf], spec_args: &[String]) -> String {
+    let mut lines = vec![
+        format!("# Generated by stitch {}", env!("CARGO_PKG_VERSION")),
+        "STITCH ?= stitch".to_string(),
+        String::new(),
+        ".PHONY: all".to_string(),
+        format!(
+            "all: {}",
+            plans
+                .iter()
+                .map(|plan| make_path(&plan.target_path.path))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    ];
+    for plan in plans {
+        lines.push(String::new());
+        lines.push(format!(
+            "{}: {}",
+            make_path(&plan.target_path.path),
+            dependencies(plan, specs)
+                .iter()
+                .map(|path| make_path(path))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ));
+        lines.push(format!(
+            "\t$(STITCH) run {}",
+            run_args(plan, spec_args).replace('$', "$$")
+        ));
+    }
+    lines.join("\n")
+}
+
+fn ninja_path(path: &Path) -> String {
+    path.display()
+        .to_string()
+        .replace('$', "$$")
+        .replace(' ', "$ ")
+        .replace(':', "$:")
+}
+
+fn ninja_file(plans: &[Plan], specs: &[PathBuf], spec_args: &[String]) -> String {
+    let mut lines = vec![
+        format!("# Generated by stitch {}", env!("CARGO_PKG_VERSION")),
+        "stitch = stitch".to_string(),
+        String::new(),
+        "rule stitch".to_string(),
+        "  command = $stitch run $args".to_string(),
+        "  description = Stitching $target".to_string(),
+    ];
+    for plan in plans {
+        let sources = plan.sources.len();
+        let dependencies = dependencies(plan, specs);
+        let (sources, specs) = dependencies.split_at(sources);
+        let join = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| ninja_path(path))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        lines.push(String::new());
+        // Sources are explicit inputs, the specs only implicit ones
+        lines.push(format!(
+            "build {}: stitch {} | {}",
+            ninja_path(&plan.target_path.path),
+            join(sources),
+            join(specs)
+        ));
+        lines.push(format!(
+            "  target = {}",
+            plan.target_path.leaf.replace('$', "$$")
+        ));
+        lines.push(format!(
+            "  args = {}",
+            run_args(plan, spec_args).replace('$', "$$")
+        ));
+    }
+    lines.push(String::new());
+    lines.push(format!(
+        "default {}",
+        plans
+            .iter()
+            .map(|plan| ninja_path(&plan.target_path.path))
+            .collect::<Vec<_>>()
+            .join(" ")
+    ));
+    lines.join("\n")
+}
+
+/// Prints every target with its sources in order and their resolved paths, or as a build file
+pub fn list(args: ListArgs, verbose: bool) -> anyhow::Result<()> {
+    let specs = args
+        .spec
+        .paths()
+        .iter()
+        .map(|spec| std::path::absolute(spec).unwrap_or_else(|_| spec.clone()))
+        .collect::<Vec<_>>();
+    let spec_args = args.spec.to_args();
+    let plans = args.targets.apply(args.spec.load(verbose)?)?;
+
+    match args.format {
+        ListFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&plans)?);
+            return Ok(());
+        }
+        ListFormat::Make => {
+            println!("{}", makefile(&plans, &specs, &spec_args));
+            return Ok(());
+        }
+        ListFormat::Ninja => {
+            println!("{}", ninja_file(&plans, &specs, &spec_args));
+            return Ok(());
+        }
+        ListFormat::Tree => {}
+    }
+
+    for plan in plans {
+        let mode = if plan.flags.contains(&Flag::ConcatFilter) {
+            "concat-filter"
+        } else if plan.flags.contains(&Flag::Mezzanine) {
+            "mezzanine"
+        } else if plan.is_concat_protocol() {
+            "concat-protocol"
+        } else {
+            "concat"
+        };
+        println!(
+            "{} ({mode}) -> {}",
+            plan.target_path.leaf,
+            plan.target_path.path.display()
+        );
+
+        let width = plan.sources.len().to_string().len();
+        for (index, source) in plan.sources.iter().enumerate() {
+            let branch = if index + 1 == plan.sources.len() {
+                "└──"
+            } else {
+                "├──"
+            };
+            println!(
+                "{branch} {:>width$}. {}  {}",
+                index + 1,
+                source.leaf,
+                source.path.display()
+            );
+        }
+        println!();
+    }
+
+    Ok(())
+}
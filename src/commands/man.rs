@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Args, CommandFactory};
+
+#[derive(Debug, Clone, Args)]
+pub struct ManArgs {
+    /// Write a page for stitch and each subcommand into DIR, instead of printing stitch's page to stdout
+    #[arg(short, long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+}
+
+/// Renders roff man pages from the CLI definition
+pub fn man(args: ManArgs) -> anyhow::Result<()> {
+    let command = crate::Args::command();
+
+    let Some(out_dir) = args.out_dir else {
+        clap_mangen::Man::new(command)
+            .render(&mut std::io::stdout())
+            .context("Failed to render man page")?;
+        return Ok(());
+    };
+
+    std::fs::create_dir_all(&out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+    clap_mangen::generate_to(command, &out_dir)
+        .with_context(|| format!("Failed to write man pages to {}", out_dir.display()))?;
+    println!("Wrote man pages to {}", out_dir.display());
+
+    Ok(())
+}
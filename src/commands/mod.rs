@@ -0,0 +1,352 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{Arg, Args, builder::BoolishValueParser};
+use glob::Pattern;
+
+use crate::{
+    env::{Binary, find_binaries},
+    gha,
+    parse::{ParseError, ParseOptions, Plan, ValidationError, parse_spec},
+};
+
+pub mod clean;
+pub mod completions;
+pub mod doctor;
+pub mod estimate;
+pub mod fmt;
+pub mod history;
+pub mod init;
+pub mod list;
+pub mod man;
+pub mod plan;
+pub mod probe;
+pub mod run;
+pub mod schema;
+pub mod serve;
+pub mod validate;
+
+/// Makes the `Option<bool>` flags named by `ids` switches that can also be given a value, so `--flag` turns one on
+/// and `--flag=false` overrides the config file. For `#[command(mut_args(..))]`
+pub fn bool_switches(ids: &'static [&'static str]) -> impl FnMut(Arg) -> Arg {
+    |arg| {
+        if !ids.contains(&arg.get_id().as_str()) {
+            return arg;
+        }
+        arg.value_name("BOOL")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("true")
+            .value_parser(BoolishValueParser::new())
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SpecArgs {
+    /// Paths to the specification files containing stitch instructions, their targets are run together
+    #[arg(value_name = "SPEC_FILE", required_unless_present = "spec")]
+    pub specs: Vec<PathBuf>,
+
+    /// Another specification file, may be repeated
+    #[arg(long = "spec", id = "spec", value_name = "SPEC_FILE")]
+    pub more_specs: Vec<PathBuf>,
+
+    /// Output directory for stitched video files (default: current directory)
+    #[arg(short = 'o', long, value_name = "DIR", help_heading = "Directories")]
+    pub target_dir: Option<PathBuf>,
+
+    /// Input directory containing source video files (default: current directory)
+    #[arg(short = 'i', long, value_name = "DIR", help_heading = "Directories")]
+    pub sources_dir: Option<PathBuf>,
+
+    /// Fail on lints in the spec, like duplicate flags or mixed indentation, instead of warning about them
+    #[arg(env = "STITCH_STRICT", long)]
+    pub strict: bool,
+
+    /// Look for sources that aren't directly in the sources directory in its subdirectories, e.g. camera imports in DCIM folders. Only a single match is used
+    #[arg(env = "STITCH_RECURSIVE_SOURCES", long, help_heading = "Directories")]
+    pub recursive_sources: bool,
+
+    /// Match sources to files whose names only differ in case, e.g. `clip.mp4` to `CLIP.MP4`, using the name on disk
+    #[arg(
+        env = "STITCH_CASE_INSENSITIVE_SOURCES",
+        long,
+        help_heading = "Directories"
+    )]
+    pub case_insensitive_sources: bool,
+}
+impl SpecArgs {
+    /// Every spec given, positional ones first
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.specs
+            .iter()
+            .chain(self.more_specs.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Arguments that load the same specs the same way from any directory, e.g. for `stitch run` in generated build files
+    pub fn to_args(&self) -> Vec<String> {
+        let absolute = |path: &PathBuf| {
+            std::path::absolute(path)
+                .unwrap_or_else(|_| path.clone())
+                .display()
+                .to_string()
+        };
+        let cwd = std::env::current_dir().unwrap_or_default();
+
+        let mut args: Vec<String> = self.paths().iter().map(absolute).collect();
+        args.push("--target-dir".to_string());
+        args.push(absolute(self.target_dir.as_ref().unwrap_or(&cwd)));
+        args.push("--sources-dir".to_string());
+        args.push(absolute(self.sources_dir.as_ref().unwrap_or(&cwd)));
+        if self.strict {
+            args.push("--strict".to_string());
+        }
+        if self.recursive_sources {
+            args.push("--recursive-sources".to_string());
+        }
+        if self.case_insensitive_sources {
+            args.push("--case-insensitive-sources".to_string());
+        }
+        args
+    }
+
+    /// Parses and validates every spec, merging their plans, printing validation errors if they won't be logged
+    pub fn load(self, verbose: bool) -> anyhow::Result<Vec<Plan>> {
+        let cwd = std::env::current_dir().expect(
+            "Failed to get current directory, please pass an directories with --target-dir and --sources-dir",
+        );
+
+        let target_dir = self.target_dir.clone().unwrap_or(cwd.clone());
+        let sources_dir = self.sources_dir.clone().unwrap_or(cwd.clone());
+
+        if !target_dir.exists() {
+            std::fs::create_dir_all(&target_dir).expect("Failed to create target directory");
+        }
+
+        let options = ParseOptions {
+            strict: self.strict,
+            recursive_sources: self.recursive_sources,
+            case_insensitive_sources: self.case_insensitive_sources,
+        };
+
+        let mut plans = Vec::new();
+        for spec in self.paths() {
+            let spec_path = spec.display().to_string();
+            let spec_plans = load_spec(
+                spec,
+                target_dir.clone(),
+                sources_dir.clone(),
+                options,
+                verbose,
+            )
+            .with_context(|| format!("Failed to load {spec_path}"))?;
+            plans.extend(spec_plans.into_iter().map(|plan| (spec_path.clone(), plan)));
+        }
+
+        // Each spec has been checked on its own, so only targets from different specs can collide
+        let mut errors = Vec::new();
+        let mut targets: HashMap<&PathBuf, &String> = HashMap::new();
+        for (spec, plan) in plans.iter() {
+            match targets.get(&plan.target_path.path) {
+                Some(first_spec) => errors.push(ValidationError::DuplicateTargetAcrossSpecs {
+                    target_name: plan.target_path.leaf.clone(),
+                    first_spec: first_spec.to_string(),
+                    second_spec: spec.clone(),
+                }),
+                None => {
+                    targets.insert(&plan.target_path.path, spec);
+                }
+            }
+        }
+        if !errors.is_empty() {
+            if gha::is_enabled() {
+                for error in errors.iter() {
+                    if let ValidationError::DuplicateTargetAcrossSpecs { second_spec, .. } = error {
+                        gha::spec_error(
+                            Path::new(second_spec),
+                            &ParseError::Validation {
+                                errors: vec![error.clone()],
+                            },
+                        );
+                    }
+                }
+            }
+            return report_spec_error(ParseError::Validation { errors }, verbose);
+        }
+
+        Ok(plans.into_iter().map(|(_, plan)| plan).collect())
+    }
+}
+
+/// Parses and validates one spec, printing validation errors if they won't be logged
+fn load_spec(
+    spec: PathBuf,
+    target_dir: PathBuf,
+    sources_dir: PathBuf,
+    options: ParseOptions,
+    verbose: bool,
+) -> anyhow::Result<Vec<Plan>> {
+    match parse_spec(spec.clone(), target_dir, sources_dir, options) {
+        Ok((plans, lints)) => {
+            if gha::is_enabled() {
+                gha::spec_lints(&spec, &lints);
+            }
+            // Lints are logged in verbose mode
+            if !verbose {
+                for lint in lints.iter() {
+                    eprintln!("Warning: {lint}");
+                }
+            }
+            Ok(plans)
+        }
+
+        Err(e) => {
+            if gha::is_enabled() {
+                gha::spec_error(&spec, &e);
+            }
+            report_spec_error(e, verbose)
+        }
+    }
+}
+
+fn report_spec_error(e: ParseError, verbose: bool) -> anyhow::Result<Vec<Plan>> {
+    match &e {
+        ParseError::Validation { errors } => {
+            if !verbose {
+                eprintln!("Validation failed:");
+                for error in errors {
+                    eprintln!("\t{error}")
+                }
+                eprintln!();
+            }
+
+            Err(e.into())
+        }
+        ParseError::Lines { errors } => {
+            if !verbose {
+                eprintln!("Parsing failed:");
+                for error in errors {
+                    eprintln!("\t{error}")
+                }
+                eprintln!();
+            }
+
+            Err(e.into())
+        }
+        ParseError::Lints { lints } => {
+            if !verbose {
+                eprintln!("Lints found with --strict:");
+                for lint in lints {
+                    eprintln!("\t{lint}")
+                }
+                eprintln!();
+            }
+
+            Err(e.into())
+        }
+        _ => Err(e.into()),
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct BinaryArgs {
+    #[arg(env = "STITCH_BIN_FFMPEG", long, help_heading = "Binaries")]
+    pub ffmpeg_path: Option<PathBuf>,
+
+    #[arg(env = "STITCH_BIN_FFPROBE", long, help_heading = "Binaries")]
+    pub ffprobe_path: Option<PathBuf>,
+
+    /// Command to run ffmpeg with instead of a path, with any leading arguments, e.g. "docker run --rm -i -v $PWD:$PWD -w $PWD jrottenberg/ffmpeg". Unix only
+    #[arg(
+        env = "STITCH_FFMPEG_CMD",
+        long,
+        value_name = "COMMAND",
+        conflicts_with = "ffmpeg_path",
+        help_heading = "Binaries"
+    )]
+    pub ffmpeg_cmd: Option<String>,
+
+    /// Command to run ffprobe with instead of a path, like --ffmpeg-cmd
+    #[arg(
+        env = "STITCH_FFPROBE_CMD",
+        long,
+        value_name = "COMMAND",
+        conflicts_with = "ffprobe_path",
+        help_heading = "Binaries"
+    )]
+    pub ffprobe_cmd: Option<String>,
+}
+impl BinaryArgs {
+    pub fn find(self) -> std::io::Result<()> {
+        let binary = |path: Option<PathBuf>, command: Option<String>| {
+            command.map(Binary::Command).or(path.map(Binary::Path))
+        };
+        find_binaries(
+            binary(self.ffmpeg_path, self.ffmpeg_cmd),
+            binary(self.ffprobe_path, self.ffprobe_cmd),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct TargetFilterArgs {
+    /// Only process targets matching this name or glob, may be repeated
+    #[arg(long, value_name = "PATTERN", help_heading = "Targets")]
+    pub only: Vec<String>,
+
+    /// Skip targets matching this name or glob, may be repeated
+    #[arg(long, value_name = "PATTERN", help_heading = "Targets")]
+    pub except: Vec<String>,
+}
+impl TargetFilterArgs {
+    /// Drops plans whose target doesn't match `--only`, or does match `--except`
+    pub fn apply(&self, plans: Vec<Plan>) -> anyhow::Result<Vec<Plan>> {
+        if self.only.is_empty() && self.except.is_empty() {
+            return Ok(plans);
+        }
+
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Pattern::new(pattern)
+                        .with_context(|| format!("Invalid target pattern \"{pattern}\""))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        let only = compile(&self.only)?;
+        let except = compile(&self.except)?;
+
+        for pattern in only.iter().chain(except.iter()) {
+            if !plans
+                .iter()
+                .any(|plan| pattern.matches(&plan.target_path.leaf))
+            {
+                tracing::warn!(
+                    pattern = pattern.as_str(),
+                    "Target pattern didn't match any targets"
+                );
+            }
+        }
+
+        let plans = plans
+            .into_iter()
+            .filter(|plan| {
+                let target_name = &plan.target_path.leaf;
+                (only.is_empty() || only.iter().any(|pattern| pattern.matches(target_name)))
+                    && !except.iter().any(|pattern| pattern.matches(target_name))
+            })
+            .collect::<Vec<_>>();
+
+        if plans.is_empty() {
+            anyhow::bail!("No targets left after applying --only/--except");
+        }
+
+        Ok(plans)
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use clap::Args;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    commands::{BinaryArgs, SpecArgs, TargetFilterArgs},
+    env,
+    execute::{
+        AudioPlaceholder, DEFAULT_CHANNEL_LAYOUT, DEFAULT_SAMPLE_RATE, FrameRateConversion,
+        HwaccelDecode, Mp4Layout, VideoEncoding, catfile_content, configure_ffmpeg,
+        configure_intermediate, find_audio_normalization, find_color_conversions,
+        find_placeholders, intermediate_format, render_args, render_command,
+    },
+    parse::Flag,
+    probe,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct PlanArgs {
+    #[command(flatten)]
+    pub spec: SpecArgs,
+
+    #[command(flatten)]
+    pub targets: TargetFilterArgs,
+
+    #[command(flatten)]
+    pub binaries: BinaryArgs,
+
+    /// Stand in video for sources that have audio but no video, `black` or the path to a still image. Without one, those sources fail
+    #[arg(env = "STITCH_AUDIO_PLACEHOLDER", long, value_name = "IMAGE")]
+    pub audio_placeholder: Option<AudioPlaceholder>,
+
+    /// Decode the concat filter's inputs on the GPU, often the bottleneck with many 4K sources even when encoding on the CPU
+    #[arg(env = "STITCH_HWACCEL_DECODE", long, value_name = "API")]
+    pub hwaccel_decode: Option<HwaccelDecode>,
+
+    /// How sources are converted to 30fps when re-encoding, blend or interpolate smooth out 60 to 30 (default: drop)
+    #[arg(env = "STITCH_FRAME_RATE_CONVERSION", long, value_name = "METHOD")]
+    pub frame_rate_conversion: Option<FrameRateConversion>,
+
+    /// How MP4 style targets (.mp4, .m4v, .m4a, .mov) are laid out, faststart moves the index to the front so they play before they've downloaded (default: faststart)
+    #[arg(env = "STITCH_MP4_LAYOUT", long, value_name = "LAYOUT")]
+    pub mp4_layout: Option<Mp4Layout>,
+
+    /// ffmpeg encoder re-encoded targets and intermediates are written with, e.g. libx265 or h264_nvenc (default: libx264)
+    #[arg(env = "STITCH_CODEC", long, value_name = "ENCODER")]
+    pub codec: Option<String>,
+
+    /// Encoder preset, trading encoding speed for size, e.g. fast or slow (default: medium)
+    #[arg(env = "STITCH_PRESET", long, value_name = "PRESET")]
+    pub preset: Option<String>,
+
+    /// Constant rate factor re-encoded video is encoded at, lower is higher quality and larger (default: 23)
+    #[arg(env = "STITCH_CRF", long, value_name = "CRF")]
+    pub crf: Option<u8>,
+
+    /// Channel layout concat filter targets mix their sources to when the sources' layouts differ, e.g. mono or 5.1 (default: stereo)
+    #[arg(env = "STITCH_CHANNEL_LAYOUT", long, value_name = "LAYOUT")]
+    pub channel_layout: Option<String>,
+
+    /// Sample rate concat filter targets resample their sources to when the sources' rates differ, in Hz (default: 48000)
+    #[arg(env = "STITCH_SAMPLE_RATE", long, value_name = "HZ")]
+    pub sample_rate: Option<u32>,
+
+    /// Write the commands to FILE as a shell script, catfiles included, to run the encodes where stitch isn't installed
+    #[arg(long, value_name = "FILE")]
+    pub export_script: Option<PathBuf>,
+}
+
+/// Quotes `text` for a POSIX shell
+fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// A portable script running each target's `commands` in a temp directory, which catfiles and intermediates are written to
+fn script(commands: &[String]) -> String {
+    let mut script = vec![
+        "#!/bin/sh".to_string(),
+        format!(
+            "# Generated by stitch {}, runs every target's ffmpeg commands without stitch",
+            env!("CARGO_PKG_VERSION")
+        ),
+        "# Set FFMPEG to use an ffmpeg that isn't on PATH".to_string(),
+        "set -eu".to_string(),
+        String::new(),
+        "FFMPEG=\"${FFMPEG:-ffmpeg}\"".to_string(),
+        "work_dir=\"$(mktemp -d)\"".to_string(),
+        "trap 'rm -rf \"$work_dir\"' EXIT".to_string(),
+        "cd \"$work_dir\"".to_string(),
+        String::new(),
+    ];
+    script.extend(commands.iter().cloned());
+    script.join("\n")
+}
+
+fn write_script(path: &Path, content: &str) -> anyhow::Result<()> {
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write script to {}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Prints the ffmpeg invocation each target would run without running it, or writes them as a script
+pub async fn plan(
+    args: PlanArgs,
+    verbose: bool,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    args.binaries.find()?;
+    let plans = args.targets.apply(args.spec.load(verbose)?)?;
+    let ffmpeg = env::get_ffmpeg().expect("Binaries were just found");
+    // The script runs from a temp directory, so a relative image would be looked for there
+    let audio_placeholder = match args.audio_placeholder {
+        Some(AudioPlaceholder::Image(image)) if args.export_script.is_some() => Some(
+            AudioPlaceholder::Image(std::path::absolute(&image).unwrap_or(image)),
+        ),
+        placeholder => placeholder,
+    };
+
+    // Printed, or with `--export-script` written to the script
+    let video_encoding = VideoEncoding::new(args.codec, args.preset, args.crf);
+    let mut lines = vec![];
+    for mut plan in plans {
+        lines.push(format!("# {}", plan.target_path.leaf));
+        if args.export_script.is_some()
+            && let Some(target_dir) = plan.target_path.path.parent()
+        {
+            lines.push(format!(
+                "mkdir -p {}",
+                sh_quote(&target_dir.display().to_string())
+            ));
+        }
+
+        let mut source_has_audio = HashMap::new();
+        let mut total_seconds = 0.0;
+        for source in plan.sources.iter() {
+            let has_audio =
+                probe::has_audio(source.path.clone(), cancellation_token.child_token()).await?;
+            source_has_audio.insert(source.leaf.clone(), has_audio);
+            // Only fades need to know where the end is
+            if plan.fade.is_some() {
+                total_seconds += probe::duration(source, cancellation_token.child_token())
+                    .await?
+                    .as_secs_f64();
+            }
+        }
+
+        // Only copied Matroska targets keep their sources' attachments
+        let mut attachment_sources = vec![];
+        if !plan.is_encode() && plan.target_path.is_matroska() {
+            for source in plan.sources.iter().filter(|source| source.is_matroska()) {
+                let streams =
+                    probe::streams(source.path.clone(), cancellation_token.child_token()).await?;
+                if streams
+                    .iter()
+                    .any(|(codec_type, _)| codec_type == "attachment")
+                {
+                    attachment_sources.push(source.clone());
+                }
+            }
+        }
+
+        if plan.flags.contains(&Flag::Mezzanine) {
+            let format = intermediate_format(
+                &plan,
+                &source_has_audio,
+                args.frame_rate_conversion.unwrap_or_default(),
+                video_encoding.clone(),
+                cancellation_token.child_token(),
+            )
+            .await?;
+
+            let color_conversions =
+                find_color_conversions(&plan, cancellation_token.child_token()).await?;
+
+            lines.push("# Each source is transcoded to an intermediate first".to_string());
+            for (index, source) in plan.sources.iter_mut().enumerate() {
+                let output = PathBuf::from(format!(
+                    "{}_{index}.mkv",
+                    plan.target_path.leaf.replace(".", "_")
+                ));
+                let mut cmd = tokio::process::Command::new(ffmpeg);
+                configure_intermediate(
+                    &mut cmd,
+                    source,
+                    source_has_audio.get(&source.leaf).copied().unwrap_or(false),
+                    &format,
+                    color_conversions.get(&source.leaf).copied(),
+                    &output,
+                );
+                lines.push(match args.export_script {
+                    Some(_) => format!("\"$FFMPEG\" {}", render_args(cmd.as_std())),
+                    None => render_command(cmd.as_std()),
+                });
+
+                source.path = output;
+                source.framerate = None;
+                source.video_stream = None;
+                source_has_audio.insert(source.leaf.clone(), format.with_audio);
+            }
+            plan.flags.retain(|flag| *flag != Flag::Mezzanine);
+        }
+
+        let placeholders = find_placeholders(
+            &plan,
+            audio_placeholder.as_ref(),
+            cancellation_token.child_token(),
+        )
+        .await?;
+
+        let color_conversions = if plan.flags.contains(&Flag::ConcatFilter) {
+            find_color_conversions(&plan, cancellation_token.child_token()).await?
+        } else {
+            HashMap::new()
+        };
+
+        if plan.is_trimmed() {
+            lines.push(
+                "# Leading and trailing black video and silence are trimmed from each source once they're found, which only stitch does"
+                    .to_string(),
+            );
+        }
+
+        let audio_normalization = find_audio_normalization(
+            &plan,
+            args.channel_layout
+                .as_deref()
+                .unwrap_or(DEFAULT_CHANNEL_LAYOUT),
+            args.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE),
+            cancellation_token.child_token(),
+        )
+        .await?;
+
+        let catfile_path = PathBuf::from(format!(
+            "{}.catfile",
+            plan.target_path.leaf.replace(".", "_")
+        ));
+
+        let mut cmd = tokio::process::Command::new(ffmpeg);
+        configure_ffmpeg(
+            &mut cmd,
+            &plan,
+            &catfile_path,
+            "pipe:1",
+            &source_has_audio,
+            placeholders.as_ref(),
+            &HashMap::new(),
+            &attachment_sources,
+            &color_conversions,
+            &audio_normalization,
+            args.hwaccel_decode,
+            args.frame_rate_conversion.unwrap_or_default(),
+            args.mp4_layout.unwrap_or_default(),
+            &video_encoding,
+            total_seconds,
+        );
+
+        if !plan.flags.contains(&Flag::ConcatFilter)
+            && !plan.is_remux()
+            && !plan.is_concat_protocol()
+        {
+            if args.export_script.is_some() {
+                lines.push(format!(
+                    "cat > {} <<'STITCH_CATFILE'",
+                    sh_quote(&catfile_path.display().to_string())
+                ));
+                lines.push(catfile_content(&plan, &HashMap::new()));
+                lines.push("STITCH_CATFILE".to_string());
+            } else {
+                lines.push(format!("# {}:", catfile_path.display()));
+                for line in catfile_content(&plan, &HashMap::new()).lines() {
+                    lines.push(format!("#   {line}"));
+                }
+            }
+        }
+        lines.push(match args.export_script {
+            // Progress is written for stitch to read, there's nothing reading it here
+            Some(_) => format!("\"$FFMPEG\" {} >/dev/null", render_args(cmd.as_std())),
+            None => render_command(cmd.as_std()),
+        });
+        lines.push(String::new());
+    }
+
+    match &args.export_script {
+        Some(path) => {
+            write_script(path, &script(&lines))?;
+            println!("Wrote {}", path.display());
+        }
+        None => {
+            for line in lines {
+                println!("{line}");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use clap::Args;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    commands::{BinaryArgs, SpecArgs, TargetFilterArgs},
+    parse::Flag,
+    probe,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct ProbeArgs {
+    #[command(flatten)]
+    pub spec: SpecArgs,
+
+    #[command(flatten)]
+    pub targets: TargetFilterArgs,
+
+    #[command(flatten)]
+    pub binaries: BinaryArgs,
+
+    /// Also read every video packet to report each source's average and peak bitrate, e.g. to choose the quality to encode at
+    #[arg(long)]
+    pub bitrate: bool,
+}
+
+/// Prints the duration, resolution and audio presence of every source in the spec, and with `--bitrate` their bitrates
+pub async fn probe(
+    args: ProbeArgs,
+    verbose: bool,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    args.binaries.find()?;
+    let plans = args.targets.apply(args.spec.load(verbose)?)?;
+
+    for plan in plans {
+        let mode = if plan.flags.contains(&Flag::ConcatFilter) {
+            "concat-filter"
+        } else if plan.flags.contains(&Flag::Mezzanine) {
+            "mezzanine"
+        } else if plan.is_concat_protocol() {
+            "concat-protocol"
+        } else {
+            "concat"
+        };
+        println!("{} ({mode})", plan.target_path.leaf);
+
+        let mut total_seconds = 0.0f64;
+        let mut peak = None::<f64>;
+        for source in plan.sources.iter() {
+            let duration = probe::duration(source, cancellation_token.child_token()).await?;
+            let resolution =
+                probe::resolution(source.path.clone(), cancellation_token.child_token()).await?;
+            let has_audio =
+                probe::has_audio(source.path.clone(), cancellation_token.child_token()).await?;
+
+            total_seconds += duration.as_secs_f64();
+
+            // Image sequences are frames on disk, there are no packets to measure
+            let bitrate = if args.bitrate && source.framerate.is_none() {
+                probe::bitrate(source.path.clone(), cancellation_token.child_token()).await?
+            } else {
+                None
+            };
+            if let Some(bitrate) = bitrate {
+                peak = Some(peak.unwrap_or_default().max(bitrate.peak));
+            }
+
+            println!(
+                "  {}  {:.1}s  {}  {}{}",
+                source.leaf,
+                duration.as_secs_f64(),
+                resolution
+                    .map(|(width, height)| format!("{width}x{height}"))
+                    .unwrap_or_else(|| "no video".to_string()),
+                if has_audio { "audio" } else { "no audio" },
+                bitrate
+                    .map(|bitrate| format!(
+                        "  {} average, {} peak",
+                        probe::format_bitrate(bitrate.average),
+                        probe::format_bitrate(bitrate.peak)
+                    ))
+                    .unwrap_or_default(),
+            );
+        }
+
+        match peak {
+            Some(peak) => println!(
+                "  total: {total_seconds:.1}s, {} peak",
+                probe::format_bitrate(peak)
+            ),
+            None => println!("  total: {total_seconds:.1}s"),
+        }
+        println!();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,487 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::IsTerminal,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use clap::Args;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    commands::{BinaryArgs, SpecArgs, TargetFilterArgs, bool_switches},
+    execute::{
+        AudioPlaceholder, ExecuteOptions, ExecuteProgress, ExecuteProgressPayload,
+        FrameRateConversion, HwaccelDecode, Mp4Layout, PlanExecutions, ProgressSource,
+        VideoEncoding, execute_plans,
+    },
+    exit::TargetsFailed,
+    gha, ipc, limits, logging, metrics,
+    monitor::{
+        ProgressFormat, json_monitor, line_monitor, monitor, plain_monitor, tap_with, update_title,
+        write_progress_file,
+    },
+    path, pause, probe,
+    report::{self, RunReport},
+    runs,
+    throughput::{self, ThroughputHistory},
+    webhook::{self, Webhook},
+};
+
+#[derive(Debug, Clone, Args)]
+#[command(mut_args(bool_switches(&[
+    "low_priority",
+    "fail_fast",
+    "terminal_title",
+    "detect_gaps",
+])))]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub spec: SpecArgs,
+
+    #[command(flatten)]
+    pub targets: TargetFilterArgs,
+
+    #[command(flatten)]
+    pub binaries: BinaryArgs,
+
+    /// Maximum number of plans to run at once (default: number of CPUs, at most 4 if every plan is a stream copy)
+    #[arg(
+        env = "STITCH_JOBS",
+        short = 'j',
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+    )]
+    pub jobs: Option<usize>,
+
+    /// Don't start new plans while the load average is at least LOAD
+    #[arg(env = "STITCH_MAX_LOAD", short = 'l', long, value_name = "LOAD")]
+    pub max_load: Option<f64>,
+
+    /// Estimated memory budget shared by concat-filter plans, e.g. 8G. Heavy plans wait for room rather than running at once
+    #[arg(env = "STITCH_MAX_MEMORY", long, value_name = "SIZE", value_parser = limits::parse_byte_size)]
+    pub max_memory: Option<u64>,
+
+    /// Run ffmpeg and ffprobe at reduced CPU and IO priority, `--low-priority=false` overrides the config file
+    #[arg(env = "STITCH_LOW_PRIORITY", long)]
+    pub low_priority: Option<bool>,
+
+    /// Cancel every remaining plan as soon as one fails, instead of running the rest to completion
+    #[arg(env = "STITCH_FAIL_FAST", long)]
+    pub fail_fast: Option<bool>,
+
+    /// How to report progress, `json` writes one event per line to stdout instead of the live display. `tui` falls back to `plain` when stdout isn't a terminal (default: tui)
+    #[arg(long, value_name = "FORMAT")]
+    pub progress_format: Option<ProgressFormat>,
+
+    /// Disable the live display and terminal colours, only printing a line as each target starts, finishes or fails
+    #[arg(long, visible_alias = "ci", conflicts_with = "progress_format")]
+    pub no_progress: bool,
+
+    /// Write a JSON report of every target's outcome to FILE when the run exits
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<PathBuf>,
+
+    /// Stream progress events as newline delimited JSON to every client of a unix socket at PATH, so other programs can follow the run
+    #[arg(long, value_name = "PATH")]
+    pub ipc_socket: Option<PathBuf>,
+
+    /// POST a JSON notification to URL as each target starts, finishes or fails, may be repeated. Slack, Discord and
+    /// templated payloads are set up in the config file
+    #[arg(long = "webhook", value_name = "URL", value_parser = Webhook::from_url)]
+    pub webhooks: Vec<Webhook>,
+
+    /// Serve Prometheus metrics on http://ADDR/metrics for as long as the run lasts
+    #[arg(env = "STITCH_METRICS_LISTEN", long, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Show the run's progress in the terminal's title, e.g. "stitch: 7/20 done, 54%"
+    #[arg(env = "STITCH_TERMINAL_TITLE", long)]
+    pub terminal_title: Option<bool>,
+
+    /// Append every progress event to FILE as newline delimited JSON while the run goes, whatever the progress format
+    #[arg(long, value_name = "FILE")]
+    pub progress_file: Option<PathBuf>,
+
+    /// Where ffmpeg reports encoding progress (default: pipe)
+    #[arg(long, value_name = "SOURCE")]
+    pub progress_source: Option<ProgressSource>,
+
+    /// Stand in video for sources that have audio but no video, `black` or the path to a still image. Without one, those sources fail
+    #[arg(env = "STITCH_AUDIO_PLACEHOLDER", long, value_name = "IMAGE")]
+    pub audio_placeholder: Option<AudioPlaceholder>,
+
+    /// Decode the concat filter's inputs on the GPU, often the bottleneck with many 4K sources even when encoding on the CPU
+    #[arg(env = "STITCH_HWACCEL_DECODE", long, value_name = "API")]
+    pub hwaccel_decode: Option<HwaccelDecode>,
+
+    /// How sources are converted to 30fps when re-encoding, blend or interpolate smooth out 60 to 30 (default: drop)
+    #[arg(env = "STITCH_FRAME_RATE_CONVERSION", long, value_name = "METHOD")]
+    pub frame_rate_conversion: Option<FrameRateConversion>,
+
+    /// How MP4 style targets (.mp4, .m4v, .m4a, .mov) are laid out, faststart moves the index to the front so they play before they've downloaded (default: faststart)
+    #[arg(env = "STITCH_MP4_LAYOUT", long, value_name = "LAYOUT")]
+    pub mp4_layout: Option<Mp4Layout>,
+
+    /// ffmpeg encoder re-encoded targets and intermediates are written with, e.g. libx265 or h264_nvenc (default: libx264)
+    #[arg(env = "STITCH_CODEC", long, value_name = "ENCODER")]
+    pub codec: Option<String>,
+
+    /// Encoder preset, trading encoding speed for size, e.g. fast or slow (default: medium)
+    #[arg(env = "STITCH_PRESET", long, value_name = "PRESET")]
+    pub preset: Option<String>,
+
+    /// Constant rate factor re-encoded video is encoded at, lower is higher quality and larger (default: 23)
+    #[arg(env = "STITCH_CRF", long, value_name = "CRF")]
+    pub crf: Option<u8>,
+
+    /// Channel layout concat filter targets mix their sources to when the sources' layouts differ, e.g. mono or 5.1 (default: stereo)
+    #[arg(env = "STITCH_CHANNEL_LAYOUT", long, value_name = "LAYOUT")]
+    pub channel_layout: Option<String>,
+
+    /// Sample rate concat filter targets resample their sources to when the sources' rates differ, in Hz (default: 48000)
+    #[arg(env = "STITCH_SAMPLE_RATE", long, value_name = "HZ")]
+    pub sample_rate: Option<u32>,
+
+    /// Check each finished target for black video and silence where its sources meet, e.g. padded tails on camera files. Decodes every target again
+    #[arg(env = "STITCH_DETECT_GAPS", long)]
+    pub detect_gaps: Option<bool>,
+}
+
+/// How each target settled, read off the progress stream
+struct Outcomes {
+    tokens: HashMap<String, CancellationToken>,
+    cancellation_token: CancellationToken,
+    fail_fast: bool,
+    names: HashMap<Uuid, String>,
+    settled: HashSet<String>,
+    failures: Vec<(String, String)>,
+    /// Stopped along with the whole run, by --fail-fast, `q` or Ctrl+C
+    cancelled: Vec<String>,
+    /// Cancelled on their own from the monitor, the rest of the run carried on
+    cancelled_alone: Vec<String>,
+}
+
+impl Outcomes {
+    fn new(
+        tokens: HashMap<String, CancellationToken>,
+        cancellation_token: CancellationToken,
+        fail_fast: bool,
+    ) -> Self {
+        Self {
+            tokens,
+            cancellation_token,
+            fail_fast,
+            names: HashMap::new(),
+            settled: HashSet::new(),
+            failures: Vec::new(),
+            cancelled: Vec::new(),
+            cancelled_alone: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, delivery: &ExecuteProgress) {
+        match &delivery.payload {
+            ExecuteProgressPayload::Start { target_name } => {
+                self.names.insert(delivery.id, target_name.clone());
+            }
+            ExecuteProgressPayload::Finished(_) => {
+                if let Some(target_name) = self.names.get(&delivery.id) {
+                    self.settled.insert(target_name.clone());
+                }
+            }
+            ExecuteProgressPayload::Failed(error) => {
+                let Some(target_name) = self.names.get(&delivery.id).cloned() else {
+                    return;
+                };
+                self.settled.insert(target_name.clone());
+
+                // Whatever a plan fails with once it's been asked to stop, it was cancelled
+                if self
+                    .tokens
+                    .get(&target_name)
+                    .is_some_and(CancellationToken::is_cancelled)
+                {
+                    if self.cancellation_token.is_cancelled() {
+                        self.cancelled.push(target_name);
+                    } else {
+                        tracing::info!(target_name = target_name, "Plan was cancelled");
+                        self.cancelled_alone.push(target_name);
+                    }
+                    return;
+                }
+
+                self.failures.push((target_name, error.to_string()));
+
+                if self.fail_fast && !self.cancellation_token.is_cancelled() {
+                    tracing::warn!(
+                        target_name = self.failures[0].0,
+                        "A plan failed, cancelling remaining plans (--fail-fast)"
+                    );
+                    self.cancellation_token.cancel();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Counts targets whose plan stopped without settling, e.g. by panicking, as failures
+    fn finish(mut self) -> Self {
+        let mut unsettled: Vec<_> = self
+            .tokens
+            .keys()
+            .filter(|target_name| !self.settled.contains(*target_name))
+            .cloned()
+            .collect();
+        unsettled.sort();
+        for target_name in unsettled {
+            self.failures
+                .push((target_name, "Stopped without finishing".to_string()));
+        }
+        self
+    }
+}
+
+pub async fn run(
+    args: RunArgs,
+    verbose: bool,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    pause::toggle_on_signal();
+
+    args.binaries.find()?;
+    probe::record_ffmpeg_build(cancellation_token.child_token()).await;
+    limits::set_low_priority(args.low_priority.unwrap_or_default());
+
+    let spec_paths = args.spec.paths();
+    let spec = args.targets.apply(args.spec.load(verbose)?)?;
+
+    let jobs = args.jobs.unwrap_or_else(|| {
+        let all_copy = spec.iter().all(|plan| !plan.is_encode());
+        limits::default_process_limit(all_copy)
+    });
+    limits::set_process_limit(jobs);
+    if let Some(max_load) = args.max_load {
+        limits::set_max_load(max_load);
+    }
+    if let Some(max_memory) = args.max_memory {
+        limits::set_max_memory(max_memory);
+    }
+
+    let options = ExecuteOptions {
+        progress_source: args.progress_source.unwrap_or_default(),
+        audio_placeholder: args.audio_placeholder,
+        hwaccel_decode: args.hwaccel_decode,
+        frame_rate_conversion: args.frame_rate_conversion.unwrap_or_default(),
+        mp4_layout: args.mp4_layout.unwrap_or_default(),
+        video_encoding: VideoEncoding::new(args.codec, args.preset, args.crf),
+        channel_layout: args.channel_layout,
+        sample_rate: args.sample_rate,
+        detect_gaps: args.detect_gaps.unwrap_or_default(),
+    };
+
+    let target_count = spec.len();
+    // Recorded whether or not it's written, for the run database
+    let report = RunReport::new(spec_paths, &spec);
+
+    // Bound before any plan starts, so a bad address doesn't leave plans running
+    let metrics_listener = match args.metrics_listen {
+        Some(listen) => Some(metrics::bind(listen).await?),
+        None => None,
+    };
+    let ipc_socket = args.ipc_socket.map(ipc::bind).transpose()?;
+
+    let fail_fast = args.fail_fast.unwrap_or_default();
+    let PlanExecutions { tokens, progress } = execute_plans(
+        spec,
+        path::run_tmp_root(),
+        options,
+        cancellation_token.clone(),
+    );
+
+    let (rx, outcomes) = tap_with(
+        progress.into_inner(),
+        Outcomes::new(tokens.clone(), cancellation_token.clone(), fail_fast),
+        Outcomes::record,
+    );
+
+    let (rx, report) = report::record(report, rx);
+
+    let rx = throughput::record_all(rx);
+
+    // Read once, the monitors estimate every plan from it
+    let history = Arc::new(ThroughputHistory::load());
+
+    let rx = if gha::is_enabled() {
+        gha::annotate_failures(rx)
+    } else {
+        rx
+    };
+
+    let rx = if args.terminal_title.unwrap_or_default() && std::io::stderr().is_terminal() {
+        update_title(target_count, history.clone(), rx)
+    } else {
+        rx
+    };
+
+    let rx = match &args.progress_file {
+        Some(progress_file) => write_progress_file(progress_file, rx)?,
+        None => rx,
+    };
+
+    let rx = if logging::file_log_enabled() {
+        logging::write_target_logs(rx)
+    } else {
+        rx
+    };
+
+    let (rx, webhooks) = if args.webhooks.is_empty() {
+        (rx, None)
+    } else {
+        let (rx, webhooks) = webhook::notify(args.webhooks.clone(), rx);
+        (rx, Some(webhooks))
+    };
+
+    let metrics_token = cancellation_token.child_token();
+    let (rx, metrics_server) = match metrics_listener {
+        Some(listener) => {
+            let server = tokio::spawn(metrics::serve(listener, metrics_token.clone()));
+            (metrics::record_all(rx), Some(server))
+        }
+        None => (rx, None),
+    };
+
+    let (rx, ipc) = match ipc_socket {
+        Some(socket) => {
+            let (rx, ipc) = ipc::broadcast(socket, rx);
+            (rx, Some(ipc))
+        }
+        None => (rx, None),
+    };
+
+    let progress_format = match args.progress_format.unwrap_or_default() {
+        _ if args.no_progress => ProgressFormat::Lines,
+        ProgressFormat::Tui if !std::io::stdout().is_terminal() => {
+            tracing::info!("stdout isn't a terminal, writing progress as plain lines");
+            ProgressFormat::Plain
+        }
+        progress_format => progress_format,
+    };
+
+    let handle = match progress_format {
+        ProgressFormat::Tui => tokio::spawn(monitor(
+            rx,
+            verbose,
+            cancellation_token.clone(),
+            tokens,
+            history,
+        )),
+        ProgressFormat::Plain => tokio::spawn(plain_monitor(rx, history)),
+        ProgressFormat::Lines => tokio::spawn(line_monitor(rx)),
+        ProgressFormat::Json => tokio::spawn(json_monitor(rx)),
+    };
+
+    // Monitor will exit naturally once every plan has settled and the channel closes, just wait for it
+    match handle.await {
+        Ok(_) => { /* monitor closed normally */ }
+        Err(join_error) => {
+            tracing::error!(error =% join_error, error_context =? join_error,"Failed to join monitor thread")
+        }
+    }
+
+    metrics_token.cancel();
+    if let Some(server) = metrics_server {
+        match server.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                tracing::error!(error =% e, error_context =? e, "Metrics server failed");
+            }
+            Err(join_error) => {
+                tracing::error!(error =% join_error, error_context =? join_error, "Failed to join metrics server");
+            }
+        }
+    }
+
+    if let Some(ipc) = ipc
+        && let Err(join_error) = ipc.await
+    {
+        tracing::error!(error =% join_error, error_context =? join_error, "Failed to join socket stream");
+    }
+
+    let Outcomes {
+        failures,
+        cancelled,
+        cancelled_alone,
+        ..
+    } = match outcomes.await {
+        Ok(outcomes) => outcomes.finish(),
+        Err(join_error) => {
+            tracing::error!(error =% join_error, error_context =? join_error, "Failed to join outcome recorder");
+            return Err(join_error.into());
+        }
+    };
+
+    if let Some(webhooks) = webhooks
+        && let Err(join_error) = webhooks.await
+    {
+        tracing::error!(error =% join_error, error_context =? join_error, "Failed to join webhook notifications");
+    }
+
+    match report.await {
+        Ok(mut report) => {
+            report.finish(&[cancelled.as_slice(), cancelled_alone.as_slice()].concat());
+            if let Err(e) = runs::record(report.run_id, &report) {
+                tracing::warn!(error =% e, error_context =? e, "Failed to record run in the run database");
+            }
+            if let Some(report_path) = &args.report {
+                report.write(report_path)?;
+            }
+        }
+        Err(join_error) => {
+            tracing::error!(error =% join_error, error_context =? join_error, "Failed to join report recorder");
+        }
+    }
+
+    if !failures.is_empty() {
+        eprintln!("Failed targets:");
+        for (target_name, error) in failures.iter() {
+            eprintln!("\t{target_name}: {error}");
+        }
+        eprintln!();
+    }
+
+    if !cancelled.is_empty() {
+        if fail_fast && !failures.is_empty() {
+            eprintln!("Cancelled targets (--fail-fast):");
+        } else {
+            eprintln!("Cancelled targets:");
+        }
+        for target_name in cancelled.iter() {
+            eprintln!("\t{target_name}");
+        }
+        eprintln!();
+    }
+
+    if !cancelled_alone.is_empty() {
+        eprintln!("Targets cancelled from the monitor:");
+        for target_name in cancelled_alone.iter() {
+            eprintln!("\t{target_name}");
+        }
+        eprintln!();
+    }
+
+    // Targets cancelled on their own were meant to stop, so they don't fail the run
+    if !failures.is_empty() || !cancelled.is_empty() {
+        return Err(TargetsFailed {
+            failed: failures.len(),
+            cancelled: cancelled.len(),
+            target_count,
+        }
+        .into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use clap::Args;
+
+use crate::{execute::ExecuteProgress, report::RunReport};
+
+/// Bumped when a change to the output would break a consumer of the previous schema, e.g. a field removed or renamed
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SchemaKind {
+    /// A line of `--progress-format json` or `--progress-file`
+    ProgressEvent,
+    /// The file written with `--report`
+    RunReport,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SchemaArgs {
+    /// Output to print the JSON Schema of
+    #[arg(value_name = "OUTPUT")]
+    pub kind: SchemaKind,
+}
+
+/// Writes the JSON Schema of one of stitch's machine readable outputs to stdout
+pub fn schema(args: SchemaArgs) -> anyhow::Result<()> {
+    let (name, mut schema) = match args.kind {
+        SchemaKind::ProgressEvent => ("progress-event", schemars::schema_for!(ExecuteProgress)),
+        SchemaKind::RunReport => ("run-report", schemars::schema_for!(RunReport)),
+    };
+    schema.insert(
+        "$id".to_string(),
+        format!("urn:stitch:{name}:v{SCHEMA_VERSION}").into(),
+    );
+
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use std::net::SocketAddr;
+
+use clap::Args;
+use tokio_util::sync::CancellationToken;
+
+use crate::{commands::BinaryArgs, limits, probe, server};
+
+#[derive(Debug, Clone, Args)]
+pub struct ServeArgs {
+    /// Address to accept API requests on
+    #[arg(
+        env = "STITCH_LISTEN",
+        long,
+        value_name = "ADDR",
+        default_value = "127.0.0.1:7878"
+    )]
+    pub listen: SocketAddr,
+
+    #[command(flatten)]
+    pub binaries: BinaryArgs,
+
+    /// Maximum number of plans to run at once, shared by every job (default: number of CPUs)
+    #[arg(
+        env = "STITCH_JOBS",
+        short = 'j',
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+    )]
+    pub jobs: Option<usize>,
+}
+
+/// Runs stitch as a daemon accepting jobs over HTTP
+pub async fn serve(args: ServeArgs, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+    args.binaries.find()?;
+    probe::record_ffmpeg_build(cancellation_token.child_token()).await;
+    limits::set_process_limit(
+        args.jobs
+            .unwrap_or_else(|| limits::default_process_limit(false)),
+    );
+
+    server::serve(args.listen, cancellation_token).await
+}
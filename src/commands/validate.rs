@@ -0,0 +1,28 @@
+use clap::Args;
+
+use crate::commands::SpecArgs;
+
+#[derive(Debug, Clone, Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub spec: SpecArgs,
+}
+
+pub fn validate(args: ValidateArgs, verbose: bool) -> anyhow::Result<()> {
+    let spec_path = args
+        .spec
+        .paths()
+        .iter()
+        .map(|spec| spec.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let plans = args.spec.load(verbose)?;
+
+    let source_count = plans.iter().map(|plan| plan.sources.len()).sum::<usize>();
+    println!(
+        "{spec_path} is valid: {} targets, {source_count} sources",
+        plans.len()
+    );
+
+    Ok(())
+}
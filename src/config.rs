@@ -0,0 +1,323 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use liberror::AnyError;
+use serde::{Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+use valuable::Valuable;
+
+use crate::{
+    Command,
+    commands::{BinaryArgs, clean::parse_age, run::RunArgs},
+    execute::{AudioPlaceholder, FrameRateConversion, HwaccelDecode, Mp4Layout, ProgressSource},
+    limits,
+    logging::LogRetention,
+    monitor::ProgressFormat,
+    theme::{ColorChoice, Theme},
+    webhook::Webhook,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Valuable, Error)]
+pub enum ConfigError {
+    #[error("Failed to read config at \"{path}\": {inner_error}")]
+    Read { path: String, inner_error: AnyError },
+    #[error("Invalid config at \"{path}\": {inner_error}")]
+    Parse { path: String, inner_error: AnyError },
+}
+
+/// Defaults loaded from the config file, command line flags and environment variables take precedence
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub jobs: Option<usize>,
+    pub max_load: Option<f64>,
+    #[serde(deserialize_with = "deserialize_byte_size")]
+    pub max_memory: Option<u64>,
+    pub low_priority: Option<bool>,
+    pub fail_fast: Option<bool>,
+    pub progress_format: Option<ProgressFormat>,
+    pub progress_source: Option<ProgressSource>,
+    /// `black` or the path to a still image
+    pub audio_placeholder: Option<AudioPlaceholder>,
+    pub hwaccel_decode: Option<HwaccelDecode>,
+    pub frame_rate_conversion: Option<FrameRateConversion>,
+    pub mp4_layout: Option<Mp4Layout>,
+    /// ffmpeg encoder re-encoded video is written with, e.g. `libx265`
+    pub codec: Option<String>,
+    pub preset: Option<String>,
+    pub crf: Option<u8>,
+    /// Layout sources with mixed channel layouts are mixed to, e.g. `mono` or `5.1`
+    pub channel_layout: Option<String>,
+    /// Rate in Hz sources with mixed sample rates are resampled to
+    pub sample_rate: Option<u32>,
+    pub detect_gaps: Option<bool>,
+    pub terminal_title: Option<bool>,
+    pub ffmpeg_path: Option<PathBuf>,
+    pub ffprobe_path: Option<PathBuf>,
+    /// Command ffmpeg is run with instead of a path, e.g. `docker run --rm -i jrottenberg/ffmpeg`
+    pub ffmpeg_cmd: Option<String>,
+    pub ffprobe_cmd: Option<String>,
+    /// Directory the per-run temp directories are created in, instead of the system temp dir
+    pub tmp_dir: Option<PathBuf>,
+    /// Run directories left behind by exited runs for longer than this are removed at startup, e.g. `12h`
+    #[serde(deserialize_with = "deserialize_age")]
+    pub tmp_max_age: Option<Duration>,
+    pub verbose: Option<bool>,
+    pub color: Option<ColorChoice>,
+    pub theme: Option<Theme>,
+    /// Filter for verbose logging when RUST_LOG isn't set, e.g. `stitch=debug`
+    pub log_filter: Option<String>,
+    /// Directory logs and other data are kept in, instead of the platform data dir
+    pub data_dir: Option<PathBuf>,
+    /// Directory JSON logs are written to, instead of `logs` in the data dir
+    pub log_dir: Option<PathBuf>,
+    pub no_file_log: Option<bool>,
+    /// Logs from previous runs to keep, counting the current run's
+    pub log_max_count: Option<usize>,
+    /// Remove logs older than this, e.g. `30d`
+    #[serde(deserialize_with = "deserialize_age")]
+    pub log_max_age: Option<Duration>,
+    /// Remove the oldest logs until the logs directory is at most this size, e.g. `500M`
+    #[serde(deserialize_with = "deserialize_byte_size")]
+    pub log_max_size: Option<u64>,
+    /// Notified as targets start, finish and fail, unless `--webhook` is given
+    pub webhooks: Vec<Webhook>,
+}
+
+fn deserialize_byte_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|given| limits::parse_byte_size(&given).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn deserialize_age<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|given| parse_age(&given).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// `~/.config/stitch/config.toml` on Linux, the platform's config directory elsewhere
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("stitch").join("config.toml"))
+}
+
+/// Loads `given`, or the default config file if it exists. A missing default file is an empty config
+pub fn load(given: Option<&Path>) -> Result<Config, ConfigError> {
+    let path = match given {
+        Some(path) => path.to_path_buf(),
+        None => match default_config_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+
+    let content = std::fs::read_to_string(&path).map_err(|e| ConfigError::Read {
+        path: path.display().to_string(),
+        inner_error: e.into(),
+    })?;
+
+    toml::from_str(&content).map_err(|e| ConfigError::Parse {
+        path: path.display().to_string(),
+        inner_error: e.into(),
+    })
+}
+
+impl Config {
+    /// How many, how old and how much of the JSON logs to keep
+    pub fn log_retention(&self) -> LogRetention {
+        LogRetention {
+            max_count: self.log_max_count,
+            max_age: self.log_max_age,
+            max_size: self.log_max_size,
+        }
+    }
+
+    /// Fills in anything the command line left unset
+    pub fn apply(&self, command: &mut Command) {
+        match command {
+            Command::Run(run) => self.apply_run(run),
+            Command::Plan(plan) => {
+                self.apply_binaries(&mut plan.binaries);
+                if plan.audio_placeholder.is_none() {
+                    plan.audio_placeholder = self.audio_placeholder.clone();
+                }
+                plan.hwaccel_decode = plan.hwaccel_decode.or(self.hwaccel_decode);
+                plan.frame_rate_conversion =
+                    plan.frame_rate_conversion.or(self.frame_rate_conversion);
+                plan.mp4_layout = plan.mp4_layout.or(self.mp4_layout);
+                if plan.codec.is_none() {
+                    plan.codec = self.codec.clone();
+                }
+                if plan.preset.is_none() {
+                    plan.preset = self.preset.clone();
+                }
+                plan.crf = plan.crf.or(self.crf);
+                if plan.channel_layout.is_none() {
+                    plan.channel_layout = self.channel_layout.clone();
+                }
+                plan.sample_rate = plan.sample_rate.or(self.sample_rate);
+            }
+            Command::Probe(probe) => self.apply_binaries(&mut probe.binaries),
+            Command::Doctor(doctor) => self.apply_binaries(&mut doctor.binaries),
+            Command::Serve(serve) => {
+                self.apply_binaries(&mut serve.binaries);
+                serve.jobs = serve.jobs.or(self.jobs);
+            }
+            Command::Estimate(estimate) => {
+                self.apply_binaries(&mut estimate.binaries);
+                estimate.jobs = estimate.jobs.or(self.jobs);
+            }
+            Command::Validate(_)
+            | Command::List(_)
+            | Command::Clean(_)
+            | Command::Init(_)
+            | Command::Fmt(_)
+            | Command::History(_)
+            | Command::Completions(_)
+            | Command::Man(_)
+            | Command::Schema(_) => {}
+        }
+    }
+
+    fn apply_run(&self, run: &mut RunArgs) {
+        self.apply_binaries(&mut run.binaries);
+
+        run.jobs = run.jobs.or(self.jobs);
+        run.max_load = run.max_load.or(self.max_load);
+        run.max_memory = run.max_memory.or(self.max_memory);
+        run.low_priority = run.low_priority.or(self.low_priority);
+        run.fail_fast = run.fail_fast.or(self.fail_fast);
+        run.progress_source = run.progress_source.or(self.progress_source);
+        if run.audio_placeholder.is_none() {
+            run.audio_placeholder = self.audio_placeholder.clone();
+        }
+        run.hwaccel_decode = run.hwaccel_decode.or(self.hwaccel_decode);
+        run.frame_rate_conversion = run.frame_rate_conversion.or(self.frame_rate_conversion);
+        run.mp4_layout = run.mp4_layout.or(self.mp4_layout);
+        if run.codec.is_none() {
+            run.codec = self.codec.clone();
+        }
+        if run.preset.is_none() {
+            run.preset = self.preset.clone();
+        }
+        run.crf = run.crf.or(self.crf);
+        if run.channel_layout.is_none() {
+            run.channel_layout = self.channel_layout.clone();
+        }
+        run.sample_rate = run.sample_rate.or(self.sample_rate);
+        run.detect_gaps = run.detect_gaps.or(self.detect_gaps);
+        run.terminal_title = run.terminal_title.or(self.terminal_title);
+        if run.webhooks.is_empty() {
+            run.webhooks = self.webhooks.clone();
+        }
+        // --no-progress conflicts with --progress-format, so it wins over the config too
+        if !run.no_progress {
+            run.progress_format = run.progress_format.or(self.progress_format);
+        }
+    }
+
+    /// A path or command given on the command line replaces both from the config
+    fn apply_binaries(&self, binaries: &mut BinaryArgs) {
+        if binaries.ffmpeg_path.is_none() && binaries.ffmpeg_cmd.is_none() {
+            binaries.ffmpeg_path = self.ffmpeg_path.clone();
+            binaries.ffmpeg_cmd = self.ffmpeg_cmd.clone();
+        }
+        if binaries.ffprobe_path.is_none() && binaries.ffprobe_cmd.is_none() {
+            binaries.ffprobe_path = self.ffprobe_path.clone();
+            binaries.ffprobe_cmd = self.ffprobe_cmd.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::Args;
+
+    /// `stitch run` parsed from `given`, with `config` filling in what it leaves unset
+    fn run_args(config: &str, given: &[&str]) -> RunArgs {
+        let config: Config = toml::from_str(config).unwrap();
+        let args = Args::try_parse_from(["stitch", "run"].iter().chain(given)).unwrap();
+        let mut command = args.command.unwrap();
+        config.apply(&mut command);
+        match command {
+            Command::Run(run) => run,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn config_fills_unset_flags() {
+        let run = run_args(
+            "jobs = 3\nfail-fast = true\ncodec = \"libx265\"\nmax-memory = \"8G\"",
+            &["x.stitchspec"],
+        );
+
+        assert_eq!(run.jobs, Some(3));
+        assert_eq!(run.fail_fast, Some(true));
+        assert_eq!(run.codec.as_deref(), Some("libx265"));
+        assert_eq!(run.max_memory, Some(8 * 1024 * 1024 * 1024));
+        assert_eq!(run.low_priority, None);
+    }
+
+    #[test]
+    fn flags_win_over_config() {
+        let run = run_args(
+            "jobs = 3\nfail-fast = true\nlow-priority = true",
+            &["--jobs", "2", "--fail-fast=false", "x.stitchspec"],
+        );
+
+        assert_eq!(run.jobs, Some(2));
+        assert_eq!(run.fail_fast, Some(false));
+        assert_eq!(run.low_priority, Some(true));
+    }
+
+    #[test]
+    fn bare_switch_doesnt_take_the_spec() {
+        let run = run_args("fail-fast = false", &["--fail-fast", "x.stitchspec"]);
+
+        assert_eq!(run.fail_fast, Some(true));
+        assert_eq!(run.spec.specs, [PathBuf::from("x.stitchspec")]);
+    }
+
+    #[test]
+    fn no_progress_wins_over_config_progress_format() {
+        let run = run_args(
+            "progress-format = \"json\"",
+            &["--no-progress", "x.stitchspec"],
+        );
+
+        assert_eq!(run.progress_format, None);
+    }
+
+    #[test]
+    fn binary_flag_replaces_both_from_config() {
+        let run = run_args(
+            "ffmpeg-cmd = \"docker run --rm -i ffmpeg\"\nffprobe-path = \"/opt/ffprobe\"",
+            &["--ffmpeg-path", "/usr/bin/ffmpeg", "x.stitchspec"],
+        );
+
+        assert_eq!(
+            run.binaries.ffmpeg_path,
+            Some(PathBuf::from("/usr/bin/ffmpeg"))
+        );
+        assert_eq!(run.binaries.ffmpeg_cmd, None);
+        assert_eq!(
+            run.binaries.ffprobe_path,
+            Some(PathBuf::from("/opt/ffprobe"))
+        );
+    }
+
+    #[test]
+    fn unknown_config_key() {
+        assert!(toml::from_str::<Config>("job = 3").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+/// Codecs a container can hold. Containers that aren't listed, like mkv, are assumed to hold anything
+struct Container {
+    extensions: &'static [&'static str],
+    video: &'static [&'static str],
+    /// Entries ending in `_` match every codec with that prefix
+    audio: &'static [&'static str],
+}
+
+const CONTAINERS: &[Container] = &[
+    Container {
+        extensions: &["mp4", "m4v"],
+        video: &["h264", "hevc", "av1", "vp9", "mpeg4", "mpeg2video", "mjpeg"],
+        audio: &["aac", "mp3", "ac3", "eac3", "opus", "flac", "alac"],
+    },
+    Container {
+        extensions: &["mov"],
+        video: &[
+            "h264",
+            "hevc",
+            "av1",
+            "vp9",
+            "mpeg4",
+            "mpeg2video",
+            "mjpeg",
+            "prores",
+        ],
+        audio: &["aac", "mp3", "ac3", "eac3", "alac", "pcm_"],
+    },
+    Container {
+        extensions: &["webm"],
+        video: &["vp8", "vp9", "av1"],
+        audio: &["vorbis", "opus"],
+    },
+    Container {
+        extensions: &["avi"],
+        video: &[
+            "h264",
+            "mpeg4",
+            "msmpeg4v2",
+            "msmpeg4v3",
+            "mjpeg",
+            "mpeg2video",
+            "rawvideo",
+        ],
+        audio: &["mp3", "mp2", "ac3", "pcm_"],
+    },
+    Container {
+        extensions: &["ts", "mts", "m2ts"],
+        video: &["h264", "hevc", "mpeg2video", "mpeg4"],
+        audio: &["aac", "mp3", "mp2", "ac3", "eac3"],
+    },
+    Container {
+        extensions: &["flv"],
+        video: &["h264", "flv1"],
+        audio: &["aac", "mp3"],
+    },
+];
+
+/// The first of `streams`, as `(codec_type, codec_name)`, that a file with `extension` can't hold
+pub fn unsupported_stream<'a>(
+    extension: &str,
+    streams: impl IntoIterator<Item = &'a (String, String)>,
+) -> Option<&'a (String, String)> {
+    let extension = extension.to_ascii_lowercase();
+    let container = CONTAINERS
+        .iter()
+        .find(|container| container.extensions.contains(&extension.as_str()))?;
+
+    streams.into_iter().find(|(codec_type, codec_name)| {
+        let supported = match codec_type.as_str() {
+            "video" => container.video,
+            "audio" => container.audio,
+            _ => return false,
+        };
+        !supported.iter().any(|supported| {
+            codec_name == supported
+                || (supported.ends_with('_') && codec_name.starts_with(supported))
+        })
+    })
+}
@@ -1,5 +1,5 @@
 use std::{
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     io,
     path::{Path, PathBuf},
     sync::OnceLock,
@@ -68,12 +68,75 @@ fn find_binary(bin: &str, given: Option<PathBuf>) -> io::Result<PathBuf> {
     ))
 }
 
+/// Where wrapper scripts are written, in this run's temp directory
+fn wrapper_dir() -> PathBuf {
+    crate::path::run_tmp_root().join("bin")
+}
+
+/// Writes a script named `bin` that runs `command` with the arguments it's given, e.g. `docker run --rm -i jrottenberg/ffmpeg`,
+/// into a directory [apply_wrappers] puts first on the PATH of every ffmpeg and ffprobe started
+#[cfg(unix)]
+#[instrument(level = Level::DEBUG)]
+fn write_wrapper(bin: &str, command: &str) -> io::Result<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = wrapper_dir();
+    std::fs::create_dir_all(&dir)?;
+
+    let path = dir.join(bin);
+    std::fs::write(&path, format!("#!/bin/sh\nexec {command} \"$@\"\n"))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+
+    tracing::info!(bin = bin, command = command, path =% path.display(), "Running {} with {}", bin, command);
+    Ok(path)
+}
+
+#[cfg(not(unix))]
+fn write_wrapper(bin: &str, _command: &str) -> io::Result<PathBuf> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Wrapper commands for {bin} are only supported on unix"),
+    ))
+}
+
 static FFMPEG_PATH: OnceLock<PathBuf> = OnceLock::new();
 static FFPROBE_PATH: OnceLock<PathBuf> = OnceLock::new();
+/// PATH with the wrapper scripts' directory first, only set when there are any. Other threads read the environment,
+/// so it's given to each process started rather than set on stitch's own
+static CHILD_PATH: OnceLock<OsString> = OnceLock::new();
+
+/// Where ffmpeg and ffprobe are found, a path or a wrapper command
+#[derive(Debug, Clone)]
+pub enum Binary {
+    Path(PathBuf),
+    Command(String),
+}
 
-pub fn find_binaries(ffmpeg: Option<PathBuf>, ffprobe: Option<PathBuf>) -> io::Result<()> {
-    let ffmpeg = find_binary("ffmpeg", ffmpeg)?;
-    let ffprobe = find_binary("ffprobe", ffprobe)?;
+/// Finds ffmpeg and ffprobe, each from the first of:
+/// 1. `--ffmpeg-cmd`/`--ffmpeg-path` (and the ffprobe equivalents)
+/// 2. `STITCH_FFMPEG_CMD`/`STITCH_BIN_FFMPEG`
+/// 3. `ffmpeg-cmd`/`ffmpeg-path` in the config file
+/// 4. PATH
+///
+/// clap and [`crate::config::Config::apply`] settle the first three into `ffmpeg` and `ffprobe`. A path that isn't a
+/// file is warned about and PATH is searched instead, a command is used as given
+pub fn find_binaries(ffmpeg: Option<Binary>, ffprobe: Option<Binary>) -> io::Result<()> {
+    let find = |bin: &str, given: Option<Binary>| match given {
+        Some(Binary::Command(command)) => write_wrapper(bin, &command),
+        Some(Binary::Path(path)) => find_binary(bin, Some(path)),
+        None => find_binary(bin, None),
+    };
+    let wrapped = [&ffmpeg, &ffprobe]
+        .iter()
+        .any(|given| matches!(given, Some(Binary::Command(_))));
+    let ffmpeg = find("ffmpeg", ffmpeg)?;
+    let ffprobe = find("ffprobe", ffprobe)?;
+
+    if wrapped {
+        let path_variable = std::env::var_os("PATH").unwrap_or_default();
+        let search_paths = std::iter::once(wrapper_dir()).chain(std::env::split_paths(&path_variable));
+        let _ = CHILD_PATH.set(std::env::join_paths(search_paths).map_err(io::Error::other)?);
+    }
 
     FFMPEG_PATH.get_or_init(|| ffmpeg);
     FFPROBE_PATH.get_or_init(|| ffprobe);
@@ -81,6 +144,14 @@ pub fn find_binaries(ffmpeg: Option<PathBuf>, ffprobe: Option<PathBuf>) -> io::R
     Ok(())
 }
 
+/// Has `cmd` look for ffmpeg and ffprobe in the wrapper scripts' directory first, if there are any. Programs started by
+/// name are looked up on the PATH they're given
+pub fn apply_wrappers(cmd: &mut tokio::process::Command) {
+    if let Some(path_variable) = CHILD_PATH.get() {
+        cmd.env("PATH", path_variable);
+    }
+}
+
 pub fn get_ffmpeg<'a>() -> Option<&'a PathBuf> {
     FFMPEG_PATH.get()
 }
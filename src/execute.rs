@@ -1,59 +1,665 @@
 use std::{
     collections::HashMap,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
-        Arc,
+        Arc, OnceLock,
         atomic::{AtomicUsize, Ordering},
     },
+    time::Duration,
 };
 
 use liberror::AnyError;
 use libffmpeg::{
-    duration::DurationError,
     ffmpeg::{FfmpegError, ffmpeg_with_progress},
     util::cmd::{self, CommandError, CommandExit},
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
-use tokio::{io::AsyncWriteExt, task::JoinSet};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt},
+    sync::SemaphorePermit,
+    task::JoinSet,
+};
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_util::{future::FutureExt, sync::CancellationToken};
 use tracing::{Instrument, Level, Span, instrument};
 use uuid::Uuid;
 use valuable::Valuable;
 
-use crate::parse::{Flag, Plan};
+use crate::{
+    color, container, env, limits,
+    parse::{Flag, Plan, PlanPath},
+    path, pause,
+    probe::{self, DurationError},
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize, Valuable, Error)]
+/// Why a target failed. Errors from other crates are any JSON in the schema, their shape is up to those crates
+#[derive(Debug, Clone, Serialize, Deserialize, Valuable, Error, JsonSchema)]
 pub enum ExecuteError {
     #[error(transparent)]
     Ffmpeg {
         #[from]
+        #[schemars(with = "serde_json::Value")]
         inner_error: FfmpegError,
     },
     #[error(transparent)]
     Duration {
         #[from]
+        #[schemars(with = "serde_json::Value")]
         inner_error: DurationError,
     },
     #[error("Failed to send progress message: {inner_error}")]
-    Send { inner_error: AnyError },
+    Send {
+        #[schemars(with = "serde_json::Value")]
+        inner_error: AnyError,
+    },
+    #[error("Failed to create target directory \"{path}\": {inner_error}")]
+    CreateTargetDir {
+        path: String,
+        #[schemars(with = "serde_json::Value")]
+        inner_error: AnyError,
+    },
     #[error("Failed to create catfile at \"{catfile_path}\": {inner_error}")]
     CreateCatFile {
         catfile_path: String,
+        #[schemars(with = "serde_json::Value")]
         inner_error: AnyError,
     },
     #[error("Failed to write to catfile at \"{catfile_path}\": {inner_error}")]
     WriteToCatFile {
         catfile_path: String,
+        #[schemars(with = "serde_json::Value")]
         inner_error: AnyError,
     },
     #[error("Failed to determine if some sources had audio tracks: {inner_errors:?}")]
-    AudioFailures { inner_errors: Vec<CommandError> },
+    AudioFailures {
+        #[schemars(with = "Vec<serde_json::Value>")]
+        inner_errors: Vec<CommandError>,
+    },
+    #[error("Failed to determine the resolution of some sources: {inner_errors:?}")]
+    ResolutionFailures {
+        #[schemars(with = "Vec<serde_json::Value>")]
+        inner_errors: Vec<CommandError>,
+    },
+    #[error("Failed to list the streams of some sources: {inner_errors:?}")]
+    StreamFailures {
+        #[schemars(with = "Vec<serde_json::Value>")]
+        inner_errors: Vec<CommandError>,
+    },
+    #[error("Failed to determine the colour space of some sources: {inner_errors:?}")]
+    ColorFailures {
+        #[schemars(with = "Vec<serde_json::Value>")]
+        inner_errors: Vec<CommandError>,
+    },
+    #[error("Failed to determine the audio format of some sources: {inner_errors:?}")]
+    AudioFormatFailures {
+        #[schemars(with = "Vec<serde_json::Value>")]
+        inner_errors: Vec<CommandError>,
+    },
+    #[error("Failed to check the target for black video and silence: {inner_error}")]
+    GapDetection {
+        #[schemars(with = "serde_json::Value")]
+        inner_error: CommandError,
+    },
+    #[error("Failed to check the target's timestamps: {inner_error}")]
+    TimestampCheck {
+        #[schemars(with = "serde_json::Value")]
+        inner_error: CommandError,
+    },
+    #[error("Failed to find black video and silence to trim from \"{source_name}\": {inner_error}")]
+    TrimDetection {
+        source_name: String,
+        #[schemars(with = "serde_json::Value")]
+        inner_error: CommandError,
+    },
+    #[error(
+        "Sources {source_names:?} have no video, use --audio-placeholder to stand in a still image or black frames"
+    )]
+    NoVideo { source_names: Vec<String> },
+    #[error(
+        "A .{extension} target can't hold {codec_type} in {codec_name}, use a container that can or the concat-filter flag to re-encode"
+    )]
+    UnsupportedCodec {
+        extension: String,
+        codec_type: String,
+        codec_name: String,
+    },
+    #[error("Failed to run ffmpeg for the intermediate of \"{source_name}\": {inner_error}")]
+    IntermediateCommand {
+        source_name: String,
+        #[schemars(with = "serde_json::Value")]
+        inner_error: CommandError,
+    },
+    #[error("ffmpeg exited unsuccessfully making the intermediate of \"{source_name}\"")]
+    IntermediateUnsuccessful { source_name: String },
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("ffmpeg exited unsuccessfully")]
+    Unsuccessful,
+}
+
+impl ExecuteError {
+    /// Stable across releases like [crate::parse::ParseError::code], errors running a target are 3xx
+    pub fn code(&self) -> u16 {
+        match self {
+            ExecuteError::Ffmpeg { .. } => 301,
+            ExecuteError::Duration { .. } => 302,
+            ExecuteError::Send { .. } => 303,
+            ExecuteError::CreateTargetDir { .. } => 304,
+            ExecuteError::CreateCatFile { .. } => 305,
+            ExecuteError::WriteToCatFile { .. } => 306,
+            ExecuteError::AudioFailures { .. } => 307,
+            ExecuteError::ResolutionFailures { .. } => 308,
+            ExecuteError::StreamFailures { .. } => 309,
+            ExecuteError::ColorFailures { .. } => 310,
+            ExecuteError::AudioFormatFailures { .. } => 311,
+            ExecuteError::GapDetection { .. } => 312,
+            ExecuteError::TimestampCheck { .. } => 313,
+            ExecuteError::TrimDetection { .. } => 314,
+            ExecuteError::NoVideo { .. } => 315,
+            ExecuteError::UnsupportedCodec { .. } => 316,
+            ExecuteError::IntermediateCommand { .. } => 317,
+            ExecuteError::IntermediateUnsuccessful { .. } => 318,
+            ExecuteError::Cancelled => 319,
+            ExecuteError::Unsuccessful => 320,
+        }
+    }
 }
 
 pub type ExecuteResult = Result<(), ExecuteError>;
 
-#[derive(Debug, Clone, Valuable)]
+/// How long to wait before re-reading a file that ffmpeg hasnt written to yet
+const FOLLOW_FILE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often the size of the target is checked while ffmpeg writes it
+const OUTPUT_SIZE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Timestamp jumps reported one by one before the rest are summed up
+const MAX_REPORTED_JUMPS: usize = 5;
+
+/// How far black video or silence can be from the start or end of a clip and still count as being at it
+const JOIN_TOLERANCE_SECONDS: f64 = 0.5;
+
+/// How far a copied cut can snap back to a keyframe before the frames it keeps are worth a warning
+const KEYFRAME_TOLERANCE_SECONDS: f64 = 0.1;
+
+/// ffmpeg log level written to the per-plan log, 32 is `info`
+const FFREPORT_LEVEL: u8 = 32;
+
+/// Where ffmpeg writes its `-progress` output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Valuable, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressSource {
+    /// Read progress from ffmpeg's stdout
+    #[default]
+    Pipe,
+    /// Write progress to a file in the run's temp directory and tail it
+    File,
+}
+
+/// Hardware decoder ffmpeg uses for the concat filter's inputs, given to it as `-hwaccel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Valuable, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HwaccelDecode {
+    Cuda,
+    Vaapi,
+    Videotoolbox,
+}
+impl HwaccelDecode {
+    pub fn as_arg(self) -> &'static str {
+        match self {
+            Self::Cuda => "cuda",
+            Self::Vaapi => "vaapi",
+            Self::Videotoolbox => "videotoolbox",
+        }
+    }
+}
+
+/// How re-encoded sources are brought to the output's 30fps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Valuable, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameRateConversion {
+    /// Drop or repeat whole frames, fast but stutters going from 60 to 30
+    #[default]
+    Drop,
+    /// Blend neighbouring frames together
+    Blend,
+    /// Motion interpolate new frames, smoothest but far slower
+    Interpolate,
+}
+impl FrameRateConversion {
+    pub fn filter(self) -> &'static str {
+        match self {
+            Self::Drop => "fps=30",
+            Self::Blend => "framerate=fps=30",
+            Self::Interpolate => "minterpolate=fps=30:mi_mode=mci",
+        }
+    }
+}
+
+/// How MP4 style targets are laid out for playback
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Valuable, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Mp4Layout {
+    /// Index moved to the front once the target is written, so it plays and seeks before it's fully downloaded
+    #[default]
+    Faststart,
+    /// Fragmented, playable while it's still being written and suited to live streaming, though some players seek slower
+    Fragmented,
+    /// ffmpeg's default, with the index at the end
+    Plain,
+}
+impl Mp4Layout {
+    pub fn movflags(self) -> Option<&'static str> {
+        match self {
+            Self::Faststart => Some("+faststart"),
+            Self::Fragmented => Some("+frag_keyframe+empty_moov+default_base_moof"),
+            Self::Plain => None,
+        }
+    }
+}
+
+/// Video synthesized for sources with audio but no video, so they can be concatenated with normal clips
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Valuable)]
+#[serde(from = "String")]
+pub enum AudioPlaceholder {
+    Black,
+    Image(PathBuf),
+}
+impl From<String> for AudioPlaceholder {
+    fn from(value: String) -> Self {
+        if value == "black" {
+            Self::Black
+        } else {
+            Self::Image(value.into())
+        }
+    }
+}
+impl std::str::FromStr for AudioPlaceholder {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.to_string().into())
+    }
+}
+
+/// Stand in video for a plan's sources that have no video of their own
+#[derive(Debug, Clone)]
+pub struct Placeholders {
+    pub placeholder: AudioPlaceholder,
+    /// Matches the first source with video, so the concat filter gets segments of one size
+    pub resolution: (u32, u32),
+    /// How long each source without video plays for, by source name
+    pub seconds: HashMap<String, f64>,
+}
+
+/// Finds the sources of a concat filter plan that have no video. Without a `placeholder` to stand in for them, the plan can't be stitched
+pub async fn find_placeholders(
+    plan: &Plan,
+    placeholder: Option<&AudioPlaceholder>,
+    ct: CancellationToken,
+) -> Result<Option<Placeholders>, ExecuteError> {
+    if plan.is_audio() || !plan.flags.contains(&Flag::ConcatFilter) {
+        return Ok(None);
+    }
+
+    let mut resolution = None;
+    let mut without_video = vec![];
+    let mut errors = vec![];
+    // Image sequences are all video
+    for source in plan
+        .sources
+        .iter()
+        .filter(|source| source.framerate.is_none())
+    {
+        match probe::resolution(source.path.clone(), ct.child_token()).await {
+            Ok(Some(found)) => {
+                resolution.get_or_insert(found);
+            }
+            Ok(None) => without_video.push(source),
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ExecuteError::ResolutionFailures {
+            inner_errors: errors,
+        });
+    }
+
+    if without_video.is_empty() {
+        return Ok(None);
+    }
+    let Some(placeholder) = placeholder else {
+        return Err(ExecuteError::NoVideo {
+            source_names: without_video
+                .iter()
+                .map(|source| source.leaf.clone())
+                .collect(),
+        });
+    };
+
+    let mut seconds = HashMap::new();
+    for source in without_video {
+        let duration = probe::duration(source, ct.child_token()).await?;
+        seconds.insert(source.leaf.clone(), duration.as_secs_f64());
+    }
+
+    Ok(Some(Placeholders {
+        placeholder: placeholder.clone(),
+        resolution: resolution.unwrap_or(limits::FALLBACK_RESOLUTION),
+        seconds,
+    }))
+}
+
+/// The colour standard of each source of a re-encoded plan that has to be converted, by source name.
+/// Empty when the sources already share one, so matching footage is left as it is
+pub async fn find_color_conversions(
+    plan: &Plan,
+    ct: CancellationToken,
+) -> Result<HashMap<String, &'static str>, ExecuteError> {
+    if plan.is_audio() || !plan.is_encode() {
+        return Ok(HashMap::new());
+    }
+
+    let mut standards = vec![];
+    let mut errors = vec![];
+    // Image sequences have no colour space tag to go by
+    for source in plan
+        .sources
+        .iter()
+        .filter(|source| source.framerate.is_none())
+    {
+        match probe::color_space(source.path.clone(), ct.child_token()).await {
+            Ok(Some((color_space, height))) => standards.push((
+                source.leaf.clone(),
+                color::standard(color_space.as_deref(), height),
+            )),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ExecuteError::ColorFailures {
+            inner_errors: errors,
+        });
+    }
+
+    if standards
+        .iter()
+        .all(|(_, standard)| *standard == standards[0].1)
+    {
+        return Ok(HashMap::new());
+    }
+    Ok(standards
+        .into_iter()
+        .filter(|(_, standard)| *standard != color::NORMALIZED)
+        .collect())
+}
+
+/// Channel layout sources with mixed layouts are mixed to by default
+pub const DEFAULT_CHANNEL_LAYOUT: &str = "stereo";
+
+/// Sample rate sources with mixed rates are resampled to by default
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// ffmpeg encoder re-encoded video is written with by default
+pub const DEFAULT_VIDEO_CODEC: &str = "libx264";
+
+/// Encoder preset used by default, trading encoding speed for size
+pub const DEFAULT_PRESET: &str = "medium";
+
+/// Constant rate factor used by default, lower is higher quality
+pub const DEFAULT_CRF: u8 = 23;
+
+/// How re-encoded targets and intermediates have their video encoded
+#[derive(Debug, Clone, PartialEq, Eq, Valuable)]
+pub struct VideoEncoding {
+    /// An ffmpeg encoder, e.g. `libx265` or `h264_nvenc`
+    pub codec: String,
+    pub preset: String,
+    pub crf: u8,
+}
+impl Default for VideoEncoding {
+    fn default() -> Self {
+        Self::new(None, None, None)
+    }
+}
+impl VideoEncoding {
+    /// `codec`, `preset` and `crf`, or the defaults for any left unset
+    pub fn new(codec: Option<String>, preset: Option<String>, crf: Option<u8>) -> Self {
+        Self {
+            codec: codec.unwrap_or_else(|| DEFAULT_VIDEO_CODEC.to_string()),
+            preset: preset.unwrap_or_else(|| DEFAULT_PRESET.to_string()),
+            crf: crf.unwrap_or(DEFAULT_CRF),
+        }
+    }
+
+    fn configure(&self, cmd: &mut tokio::process::Command) {
+        cmd.arg("-c:v").arg(&self.codec);
+        cmd.arg("-preset").arg(&self.preset);
+        cmd.arg("-crf").arg(self.crf.to_string());
+    }
+
+    /// The name ffprobe gives the codec the encoder writes, e.g. `hevc` for `libx265` or `hevc_nvenc`
+    pub fn codec_name(&self) -> &str {
+        match self.codec.as_str() {
+            "libx264" | "libopenh264" => "h264",
+            "libx265" => "hevc",
+            "libsvtav1" | "libaom-av1" | "librav1e" => "av1",
+            "libvpx-vp9" => "vp9",
+            "libvpx" => "vp8",
+            // Hardware encoders are named after the codec, e.g. h264_nvenc or av1_qsv
+            codec => codec.split_once('_').map_or(codec, |(name, _)| name),
+        }
+    }
+}
+
+/// How a concat filter plan's audio is brought to one format before it's concatenated
+#[derive(Debug, Clone, Default)]
+pub struct AudioNormalization {
+    /// Layout to mix every source to, when they don't share one
+    pub channel_layout: Option<String>,
+    /// Rate to resample every source to, when they don't share one
+    pub sample_rate: Option<u32>,
+}
+impl AudioNormalization {
+    /// Filters each source's audio goes through ahead of the concat filter, `anull` when there's nothing to change
+    pub fn filter(&self) -> String {
+        let mut filters = vec![];
+        if let Some(channel_layout) = &self.channel_layout {
+            filters.push(format!("aformat=channel_layouts={channel_layout}"));
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            filters.push(format!("aresample={sample_rate}"));
+        }
+
+        if filters.is_empty() {
+            "anull".to_string()
+        } else {
+            filters.join(",")
+        }
+    }
+}
+
+/// Finds how a concat filter plan's audio has to be normalized for its sources to concatenate cleanly.
+/// Sources with mixed channel layouts are mixed to `channel_layout`, and mixed sample rates resampled to `sample_rate`
+pub async fn find_audio_normalization(
+    plan: &Plan,
+    channel_layout: &str,
+    sample_rate: u32,
+    ct: CancellationToken,
+) -> Result<AudioNormalization, ExecuteError> {
+    if !plan.flags.contains(&Flag::ConcatFilter) {
+        return Ok(AudioNormalization::default());
+    }
+
+    let mut formats = vec![];
+    let mut errors = vec![];
+    // Image sequences never carry audio
+    for source in plan
+        .sources
+        .iter()
+        .filter(|source| source.framerate.is_none())
+    {
+        match probe::audio_format(source.path.clone(), ct.child_token()).await {
+            Ok(Some(format)) => formats.push(format),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ExecuteError::AudioFormatFailures {
+            inner_errors: errors,
+        });
+    }
+
+    let mixed_layouts = formats
+        .iter()
+        .any(|format| format.channel_layout != formats[0].channel_layout);
+    let mixed_sample_rates = formats
+        .iter()
+        .any(|format| format.sample_rate != formats[0].sample_rate);
+    Ok(AudioNormalization {
+        channel_layout: mixed_layouts.then(|| channel_layout.to_string()),
+        sample_rate: mixed_sample_rates.then_some(sample_rate),
+    })
+}
+
+/// The part of a source kept once its leading and trailing black video and silence are trimmed off, in seconds
+#[derive(Debug, Clone, Copy)]
+pub struct Trim {
+    pub start: f64,
+    pub end: f64,
+    /// How long the whole source plays for
+    pub duration: f64,
+}
+impl Trim {
+    /// Seconds cut from the source
+    pub fn trimmed(&self) -> f64 {
+        self.duration - (self.end - self.start)
+    }
+}
+
+/// Finds the black video and silence to trim off the start and end of an `auto-trim` or `copy-trim` plan's sources, by source name.
+/// Stretches only count where every stream is blank, so speech over black video or silent footage is kept
+pub async fn find_trims(
+    plan: &Plan,
+    ct: CancellationToken,
+) -> Result<HashMap<String, Trim>, ExecuteError> {
+    if !plan.is_trimmed() {
+        return Ok(HashMap::new());
+    }
+
+    let mut trims = HashMap::new();
+    // Image sequences are left whole
+    for source in plan
+        .sources
+        .iter()
+        .filter(|source| source.framerate.is_none())
+    {
+        let duration = probe::duration(source, ct.child_token())
+            .await?
+            .as_secs_f64();
+        let video = !plan.is_audio()
+            && probe::resolution(source.path.clone(), ct.child_token())
+                .await
+                .map_err(|e| ExecuteError::ResolutionFailures {
+                    inner_errors: vec![e],
+                })?
+                .is_some();
+        let audio = probe::has_audio(source.path.clone(), ct.child_token())
+            .await
+            .map_err(|e| ExecuteError::AudioFailures {
+                inner_errors: vec![e],
+            })?;
+        let kinds = [
+            (video, probe::GapKind::Black),
+            (audio, probe::GapKind::Silence),
+        ]
+        .into_iter()
+        .filter_map(|(present, kind)| present.then_some(kind))
+        .collect::<Vec<_>>();
+        if kinds.is_empty() {
+            continue;
+        }
+
+        let gaps = probe::gaps(
+            source.path.clone(),
+            video,
+            audio,
+            duration,
+            ct.child_token(),
+        )
+        .await
+        .map_err(|e| ExecuteError::TrimDetection {
+            source_name: source.leaf.clone(),
+            inner_error: e,
+        })?;
+        let leading = |kind| {
+            gaps.iter()
+                .find(|gap| gap.kind == kind && gap.start <= JOIN_TOLERANCE_SECONDS)
+                .map(|gap| gap.end)
+        };
+        let trailing = |kind| {
+            gaps.iter()
+                .find(|gap| gap.kind == kind && gap.end >= duration - JOIN_TOLERANCE_SECONDS)
+                .map(|gap| gap.start)
+        };
+        let start = kinds
+            .iter()
+            .map(|kind| leading(*kind))
+            .collect::<Option<Vec<_>>>()
+            .map(|ends| ends.into_iter().fold(f64::INFINITY, f64::min))
+            .unwrap_or(0.0);
+        let end = kinds
+            .iter()
+            .map(|kind| trailing(*kind))
+            .collect::<Option<Vec<_>>>()
+            .map(|starts| starts.into_iter().fold(0.0, f64::max))
+            .unwrap_or(duration);
+
+        if end - start < JOIN_TOLERANCE_SECONDS {
+            tracing::warn!(
+                source_name = source.leaf,
+                "Source is blank throughout, leaving it untrimmed"
+            );
+            continue;
+        }
+        if start > 0.0 || end < duration {
+            trims.insert(
+                source.leaf.clone(),
+                Trim {
+                    start,
+                    end,
+                    duration,
+                },
+            );
+        }
+    }
+
+    Ok(trims)
+}
+
+#[derive(Debug, Clone, Default, Valuable)]
+pub struct ExecuteOptions {
+    pub progress_source: ProgressSource,
+    pub audio_placeholder: Option<AudioPlaceholder>,
+    pub hwaccel_decode: Option<HwaccelDecode>,
+    pub frame_rate_conversion: FrameRateConversion,
+    pub mp4_layout: Mp4Layout,
+    pub video_encoding: VideoEncoding,
+    /// Layout sources with mixed channel layouts are mixed to, [DEFAULT_CHANNEL_LAYOUT] when unset
+    pub channel_layout: Option<String>,
+    /// Rate sources with mixed sample rates are resampled to, [DEFAULT_SAMPLE_RATE] when unset
+    pub sample_rate: Option<u32>,
+    /// Check finished targets for black video and silence where their sources meet
+    pub detect_gaps: bool,
+}
+
+#[derive(Debug, Clone, Valuable, Serialize, JsonSchema)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum ExecuteProgressPayload {
     Start {
         target_name: String,
@@ -66,61 +672,146 @@ pub enum ExecuteProgressPayload {
         total_duration_seconds: f64,
         has_audio: bool,
         mode: String,
+        /// Of the first source with video, only probed for plans that encode
+        resolution: Option<(u32, u32)>,
     },
     Phase {
         phase: String,
     },
+    /// Waiting for a job slot, the load to drop or room in the memory budget, rather than stuck
+    Queued {
+        reason: String,
+    },
+    Command {
+        command_line: String,
+    },
+    Log {
+        line: String,
+    },
     Warning {
         message: String,
     },
-    Finished(CommandExit),
+    Finished(
+        #[serde(serialize_with = "serialize_command_exit")]
+        #[schemars(with = "FinishedExit")]
+        CommandExit,
+    ),
     Failed(ExecuteError),
     Progress {
         total_seconds: f64,
         current_seconds: f64,
     },
+    /// Size of the target so far, checked every [OUTPUT_SIZE_INTERVAL] while ffmpeg writes it
+    OutputSize {
+        bytes: u64,
+    },
+    /// From ffmpeg's stats line, copies have no fps
+    Stats {
+        fps: Option<f64>,
+        speed: Option<f64>,
+    },
     Spawned,
 }
 
-#[derive(Debug, Clone)]
+/// One line of `--progress-format json` and `--progress-file`
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct ExecuteProgress {
     pub id: Uuid,
     pub seq: usize,
     pub payload: ExecuteProgressPayload,
+    /// [ExecuteError::code] of a `failed` payload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<u16>,
+}
+
+/// How [serialize_command_exit] writes a [CommandExit]
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct FinishedExit {
+    success: bool,
+}
+
+fn serialize_command_exit<S: serde::Serializer>(
+    exit: &CommandExit,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+
+    let mut state = serializer.serialize_struct("CommandExit", 1)?;
+    state.serialize_field(
+        "success",
+        &exit.exit_code.as_ref().is_some_and(|code| code.success),
+    )?;
+    state.end()
 }
 
 #[derive(Debug)]
 struct Process {
-    seq: AtomicUsize,
+    /// Shared with [`Process::with_plan`], so deliveries stay in order
+    seq: Arc<AtomicUsize>,
     id: Uuid,
     plan: Plan,
     tx: tokio::sync::mpsc::Sender<ExecuteProgress>,
     tmp_root: PathBuf,
+    options: ExecuteOptions,
     cancellation_token: CancellationToken,
+    /// Set once the sources have been checked for anything to trim
+    trims: OnceLock<HashMap<String, Trim>>,
 }
 impl Process {
     fn new(
         plan: Plan,
         tx: tokio::sync::mpsc::Sender<ExecuteProgress>,
         tmp_root: PathBuf,
+        options: ExecuteOptions,
         cancellation_token: CancellationToken,
     ) -> Self {
         Self {
-            seq: AtomicUsize::new(0),
+            seq: Arc::new(AtomicUsize::new(0)),
             id: Uuid::new_v4(),
             plan,
             tx,
             tmp_root,
+            options,
             cancellation_token,
+            trims: OnceLock::new(),
+        }
+    }
+
+    /// The same process going on to stitch `plan` instead
+    fn with_plan(&self, plan: Plan) -> Self {
+        Self {
+            seq: self.seq.clone(),
+            id: self.id,
+            plan,
+            tx: self.tx.clone(),
+            tmp_root: self.tmp_root.clone(),
+            options: self.options.clone(),
+            cancellation_token: self.cancellation_token.clone(),
+            trims: OnceLock::new(),
         }
     }
 
+    fn tmp_file_path(&self, extension: &str) -> PathBuf {
+        // Targets can be given with directories or drive letters, intermediates all live directly in tmp_root
+        let name = self
+            .plan
+            .target_path
+            .leaf
+            .replace(['.', '/', '\\', ':'], "_");
+        self.tmp_root.join(format!("{name}.{extension}"))
+    }
+
     async fn send(&self, payload: ExecuteProgressPayload) {
         if let Err(e) = self
             .tx
             .send(ExecuteProgress {
                 id: self.id,
                 seq: self.seq.fetch_add(1, Ordering::Relaxed),
+                error_code: match &payload {
+                    ExecuteProgressPayload::Failed(error) => Some(error.code()),
+                    _ => None,
+                },
                 payload,
             })
             .await
@@ -141,17 +832,310 @@ impl Process {
         .await;
     }
 
+    /// Takes a `--jobs` slot, then waits out a pause or high load
+    async fn wait_for_slot(&self) -> Result<SemaphorePermit<'static>, ExecuteError> {
+        self.send(ExecuteProgressPayload::Queued {
+            reason: "Waiting for an available job slot".to_string(),
+        })
+        .await;
+        let Some(permit) = limits::process_limit()
+            .acquire()
+            .with_cancellation_token(&self.cancellation_token)
+            .await
+        else {
+            return Err(ExecuteError::Cancelled);
+        };
+        let permit = permit.expect("Process limit semaphore is never closed");
+
+        if pause::is_paused() {
+            self.send(ExecuteProgressPayload::Phase {
+                phase: "Paused".to_string(),
+            })
+            .await;
+        }
+        if !pause::wait_while_paused(&self.cancellation_token).await {
+            return Err(ExecuteError::Cancelled);
+        }
+
+        if limits::is_overloaded() {
+            self.send(ExecuteProgressPayload::Queued {
+                reason: "Waiting for system load to drop".to_string(),
+            })
+            .await;
+        }
+        if !limits::wait_for_load(&self.cancellation_token).await {
+            return Err(ExecuteError::Cancelled);
+        }
+
+        Ok(permit)
+    }
+
+    /// Transcodes every source to a uniform intermediate in parallel, each in a job slot of its own.
+    /// The returned process concat copies the intermediates
+    #[instrument(level = Level::INFO)]
+    async fn transcode_intermediates(self: &Arc<Self>) -> Result<Process, ExecuteError> {
+        let source_has_audio = self.get_source_has_audio().await?;
+        // Intermediates only hold video and audio, this warns that attachments are dropped
+        self.carried_attachment_sources().await?;
+        if self.plan.flags.contains(&Flag::KeepData) {
+            self.send(ExecuteProgressPayload::Warning {
+                message: "Data streams are only kept when copying, re-encoding drops them"
+                    .to_string(),
+            })
+            .await;
+        }
+        let format = intermediate_format(
+            &self.plan,
+            &source_has_audio,
+            self.options.frame_rate_conversion,
+            self.options.video_encoding.clone(),
+            self.cancellation_token.child_token(),
+        )
+        .await?;
+        let color_conversions =
+            find_color_conversions(&self.plan, self.cancellation_token.child_token()).await?;
+        for (source_name, standard) in color_conversions.iter() {
+            self.send(ExecuteProgressPayload::Warning {
+                message: format!(
+                    "{source_name} is {standard}, converting it to {}",
+                    color::NORMALIZED
+                ),
+            })
+            .await;
+        }
+
+        self.send(ExecuteProgressPayload::Phase {
+            phase: format!("Transcoding {} intermediates", self.plan.sources.len()),
+        })
+        .await;
+
+        let mut tasks = JoinSet::new();
+        let span = Span::current();
+        // Stops the rest once one fails, each removes its partial intermediate when ffmpeg has stopped
+        let transcode_token = self.cancellation_token.child_token();
+        let mut partials = vec![];
+        for (index, source) in self.plan.sources.iter().enumerate() {
+            let this = self.clone();
+            let transcode_token = transcode_token.clone();
+            let source = source.clone();
+            let has_audio = source_has_audio.get(&source.leaf).copied().unwrap_or(false);
+            let color_conversion = color_conversions.get(&source.leaf).copied();
+            let cache_root = path::intermediates_root();
+            let format = format.clone();
+            let key = intermediate_key(&source, has_audio, &format, color_conversion);
+            let cached = cache_root.join(format!("{key}.mkv"));
+            // Written under another name until it's complete, so a cancelled run never leaves a truncated intermediate to be reused
+            let partial = cache_root.join(format!("{}.{key}.partial.mkv", path::run_id()));
+            let (output, cache) = match std::fs::create_dir_all(&cache_root) {
+                Ok(()) => {
+                    partials.push(partial.clone());
+                    (partial, Some(cached))
+                }
+                Err(e) => {
+                    tracing::warn!(path =% cache_root.display(), error =% e, error_context =? e, "Failed to create intermediate cache, intermediates won't be reused");
+                    (self.tmp_file_path(&format!("{index}.mkv")), None)
+                }
+            };
+
+            tasks.spawn(
+                async move {
+                    if let Some(cached) = cache.as_ref().filter(|cached| cached.exists()) {
+                        tracing::info!(source_name = source.leaf, path =% cached.display(), "Reusing cached intermediate");
+                        // Marks it as recently used for `stitch clean --older-than`
+                        if let Err(e) = std::fs::File::options()
+                            .write(true)
+                            .open(cached)
+                            .and_then(|file| file.set_modified(std::time::SystemTime::now()))
+                        {
+                            tracing::debug!(path =% cached.display(), error =% e, error_context =? e, "Failed to touch cached intermediate");
+                        }
+                        return Ok((
+                            index,
+                            PlanPath {
+                                path: cached.clone(),
+                                leaf: source.leaf,
+                                framerate: None,
+                                video_stream: None,
+                            },
+                        ));
+                    }
+
+                    let _permit = tokio::select! {
+                        permit = this.wait_for_slot() => permit?,
+                        _ = transcode_token.cancelled() => return Err(ExecuteError::Cancelled),
+                    };
+                    let exit = cmd::run("ffmpeg", None, transcode_token.child_token(), |cmd| {
+                        env::apply_wrappers(cmd);
+                        limits::apply_priority(cmd);
+                        configure_intermediate(
+                            cmd,
+                            &source,
+                            has_audio,
+                            &format,
+                            color_conversion,
+                            &output,
+                        );
+                    })
+                    .await
+                    .map_err(|e| ExecuteError::IntermediateCommand {
+                        source_name: source.leaf.clone(),
+                        inner_error: e,
+                    });
+                    let succeeded = exit
+                        .as_ref()
+                        .is_ok_and(|exit| exit.exit_code.as_ref().is_some_and(|code| code.success));
+                    if !succeeded {
+                        let _ = std::fs::remove_file(&output);
+                        exit?;
+                        return Err(ExecuteError::IntermediateUnsuccessful {
+                            source_name: source.leaf.clone(),
+                        });
+                    }
+
+                    let output = match cache {
+                        Some(cached) => match std::fs::rename(&output, &cached) {
+                            Ok(()) => cached,
+                            Err(e) => {
+                                tracing::warn!(path =% cached.display(), error =% e, error_context =? e, "Failed to cache intermediate");
+                                output
+                            }
+                        },
+                        None => output,
+                    };
+
+                    tracing::info!(source_name = source.leaf, path =% output.display(), "Transcoded intermediate");
+                    Ok((
+                        index,
+                        PlanPath {
+                            path: output,
+                            leaf: source.leaf,
+                            framerate: None,
+                            video_stream: None,
+                        },
+                    ))
+                }
+                .instrument(span.clone()),
+            );
+        }
+
+        let mut intermediates = vec![None; self.plan.sources.len()];
+        let mut done = 0;
+        while let Some(result) = tasks.join_next().await {
+            let (index, intermediate) = match result.expect("Failed to join task") {
+                Ok(intermediate) => intermediate,
+                Err(e) => {
+                    // The others would only be thrown away. They're stopped and waited for rather than aborted, so
+                    // ffmpeg isn't left writing to a partial intermediate once it's removed
+                    transcode_token.cancel();
+                    while tasks.join_next().await.is_some() {}
+                    for partial in partials.iter() {
+                        match std::fs::remove_file(partial) {
+                            Ok(()) => {
+                                tracing::debug!(path =% partial.display(), "Removed partial intermediate");
+                            }
+                            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                            Err(e) => {
+                                tracing::warn!(path =% partial.display(), error =% e, error_context =? e, "Failed to remove partial intermediate");
+                            }
+                        }
+                    }
+                    return Err(e);
+                }
+            };
+            intermediates[index] = Some(intermediate);
+            done += 1;
+            self.send(ExecuteProgressPayload::Phase {
+                phase: format!("Transcoded {done}/{} intermediates", intermediates.len()),
+            })
+            .await;
+        }
+
+        let mut plan = self.plan.clone();
+        plan.flags.retain(|flag| *flag != Flag::Mezzanine);
+        plan.sources = intermediates.into_iter().flatten().collect();
+        Ok(self.with_plan(plan))
+    }
+
     #[instrument(level = Level::INFO)]
+    /// Targets can be given with directories of their own, e.g. `season1/ep01.mp4`, which ffmpeg won't create
+    async fn create_target_dir(&self) -> Result<(), ExecuteError> {
+        let Some(dir) = self.plan.target_path.path.parent() else {
+            return Ok(());
+        };
+
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| ExecuteError::CreateTargetDir {
+                path: dir.display().to_string(),
+                inner_error: e.into(),
+            })
+            .inspect_err(|e| tracing::error!(path =% dir.display(), error =% e, error_context =? e, "Failed to create target directory"))
+    }
+
+    /// Finds what to trim off each source before the catfile is written, since copied cuts go in it
+    async fn find_trims(&self) -> Result<(), ExecuteError> {
+        let plan = &self.plan;
+        if plan.is_trimmed() {
+            self.send(ExecuteProgressPayload::Phase {
+                phase: "Finding black video and silence to trim".to_string(),
+            })
+            .await;
+        }
+        let trims = find_trims(plan, self.cancellation_token.child_token()).await?;
+        for (source_name, trim) in trims.iter() {
+            self.send(ExecuteProgressPayload::Warning {
+                message: format!(
+                    "Trimming {:.1}s from the start and {:.1}s from the end of {source_name}",
+                    trim.start,
+                    trim.duration - trim.end
+                ),
+            })
+            .await;
+        }
+
+        // The concat demuxer starts copying from the keyframe before an inpoint, so anything between the two is kept
+        if !plan.is_encode() && !plan.is_audio() {
+            for source in plan.sources.iter() {
+                let Some(trim) = trims.get(&source.leaf).filter(|trim| trim.start > 0.0) else {
+                    continue;
+                };
+                let keyframe = probe::keyframe_before(
+                    source.path.clone(),
+                    trim.start,
+                    self.cancellation_token.child_token(),
+                )
+                .await;
+                match keyframe {
+                    Ok(Some(keyframe)) if trim.start - keyframe > KEYFRAME_TOLERANCE_SECONDS => {
+                        self.send(ExecuteProgressPayload::Warning {
+                            message: format!(
+                                "The cut {:.1}s into {} snaps back to the keyframe at {keyframe:.1}s, keeping {:.1}s meant to be trimmed. Use auto-trim to re-encode and cut exactly",
+                                trim.start,
+                                source.leaf,
+                                trim.start - keyframe
+                            ),
+                        })
+                        .await;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!(source_name = source.leaf, error =% e, error_context =? e, "Failed to find the keyframe before a cut");
+                    }
+                }
+            }
+        }
+
+        let _ = self.trims.set(trims);
+        Ok(())
+    }
+
     async fn prepare_catfile(&self) -> Result<PathBuf, ExecuteError> {
         self.send(ExecuteProgressPayload::Phase {
             phase: "Preparing concatenation file".to_string(),
         })
         .await;
 
-        let catfile_path = self.tmp_root.join(format!(
-            "{}.catfile",
-            self.plan.target_path.leaf.replace(".", "_")
-        ));
+        let catfile_path = self.tmp_file_path("catfile");
 
         let mut file = tokio::fs::OpenOptions::new()
             .create_new(true)
@@ -165,13 +1149,7 @@ impl Process {
             .inspect(|_| tracing::info!(catfile_path =% catfile_path.display(), "Successfully opened catfile"))
             .inspect_err(|e| tracing::error!(catfile_path =% catfile_path.display(), error =% e, error_context =? e, "Failed to open catfile"))?;
 
-        let content = self
-            .plan
-            .sources
-            .iter()
-            .map(|source| format!("file '{}'", source.path.display()))
-            .collect::<Vec<_>>()
-            .join("\n");
+        let content = catfile_content(&self.plan, self.trims.get().unwrap_or(&HashMap::new()));
 
         file.write_all(content.as_bytes())
             .await
@@ -191,21 +1169,171 @@ impl Process {
     }
 
     #[instrument(level = Level::INFO)]
-    async fn get_expected_output_seconds(&self) -> Result<f64, ExecuteError> {
-        self.send(ExecuteProgressPayload::Phase {
-            phase: "Calculating total duration".to_string(),
-        })
+    /// Where the finished target's sources meet, in seconds from the start, with a description of each
+    async fn find_joins(&self) -> Result<Vec<(f64, String)>, ExecuteError> {
+        let sources = &self.plan.sources;
+        let mut joins = vec![(0.0, "the start".to_string())];
+        let mut seconds = 0.0;
+        for (i, source) in sources.iter().enumerate() {
+            seconds += match self.trims.get().and_then(|trims| trims.get(&source.leaf)) {
+                Some(trim) => trim.end - trim.start,
+                None => probe::duration(source, self.cancellation_token.child_token())
+                    .await?
+                    .as_secs_f64(),
+            };
+            joins.push((
+                seconds,
+                match sources.get(i + 1) {
+                    Some(next) => format!("the join between {} and {}", source.leaf, next.leaf),
+                    None => "the end".to_string(),
+                },
+            ));
+        }
+        Ok(joins)
+    }
+
+    /// Black video and silence in the finished target that touch the start, the end, or a join between sources
+    async fn find_gaps(&self) -> Result<Vec<String>, ExecuteError> {
+        let joins = self.find_joins().await?;
+        let duration = joins
+            .last()
+            .map(|(seconds, _)| *seconds)
+            .unwrap_or_default();
+        let target = self.plan.target_path.path.clone();
+        let has_audio = probe::has_audio(target.clone(), self.cancellation_token.child_token())
+            .await
+            .map_err(|e| ExecuteError::GapDetection { inner_error: e })?;
+        let gaps = probe::gaps(
+            target,
+            !self.plan.is_audio(),
+            has_audio,
+            duration,
+            self.cancellation_token.child_token(),
+        )
+        .await
+        .map_err(|e| ExecuteError::GapDetection { inner_error: e })?;
+
+        Ok(gaps
+            .iter()
+            .filter_map(|gap| {
+                let (_, join) = joins.iter().find(|(seconds, _)| {
+                    gap.start <= seconds + JOIN_TOLERANCE_SECONDS
+                        && gap.end >= seconds - JOIN_TOLERANCE_SECONDS
+                })?;
+                let kind = match gap.kind {
+                    probe::GapKind::Black => "black video",
+                    probe::GapKind::Silence => "silence",
+                };
+                Some(format!(
+                    "{:.1}s of {kind} at {join}, from {:.1}s to {:.1}s",
+                    gap.end - gap.start,
+                    gap.start,
+                    gap.end
+                ))
+            })
+            .collect())
+    }
+
+    /// Warns about black video and silence where the finished target's sources meet. The target is kept either way
+    async fn detect_gaps(&self) {
+        self.send(ExecuteProgressPayload::Phase {
+            phase: "Checking the joins for black video and silence".to_string(),
+        })
         .await;
 
-        let mut tasks = JoinSet::new();
+        match self.find_gaps().await {
+            Ok(gaps) => {
+                for message in gaps {
+                    self.send(ExecuteProgressPayload::Warning { message }).await;
+                }
+            }
+            Err(e) => {
+                tracing::warn!(error =% e, error_context =? e, "Failed to check for gaps");
+                self.send(ExecuteProgressPayload::Warning {
+                    message: e.to_string(),
+                })
+                .await;
+            }
+        }
+    }
 
-        for source in self.plan.sources.iter() {
-            let fut = libffmpeg::duration::get_duration(
-                source.path.clone(),
-                self.cancellation_token.child_token(),
+    /// Jumps in the finished target's timestamps, described by stream
+    async fn find_timestamp_jumps(&self) -> Result<Vec<String>, ExecuteError> {
+        let target = self.plan.target_path.path.clone();
+        let streams = probe::streams(target.clone(), self.cancellation_token.child_token())
+            .await
+            .map_err(|e| ExecuteError::TimestampCheck { inner_error: e })?;
+        let jumps = probe::timestamp_jumps(target, self.cancellation_token.child_token())
+            .await
+            .map_err(|e| ExecuteError::TimestampCheck { inner_error: e })?;
+
+        let mut messages = jumps
+            .iter()
+            .take(MAX_REPORTED_JUMPS)
+            .map(|jump| {
+                let stream = streams
+                    .get(jump.stream_index)
+                    .map(|(codec_type, _)| codec_type.as_str())
+                    .unwrap_or("unknown");
+                let direction = if jump.gap < 0.0 { "back" } else { "forward" };
+                format!(
+                    "The {stream} timestamps of stream {} jump {direction} {:.2}s at {:.2}s",
+                    jump.stream_index,
+                    jump.gap.abs(),
+                    jump.at
+                )
+            })
+            .collect::<Vec<_>>();
+        if jumps.len() > MAX_REPORTED_JUMPS {
+            messages.push(format!(
+                "{} more timestamp jumps",
+                jumps.len() - MAX_REPORTED_JUMPS
+            ));
+        }
+        if !jumps.is_empty() {
+            messages.push(
+                "Timestamp jumps can make the target stutter or play out of sync, `fix-timestamps` or the concat filter can help"
+                    .to_string(),
             );
+        }
+        Ok(messages)
+    }
+
+    /// Warns about discontinuities in a copied target's timestamps, which the concat demuxer leaves when sources don't line up
+    async fn check_timestamps(&self) {
+        self.send(ExecuteProgressPayload::Phase {
+            phase: "Checking the target's timestamps".to_string(),
+        })
+        .await;
+
+        match self.find_timestamp_jumps().await {
+            Ok(jumps) => {
+                for message in jumps {
+                    self.send(ExecuteProgressPayload::Warning { message }).await;
+                }
+            }
+            Err(e) => {
+                tracing::warn!(error =% e, error_context =? e, "Failed to check timestamps");
+                self.send(ExecuteProgressPayload::Warning {
+                    message: e.to_string(),
+                })
+                .await;
+            }
+        }
+    }
+
+    async fn get_expected_output_seconds(&self) -> Result<f64, ExecuteError> {
+        self.send(ExecuteProgressPayload::Phase {
+            phase: "Calculating total duration".to_string(),
+        })
+        .await;
+
+        let mut tasks = JoinSet::new();
 
-            tasks.spawn(fut);
+        for source in self.plan.sources.iter() {
+            let source = source.clone();
+            let ct = self.cancellation_token.child_token();
+            tasks.spawn(async move { probe::duration(&source, ct).await });
         }
 
         let mut total_seconds = 0.0f64;
@@ -233,31 +1361,11 @@ impl Process {
 
             tasks.spawn(
                 async move {
-                    let results = cmd::run("ffprobe", None, ct, |cmd| {
-                        cmd.arg("-v").arg("error");
-                        cmd.arg("-select_streams").arg("a");
-                        cmd.arg("-show_entries").arg("stream=codec_type");
-                        cmd.arg("-of").arg("default=noprint_wrappers=1:nokey=1");
-                        cmd.arg(source.path);
-                    })
-                    .await?;
-
-                    let has_audio = {
-                        let exited_normally = results
-                            .exit_code
-                            .map(|code| code.success)
-                            .unwrap_or_default();
-                        let has_stdout = !results.stdout_lines.is_empty();
-                        let stdout_has_text = !results
-                            .stdout_lines
-                            .into_iter()
-                            .next()
-                            .unwrap_or_default()
-                            .is_empty();
-
-                        exited_normally && has_stdout && stdout_has_text
-                    };
-
+                    // Image sequences never carry audio
+                    if source.framerate.is_some() {
+                        return Ok((source.leaf, false));
+                    }
+                    let has_audio = probe::has_audio(source.path, ct).await?;
                     Ok((source.leaf, has_audio))
                 }
                 .instrument(span.clone()),
@@ -289,14 +1397,270 @@ impl Process {
         Ok(map)
     }
 
+    async fn get_source_resolutions(&self) -> Result<Vec<(u32, u32)>, ExecuteError> {
+        self.send(ExecuteProgressPayload::Phase {
+            phase: "Detecting resolutions".to_string(),
+        })
+        .await;
+
+        let mut tasks: JoinSet<Result<(u32, u32), CommandError>> = JoinSet::new();
+        let span = Span::current();
+
+        for source in self.plan.sources.iter() {
+            let source = source.clone();
+            let ct = self.cancellation_token.child_token();
+
+            tasks.spawn(
+                async move {
+                    let resolution = probe::resolution(source.path, ct).await?;
+
+                    Ok(resolution.unwrap_or_else(|| {
+                        tracing::warn!(source_name = source.leaf, fallback = ?limits::FALLBACK_RESOLUTION, "Unable to determine source resolution");
+                        limits::FALLBACK_RESOLUTION
+                    }))
+                }
+                .instrument(span.clone()),
+            );
+        }
+
+        let mut resolutions = Vec::new();
+        let mut errors = Vec::new();
+
+        while let Some(result) = tasks.join_next().await {
+            match result.expect("Failed to join task") {
+                Ok(resolution) => resolutions.push(resolution),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(ExecuteError::ResolutionFailures {
+                inner_errors: errors,
+            });
+        }
+
+        Ok(resolutions)
+    }
+
+    /// Matroska sources carrying attachments, usually fonts for their subtitles
+    async fn find_attachment_sources(&self) -> Result<Vec<PlanPath>, ExecuteError> {
+        let mut sources = vec![];
+        for source in self
+            .plan
+            .sources
+            .iter()
+            .filter(|source| source.is_matroska())
+        {
+            let streams =
+                probe::streams(source.path.clone(), self.cancellation_token.child_token())
+                    .await
+                    .map_err(|e| ExecuteError::StreamFailures {
+                        inner_errors: vec![e],
+                    })?;
+            if streams
+                .iter()
+                .any(|(codec_type, _)| codec_type == "attachment")
+            {
+                sources.push(source.clone());
+            }
+        }
+        Ok(sources)
+    }
+
+    /// Warns about attachments that won't make it into the target, returning the sources whose attachments will
+    async fn carried_attachment_sources(&self) -> Result<Vec<PlanPath>, ExecuteError> {
+        let sources = self.find_attachment_sources().await?;
+        let reason = if self.plan.is_encode() {
+            "re-encoding only keeps video and audio"
+        } else if !self.plan.target_path.is_matroska() {
+            "only Matroska targets can hold them"
+        } else {
+            return Ok(sources);
+        };
+
+        for source in sources {
+            self.send(ExecuteProgressPayload::Warning {
+                message: format!(
+                    "{} has attachments like subtitle fonts that will be dropped, {reason}",
+                    source.leaf
+                ),
+            })
+            .await;
+        }
+        Ok(vec![])
+    }
+
+    /// Fails before ffmpeg starts if the target's container can't hold the streams it would be given
+    async fn check_target_codecs(&self, all_have_audio: bool) -> Result<(), ExecuteError> {
+        let plan = &self.plan;
+        // Audio targets are copied into the same format or encoded with the container's own codec
+        let Some(extension) = plan
+            .target_path
+            .path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+            .filter(|_| !plan.is_audio())
+        else {
+            return Ok(());
+        };
+
+        let streams = if plan.flags.contains(&Flag::ConcatFilter) {
+            let mut streams = vec![(
+                "video".to_string(),
+                self.options.video_encoding.codec_name().to_string(),
+            )];
+            if all_have_audio {
+                streams.push(("audio".to_string(), "aac".to_string()));
+            }
+            streams
+        } else {
+            let mut streams = vec![];
+            for source in plan.sources.iter() {
+                streams.extend(
+                    probe::streams(source.path.clone(), self.cancellation_token.child_token())
+                        .await
+                        .map_err(|e| ExecuteError::StreamFailures {
+                            inner_errors: vec![e],
+                        })?,
+                );
+            }
+            streams
+        };
+
+        match container::unsupported_stream(&extension, streams.iter()) {
+            Some((codec_type, codec_name)) => Err(ExecuteError::UnsupportedCodec {
+                extension,
+                codec_type: codec_type.clone(),
+                codec_name: codec_name.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Reserves this plan's share of the `--max-memory` budget, if there is one
+    async fn reserve_memory(&self) -> Result<Option<SemaphorePermit<'static>>, ExecuteError> {
+        let Some((budget, budget_mib)) = limits::memory_budget() else {
+            return Ok(None);
+        };
+
+        let resolutions = self.get_source_resolutions().await?;
+        let estimate_mib = limits::estimate_filter_memory_mib(&resolutions);
+
+        tracing::info!(
+            estimate_mib = estimate_mib,
+            budget_mib = budget_mib,
+            "Estimated filter_complex memory usage"
+        );
+
+        // Plans bigger than the whole budget still get to run, just on their own
+        if estimate_mib > budget_mib {
+            self.send(ExecuteProgressPayload::Warning {
+                message: format!(
+                    "Estimated memory usage of {estimate_mib} MiB exceeds --max-memory, running alone"
+                ),
+            })
+            .await;
+        }
+        let permits = estimate_mib.clamp(1, budget_mib);
+
+        if let Ok(permit) = budget.try_acquire_many(permits) {
+            return Ok(Some(permit));
+        }
+
+        self.send(ExecuteProgressPayload::Queued {
+            reason: "Waiting for memory budget".to_string(),
+        })
+        .await;
+
+        match budget
+            .acquire_many(permits)
+            .with_cancellation_token(&self.cancellation_token)
+            .await
+        {
+            Some(permit) => Ok(Some(
+                permit.expect("Memory budget semaphore is never closed"),
+            )),
+            None => Err(ExecuteError::Cancelled),
+        }
+    }
+
     #[instrument(level = Level::INFO)]
     async fn execute(self: Arc<Self>, catfile_path: PathBuf) -> Result<CommandExit, ExecuteError> {
         let plan = self.plan.clone();
 
         let source_has_audio = self.get_source_has_audio().await?;
+        let attachment_sources = self.carried_attachment_sources().await?;
 
         let total_seconds = self.get_expected_output_seconds().await?;
 
+        let trims = self.trims.get().cloned().unwrap_or_default();
+        let total_seconds = total_seconds - trims.values().map(Trim::trimmed).sum::<f64>();
+
+        let mut placeholders = find_placeholders(
+            &plan,
+            self.options.audio_placeholder.as_ref(),
+            self.cancellation_token.child_token(),
+        )
+        .await?;
+        // Placeholders last as long as what's left of the audio
+        if let Some(placeholders) = &mut placeholders {
+            for (source_name, seconds) in placeholders.seconds.iter_mut() {
+                if let Some(trim) = trims.get(source_name) {
+                    *seconds = trim.end - trim.start;
+                }
+            }
+        }
+        if let Some(placeholders) = &placeholders {
+            for source_name in placeholders.seconds.keys() {
+                self.send(ExecuteProgressPayload::Warning {
+                    message: format!("{source_name} has no video, using a placeholder"),
+                })
+                .await;
+            }
+        }
+
+        let color_conversions = if plan.flags.contains(&Flag::ConcatFilter) {
+            find_color_conversions(&plan, self.cancellation_token.child_token()).await?
+        } else {
+            HashMap::new()
+        };
+        for (source_name, standard) in color_conversions.iter() {
+            self.send(ExecuteProgressPayload::Warning {
+                message: format!(
+                    "{source_name} is {standard}, converting it to {}",
+                    color::NORMALIZED
+                ),
+            })
+            .await;
+        }
+
+        let audio_normalization = find_audio_normalization(
+            &plan,
+            self.options
+                .channel_layout
+                .as_deref()
+                .unwrap_or(DEFAULT_CHANNEL_LAYOUT),
+            self.options.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE),
+            self.cancellation_token.child_token(),
+        )
+        .await?;
+        if let Some(channel_layout) = &audio_normalization.channel_layout {
+            self.send(ExecuteProgressPayload::Warning {
+                message: format!(
+                    "Sources have mixed channel layouts, mixing them to {channel_layout}"
+                ),
+            })
+            .await;
+        }
+        if let Some(sample_rate) = audio_normalization.sample_rate {
+            self.send(ExecuteProgressPayload::Warning {
+                message: format!(
+                    "Sources have mixed sample rates, resampling them to {sample_rate} Hz"
+                ),
+            })
+            .await;
+        }
+
         let all_have_audio = plan
             .sources
             .iter()
@@ -308,19 +1672,40 @@ impl Process {
             .copied()
             .any(|flag| flag == Flag::ConcatFilter);
 
+        // Encoding speed depends on the resolution, a copy's only on the disk
+        let resolution = if plan.is_encode() {
+            first_resolution(&plan, self.cancellation_token.child_token()).await?
+        } else {
+            None
+        };
+
         self.send(ExecuteProgressPayload::Info {
             source_count: plan.sources.len(),
             total_duration_seconds: total_seconds,
             has_audio: all_have_audio,
-            mode: if using_filter_complex {
-                "filter_complex".to_string()
-            } else {
-                "concat".to_string()
-            },
+            mode: plan.mode().to_string(),
+            resolution,
         })
         .await;
 
-        if using_filter_complex && !all_have_audio {
+        self.check_target_codecs(all_have_audio).await?;
+
+        // Audio alone needs next to nothing compared to decoding frames
+        let _memory_permit = if using_filter_complex && !plan.is_audio() {
+            self.reserve_memory().await?
+        } else {
+            None
+        };
+
+        if plan.is_encode() && plan.flags.contains(&Flag::KeepData) {
+            self.send(ExecuteProgressPayload::Warning {
+                message: "Data streams are only kept when copying, re-encoding drops them"
+                    .to_string(),
+            })
+            .await;
+        }
+
+        if using_filter_complex && !all_have_audio && !plan.is_audio() {
             let sources_with_audio = source_has_audio.values().filter(|&&v| v).count();
             self.send(ExecuteProgressPayload::Warning {
                 message: format!(
@@ -337,84 +1722,48 @@ impl Process {
         })
         .await;
 
-        let target_path = self.plan.target_path.path.display().to_string();
+        let progress_file_path = match self.options.progress_source {
+            ProgressSource::Pipe => None,
+            ProgressSource::File => Some(self.tmp_file_path("progress")),
+        };
+        let progress_arg = match &progress_file_path {
+            Some(path) => path.display().to_string(),
+            None => "pipe:1".to_string(),
+        };
+
+        let log_file_path = self.tmp_file_path("log");
+        let ffreport = ffreport(&log_file_path);
 
         let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let (command_tx, command_rx) = tokio::sync::oneshot::channel();
 
+        let hwaccel_decode = self.options.hwaccel_decode;
+        let frame_rate_conversion = self.options.frame_rate_conversion;
+        let mp4_layout = self.options.mp4_layout;
+        let video_encoding = self.options.video_encoding.clone();
         let process = ffmpeg_with_progress(tx, self.cancellation_token.child_token(), move |cmd| {
-            let flags = plan.flags;
-            let sources = plan.sources;
-            let catf = flags.iter().copied().any(|flag| flag == Flag::ConcatFilter);
-            if catf {
-                for source in sources.iter() {
-                    cmd.arg("-i").arg(&source.path);
-                }
-
-                let all_have_audio = sources
-                    .iter()
-                    .all(|source| source_has_audio.get(&source.leaf).copied().unwrap_or(false));
-
-                cmd.arg("-vsync").arg("cfr");
-                cmd.arg("-r").arg("30");
-
-                if all_have_audio {
-                    // All have audio - concat video and audio
-                    let input_list = (0..sources.len())
-                        .map(|i| format!("[{i}:v]fps=30,format=yuv420p[v{i}];"))
-                        .collect::<Vec<_>>()
-                        .join("");
-
-                    let audio_prep = (0..sources.len())
-                        .map(|i| format!("[{i}:a]anull[a{i}];"))
-                        .collect::<Vec<_>>()
-                        .join("");
-
-                    let video_directives = (0..sources.len())
-                        .map(|i| format!("[v{i}][a{i}]"))
-                        .collect::<Vec<_>>()
-                        .join("");
-
-                    let opts = format!("concat=n={}:v=1:a=1[outv][outa]", sources.len());
-                    let filter_complex =
-                        format!("{input_list}{audio_prep}{video_directives}{opts}");
-
-                    cmd.arg("-filter_complex").arg(filter_complex);
-                    cmd.arg("-map").arg("[outv]");
-                    cmd.arg("-map").arg("[outa]");
-                    cmd.arg("-c:a").arg("aac");
-                    cmd.arg("-b:a").arg("128k");
-                } else {
-                    // Not all have audio - video only
-                    let input_list = (0..sources.len())
-                        .map(|i| format!("[{i}:v]fps=30,format=yuv420p[v{i}];"))
-                        .collect::<Vec<_>>()
-                        .join("");
-
-                    let video_directives = (0..sources.len())
-                        .map(|i| format!("[v{i}]"))
-                        .collect::<Vec<_>>()
-                        .join("");
-
-                    let opts = format!("concat=n={}:v=1:a=0[outv]", sources.len());
-                    let filter_complex = format!("{input_list}{video_directives}{opts}");
-
-                    cmd.arg("-filter_complex").arg(filter_complex);
-                    cmd.arg("-map").arg("[outv]");
-                }
+            env::apply_wrappers(cmd);
+            limits::apply_priority(cmd);
+            cmd.env("FFREPORT", ffreport);
+            configure_ffmpeg(
+                cmd,
+                &plan,
+                &catfile_path,
+                &progress_arg,
+                &source_has_audio,
+                placeholders.as_ref(),
+                &trims,
+                &attachment_sources,
+                &color_conversions,
+                &audio_normalization,
+                hwaccel_decode,
+                frame_rate_conversion,
+                mp4_layout,
+                &video_encoding,
+                total_seconds,
+            );
 
-                cmd.arg("-c:v").arg("libx264");
-                cmd.arg("-preset").arg("medium");
-                cmd.arg("-crf").arg("23");
-                cmd.arg("-progress").arg("pipe:1");
-            } else {
-                cmd.arg("-f").arg("concat");
-                cmd.arg("-safe").arg("0");
-                cmd.arg("-i").arg(catfile_path);
-                cmd.arg("-progress").arg("pipe:1");
-                cmd.arg("-c").arg("copy");
-            }
-            cmd.arg(target_path);
-            cmd.arg("-y");
+            let _ = command_tx.send(render_command(cmd.as_std()));
         });
 
         let monitor_token = self.cancellation_token.child_token();
@@ -446,6 +1795,54 @@ impl Process {
             );
         }
 
+        /* command line task */
+        {
+            let this = this.clone();
+            tasks.spawn(
+                async move {
+                    // Sender is dropped without sending if ffmpeg never gets spawned
+                    if let Ok(command_line) = command_rx.await {
+                        tracing::info!(command_line = command_line, "Spawning ffmpeg");
+                        this.send(ExecuteProgressPayload::Command { command_line })
+                            .await;
+                    }
+                }
+                .instrument(span.clone()),
+            );
+        }
+
+        /* progress file task */
+        if let Some(progress_file_path) = progress_file_path {
+            let this = this.clone();
+            let monitor_token = monitor_token.clone();
+            tasks.spawn(
+                async move {
+                    this.tail_progress_file(progress_file_path, total_seconds, monitor_token)
+                        .await
+                }
+                .instrument(span.clone()),
+            );
+        }
+
+        /* output size task */
+        {
+            let this = this.clone();
+            let monitor_token = monitor_token.clone();
+            tasks.spawn(
+                async move { this.watch_output_size(monitor_token).await }.instrument(span.clone()),
+            );
+        }
+
+        /* log file task */
+        {
+            let this = this.clone();
+            let monitor_token = monitor_token.clone();
+            tasks.spawn(
+                async move { this.tail_log_file(log_file_path, monitor_token).await }
+                    .instrument(span.clone()),
+            );
+        }
+
         let result = process.await;
         monitor_token.cancel();
 
@@ -453,24 +1850,804 @@ impl Process {
 
         Ok(result?)
     }
+
+    /// Follows the `-progress` output ffmpeg writes to `path` until ffmpeg reports the end, or `token` is cancelled
+    #[instrument(level = Level::INFO, skip(self, token))]
+    async fn tail_progress_file(
+        &self,
+        path: PathBuf,
+        total_seconds: f64,
+        token: CancellationToken,
+    ) {
+        follow_file(&path, &token, |line| async move {
+            match line.trim().split_once('=') {
+                Some(("out_time_us", value)) => {
+                    if let Ok(micros) = value.parse::<u64>() {
+                        self.send(ExecuteProgressPayload::Progress {
+                            total_seconds,
+                            current_seconds: Duration::from_micros(micros).as_secs_f64(),
+                        })
+                        .await;
+                    }
+                    true
+                }
+                Some(("progress", "end")) => false,
+                _ => true,
+            }
+        })
+        .await
+    }
+
+    /// Sends the target's size every [OUTPUT_SIZE_INTERVAL] until `token` is cancelled
+    #[instrument(level = Level::INFO, skip(self, token))]
+    async fn watch_output_size(&self, token: CancellationToken) {
+        // Sleeping first skips whatever was at the target before ffmpeg truncated it
+        while tokio::time::sleep(OUTPUT_SIZE_INTERVAL)
+            .with_cancellation_token(&token)
+            .await
+            .is_some()
+        {
+            let Ok(meta) = tokio::fs::metadata(&self.plan.target_path.path).await else {
+                continue;
+            };
+            // Sent even when unchanged, a target that stops growing is what a stalled encode looks like
+            self.send(ExecuteProgressPayload::OutputSize { bytes: meta.len() })
+                .await;
+        }
+    }
+
+    /// Forwards each line of ffmpeg's log at `path` until `token` is cancelled
+    #[instrument(level = Level::INFO, skip(self, token))]
+    async fn tail_log_file(&self, path: PathBuf, token: CancellationToken) {
+        follow_file(&path, &token, |line| async move {
+            // ffmpeg redraws its stats line with carriage returns
+            if let Some(line) = line.rsplit('\r').next()
+                && !line.trim().is_empty()
+            {
+                if let Some((fps, speed)) = parse_stats(line) {
+                    self.send(ExecuteProgressPayload::Stats { fps, speed })
+                        .await;
+                }
+                self.send(ExecuteProgressPayload::Log {
+                    line: line.to_string(),
+                })
+                .await;
+            }
+            true
+        })
+        .await
+    }
+}
+
+/// Frames and media seconds per wall clock second from a stats line like
+/// `frame= 1234 fps= 71 q=28.0 size= 1024kB time=00:00:41.00 bitrate= 204.6kbits/s speed=2.3x`
+fn parse_stats(line: &str) -> Option<(Option<f64>, Option<f64>)> {
+    let value = |key: &str| {
+        let (_, rest) = line.split_once(key)?;
+        rest.split_whitespace().next()
+    };
+    // Only the stats line has a speed, and it's N/A until ffmpeg has a few frames to go on
+    let speed = value("speed=")?;
+    let fps = value("fps=").and_then(|fps| fps.parse::<f64>().ok());
+    let speed = speed.trim_end_matches('x').parse::<f64>().ok();
+    Some((fps, speed))
+}
+
+/// Calls `on_line` with each line written to `path` until it returns false, or `token` is cancelled.
+/// Waits for `path` to be created, and reads whatever is left in it once cancelled
+async fn follow_file<F, Fut>(path: &Path, token: &CancellationToken, mut on_line: F)
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let file = loop {
+        match tokio::fs::File::open(path).await {
+            Ok(file) => break file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if tokio::time::sleep(FOLLOW_FILE_POLL_INTERVAL)
+                    .with_cancellation_token(token)
+                    .await
+                    .is_none()
+                {
+                    return;
+                }
+            }
+            Err(e) => {
+                tracing::error!(path =% path.display(), error =% e, error_context =? e, "Failed to open followed file");
+                return;
+            }
+        }
+    };
+
+    let mut reader = tokio::io::BufReader::new(file);
+    let mut line = String::new();
+
+    loop {
+        let read = match reader.read_line(&mut line).await {
+            Ok(read) => read,
+            Err(e) => {
+                tracing::error!(path =% path.display(), error =% e, error_context =? e, "Failed to read followed file");
+                return;
+            }
+        };
+
+        // Nothing new, or the writer is partway through a line
+        if read == 0 || !line.ends_with('\n') {
+            if token.is_cancelled() {
+                if !line.is_empty() {
+                    on_line(line.trim_end().to_string()).await;
+                }
+                return;
+            }
+
+            // Cancellation falls through to one last read of the rest of the file
+            let _ = tokio::time::sleep(FOLLOW_FILE_POLL_INTERVAL)
+                .with_cancellation_token(token)
+                .await;
+            continue;
+        }
+
+        if !on_line(line.trim_end().to_string()).await {
+            return;
+        }
+
+        line.clear();
+    }
+}
+
+/// Value for ffmpeg's `FFREPORT` environment variable writing its log to `path`
+fn ffreport(path: &Path) -> String {
+    let escaped = path
+        .display()
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace(':', "\\:");
+    format!("file={escaped}:level={FFREPORT_LEVEL}")
+}
+
+/// Contents of the concat demuxer file listing `plan`'s sources, cut to `trims` when copying
+pub fn catfile_content(plan: &Plan, trims: &HashMap<String, Trim>) -> String {
+    plan.sources
+        .iter()
+        // Quoted, so Windows' backslashes are taken literally, with any quotes in the path escaped
+        .map(|source| {
+            let mut entry = format!(
+                "file '{}'",
+                source.path.display().to_string().replace('\'', r"'\''")
+            );
+            if let Some(trim) = trims.get(&source.leaf) {
+                if trim.start > 0.0 {
+                    entry.push_str(&format!("\ninpoint {}", trim.start));
+                }
+                if trim.end < trim.duration {
+                    entry.push_str(&format!("\noutpoint {}", trim.end));
+                }
+            }
+            entry
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Fades the `label` output of `filter_complex` in and out with `filter`, returning the label to map
+fn fade(
+    filter_complex: &mut String,
+    seconds: Option<f64>,
+    total_seconds: f64,
+    label: &str,
+    filter: &str,
+) -> String {
+    let Some(seconds) = seconds else {
+        return format!("[{label}]");
+    };
+
+    let out_start = (total_seconds - seconds).max(0.0);
+    filter_complex.push_str(&format!(
+        ";[{label}]{filter}=t=in:st=0:d={seconds},{filter}=t=out:st={out_start}:d={seconds}[{label}_faded]"
+    ));
+    format!("[{label}_faded]")
+}
+
+/// Adds the arguments to stitch `plan` to an ffmpeg `cmd`
+#[allow(clippy::too_many_arguments)]
+pub fn configure_ffmpeg(
+    cmd: &mut tokio::process::Command,
+    plan: &Plan,
+    catfile_path: &Path,
+    progress_arg: &str,
+    source_has_audio: &HashMap<String, bool>,
+    placeholders: Option<&Placeholders>,
+    trims: &HashMap<String, Trim>,
+    attachment_sources: &[PlanPath],
+    color_conversions: &HashMap<String, &'static str>,
+    audio_normalization: &AudioNormalization,
+    hwaccel_decode: Option<HwaccelDecode>,
+    frame_rate_conversion: FrameRateConversion,
+    mp4_layout: Mp4Layout,
+    video_encoding: &VideoEncoding,
+    total_seconds: f64,
+) {
+    let flags = &plan.flags;
+    let sources = &plan.sources;
+    let catf = flags.iter().copied().any(|flag| flag == Flag::ConcatFilter);
+    let audio_prep = || {
+        let audio_filter = audio_normalization.filter();
+        (0..sources.len())
+            .map(|i| format!("[{i}:a]{audio_filter}[a{i}];"))
+            .collect::<Vec<_>>()
+            .join("")
+    };
+    let trim = |cmd: &mut tokio::process::Command, source: &PlanPath| {
+        if let Some(trim) = trims.get(&source.leaf) {
+            cmd.arg("-ss").arg(trim.start.to_string());
+            cmd.arg("-t").arg((trim.end - trim.start).to_string());
+        }
+    };
+    if plan.is_audio() {
+        if catf {
+            for source in sources.iter() {
+                trim(cmd, source);
+                cmd.arg("-i").arg(&source.path);
+            }
+
+            let audio_directives = (0..sources.len())
+                .map(|i| format!("[a{i}]"))
+                .collect::<Vec<_>>()
+                .join("");
+            let mut filter_complex = format!(
+                "{}{audio_directives}concat=n={}:v=0:a=1[outa]",
+                audio_prep(),
+                sources.len()
+            );
+            let outa = fade(
+                &mut filter_complex,
+                plan.fade,
+                total_seconds,
+                "outa",
+                "afade",
+            );
+
+            cmd.arg("-filter_complex").arg(filter_complex);
+            cmd.arg("-map").arg(outa);
+        } else {
+            cmd.arg("-f").arg("concat");
+            cmd.arg("-safe").arg("0");
+            cmd.arg("-i").arg(catfile_path);
+            // Cover art would otherwise come through as a video stream
+            cmd.arg("-vn");
+            let extension = |path: &Path| {
+                path.extension()
+                    .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            };
+            // Sources already in the target's format are copied, anything else is encoded with the container's default codec
+            if sources
+                .iter()
+                .all(|source| extension(&source.path) == extension(&plan.target_path.path))
+            {
+                cmd.arg("-c:a").arg("copy");
+            }
+        }
+        cmd.arg("-progress").arg(progress_arg);
+    } else if catf {
+        for source in sources.iter() {
+            if let Some(framerate) = source.framerate {
+                cmd.arg("-framerate").arg(framerate.to_string());
+            } else if let Some(hwaccel_decode) = hwaccel_decode {
+                // Decoded frames come back to system memory, so the filters run as they would without it
+                cmd.arg("-hwaccel").arg(hwaccel_decode.as_arg());
+            }
+            trim(cmd, source);
+            cmd.arg("-i").arg(&source.path);
+        }
+
+        // Each source without video gets an input of its own after the sources, lasting as long as its audio
+        let mut video_inputs = (0..sources.len()).collect::<Vec<_>>();
+        if let Some(placeholders) = placeholders {
+            let (width, height) = placeholders.resolution;
+            let mut next_input = sources.len();
+            for (i, source) in sources.iter().enumerate() {
+                let Some(seconds) = placeholders.seconds.get(&source.leaf) else {
+                    continue;
+                };
+                match &placeholders.placeholder {
+                    AudioPlaceholder::Black => {
+                        cmd.arg("-f").arg("lavfi");
+                        cmd.arg("-t").arg(seconds.to_string());
+                        cmd.arg("-i")
+                            .arg(format!("color=c=black:s={width}x{height}:r=30"));
+                    }
+                    AudioPlaceholder::Image(image) => {
+                        cmd.arg("-loop").arg("1");
+                        cmd.arg("-framerate").arg("30");
+                        cmd.arg("-t").arg(seconds.to_string());
+                        cmd.arg("-i").arg(image);
+                    }
+                }
+                video_inputs[i] = next_input;
+                next_input += 1;
+            }
+        }
+        let video_prep = |i: usize| match placeholders {
+            Some(Placeholders {
+                resolution: (width, height),
+                ..
+            }) if video_inputs[i] != i => format!(
+                "[{}:v]scale={width}:{height}:force_original_aspect_ratio=decrease,pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps=30,format=yuv420p[v{i}];",
+                video_inputs[i]
+            ),
+            _ => format!(
+                "[{}]{}{},format=yuv420p[v{i}];",
+                sources[i].video_specifier(i),
+                color_conversions
+                    .get(&sources[i].leaf)
+                    .map(|standard| color::conversion_filter(standard))
+                    .unwrap_or_default(),
+                frame_rate_conversion.filter()
+            ),
+        };
+
+        let all_have_audio = sources
+            .iter()
+            .all(|source| source_has_audio.get(&source.leaf).copied().unwrap_or(false));
+
+        cmd.arg("-vsync").arg("cfr");
+        cmd.arg("-r").arg("30");
+
+        if all_have_audio {
+            // All have audio - concat video and audio
+            let input_list = (0..sources.len())
+                .map(video_prep)
+                .collect::<Vec<_>>()
+                .join("");
+
+            let audio_prep = audio_prep();
+
+            let video_directives = (0..sources.len())
+                .map(|i| format!("[v{i}][a{i}]"))
+                .collect::<Vec<_>>()
+                .join("");
+
+            let opts = format!("concat=n={}:v=1:a=1[outv][outa]", sources.len());
+            let mut filter_complex = format!("{input_list}{audio_prep}{video_directives}{opts}");
+            let outv = fade(
+                &mut filter_complex,
+                plan.fade,
+                total_seconds,
+                "outv",
+                "fade",
+            );
+            let outa = fade(
+                &mut filter_complex,
+                plan.fade,
+                total_seconds,
+                "outa",
+                "afade",
+            );
+
+            cmd.arg("-filter_complex").arg(filter_complex);
+            cmd.arg("-map").arg(outv);
+            cmd.arg("-map").arg(outa);
+            cmd.arg("-c:a").arg("aac");
+            cmd.arg("-b:a").arg("128k");
+        } else {
+            // Not all have audio - video only
+            let input_list = (0..sources.len())
+                .map(video_prep)
+                .collect::<Vec<_>>()
+                .join("");
+
+            let video_directives = (0..sources.len())
+                .map(|i| format!("[v{i}]"))
+                .collect::<Vec<_>>()
+                .join("");
+
+            let opts = format!("concat=n={}:v=1:a=0[outv]", sources.len());
+            let mut filter_complex = format!("{input_list}{video_directives}{opts}");
+            let outv = fade(
+                &mut filter_complex,
+                plan.fade,
+                total_seconds,
+                "outv",
+                "fade",
+            );
+
+            cmd.arg("-filter_complex").arg(filter_complex);
+            cmd.arg("-map").arg(outv);
+        }
+
+        video_encoding.configure(cmd);
+        if !color_conversions.is_empty() {
+            color::tag_output(cmd);
+        }
+        cmd.arg("-progress").arg(progress_arg);
+    } else if plan.is_concat_protocol() {
+        let input = sources
+            .iter()
+            .map(|source| source.path.display().to_string())
+            .collect::<Vec<_>>()
+            .join("|");
+        if Flag::gen_pts(flags) {
+            cmd.arg("-fflags").arg("+genpts");
+        }
+        cmd.arg("-i").arg(format!("concat:{input}"));
+        if let Some(first) = sources.first().filter(|first| first.video_stream.is_some()) {
+            cmd.arg("-map").arg(first.video_specifier(0));
+            cmd.arg("-map").arg("0:a?");
+        }
+        cmd.arg("-progress").arg(progress_arg);
+        cmd.arg("-c").arg("copy");
+        if Flag::make_zero(flags) {
+            cmd.arg("-avoid_negative_ts").arg("make_zero");
+        }
+        // TS carries AAC with ADTS headers, which MP4 style containers don't take
+        if !plan.target_path.is_mpeg_ts() {
+            cmd.arg("-bsf:a").arg("aac_adtstoasc");
+        }
+    } else if plan.is_remux() {
+        if Flag::gen_pts(flags) {
+            cmd.arg("-fflags").arg("+genpts");
+        }
+        cmd.arg("-i").arg(&sources[0].path);
+        // Only streams every container can hold, subtitles and data streams often can't be copied across
+        cmd.arg("-map").arg(match sources[0].video_stream {
+            Some(_) => sources[0].video_specifier(0),
+            None => "0:v?".to_string(),
+        });
+        cmd.arg("-map").arg("0:a?");
+        // Matroska holds anything, including subtitles and the fonts they're drawn with
+        if plan.target_path.is_matroska() {
+            cmd.arg("-map").arg("0:s?");
+            cmd.arg("-map").arg("0:t?");
+        }
+        if flags.contains(&Flag::KeepData) {
+            cmd.arg("-map").arg("0:d?");
+            cmd.arg("-copy_unknown");
+        }
+        cmd.arg("-progress").arg(progress_arg);
+        cmd.arg("-c").arg("copy");
+        if Flag::make_zero(flags) {
+            cmd.arg("-avoid_negative_ts").arg("make_zero");
+        }
+    } else {
+        if Flag::gen_pts(flags) {
+            cmd.arg("-fflags").arg("+genpts");
+        }
+        cmd.arg("-f").arg("concat");
+        cmd.arg("-safe").arg("0");
+        cmd.arg("-i").arg(catfile_path);
+        // The concat demuxer doesn't pass attachments through, so they're taken from the sources themselves
+        for source in attachment_sources.iter() {
+            cmd.arg("-i").arg(&source.path);
+        }
+        // Mapping anything stops ffmpeg picking streams itself, so everything wanted is mapped
+        let keep_data = flags.contains(&Flag::KeepData);
+        // Sources using different video streams are switched to the concat filter, so they all share the first's
+        let video_stream = sources.first().filter(|first| first.video_stream.is_some());
+        if keep_data || !attachment_sources.is_empty() || video_stream.is_some() {
+            cmd.arg("-map").arg(match video_stream {
+                Some(first) => first.video_specifier(0),
+                None => "0:v?".to_string(),
+            });
+            cmd.arg("-map").arg("0:a?");
+            cmd.arg("-map").arg("0:s?");
+            for i in 1..=attachment_sources.len() {
+                cmd.arg("-map").arg(format!("{i}:t"));
+            }
+        }
+        if keep_data {
+            cmd.arg("-map").arg("0:d?");
+            cmd.arg("-copy_unknown");
+        }
+        cmd.arg("-progress").arg(progress_arg);
+        cmd.arg("-c").arg("copy");
+        if Flag::make_zero(flags) {
+            cmd.arg("-avoid_negative_ts").arg("make_zero");
+        }
+    }
+    if plan.target_path.is_mp4()
+        && let Some(movflags) = mp4_layout.movflags()
+    {
+        cmd.arg("-movflags").arg(movflags);
+    }
+    cmd.arg(&plan.target_path.path);
+    cmd.arg("-y");
 }
+
+/// Renders `cmd` as a line that can be pasted into a shell
+pub fn render_command(cmd: &std::process::Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `render_command` without the program, for when it's run some other way
+pub fn render_args(cmd: &std::process::Command) -> String {
+    cmd.get_args()
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes `arg` for a POSIX shell, when it needs to be
+pub fn shell_quote(arg: &str) -> String {
+    let is_safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+
+    if is_safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
 pub async fn execute_plan(
     plan: Plan,
     tx: tokio::sync::mpsc::Sender<ExecuteProgress>,
     tmp_root: PathBuf,
+    options: ExecuteOptions,
     cancellation_token: CancellationToken,
-) {
-    let process = Arc::new(Process::new(plan, tx, tmp_root, cancellation_token));
+) -> ExecuteResult {
+    let process = Arc::new(Process::new(
+        plan,
+        tx,
+        tmp_root,
+        options,
+        cancellation_token,
+    ));
 
-    match _execute_plan(process.clone()).await {
-        Ok(result) => process.send(ExecuteProgressPayload::Finished(result)).await,
-        Err(err) => process.send(ExecuteProgressPayload::Failed(err)).await,
-    };
+    let result = _execute_plan(process.clone()).await.and_then(|exit| {
+        if exit.exit_code.as_ref().is_some_and(|code| code.success) {
+            Ok(exit)
+        } else {
+            Err(ExecuteError::Unsuccessful)
+        }
+    });
+    // ffmpeg killed part way through exits unsuccessfully, which isn't a failure when it was asked to stop
+    let result = result.map_err(|err| {
+        if process.cancellation_token.is_cancelled() {
+            ExecuteError::Cancelled
+        } else {
+            err
+        }
+    });
+
+    match result {
+        Ok(exit) => {
+            process.send(ExecuteProgressPayload::Finished(exit)).await;
+            Ok(())
+        }
+        Err(err) => {
+            process
+                .send(ExecuteProgressPayload::Failed(err.clone()))
+                .await;
+            Err(err)
+        }
+    }
+}
+
+/// Plans started together by [`execute_plans`]
+pub struct PlanExecutions {
+    /// Each plan's token by target name, cancelling one stops only that plan
+    pub tokens: HashMap<String, CancellationToken>,
+    /// Every plan's deliveries, ending once they have all settled
+    pub progress: ReceiverStream<ExecuteProgress>,
+}
+
+/// Starts every plan in the background with a child of `cancellation_token`, keeping intermediates in `tmp_root`
+pub fn execute_plans(
+    plans: Vec<Plan>,
+    tmp_root: PathBuf,
+    options: ExecuteOptions,
+    cancellation_token: CancellationToken,
+) -> PlanExecutions {
+    let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+    let mut executions = JoinSet::new();
+    let mut tokens = HashMap::new();
+    for plan in plans {
+        let plan_token = cancellation_token.child_token();
+        tokens.insert(plan.target_path.leaf.clone(), plan_token.clone());
+        executions.spawn(execute_plan(
+            plan,
+            tx.clone(),
+            tmp_root.clone(),
+            options.clone(),
+            plan_token,
+        ));
+    }
+
+    tokio::spawn(async move {
+        while let Some(result) = executions.join_next().await {
+            if let Err(join_error) = result {
+                tracing::error!(error =% join_error, error_context =? join_error, "Failed to join execution");
+            }
+        }
+    });
+
+    PlanExecutions {
+        tokens,
+        // Closes once every execution has dropped its sender
+        progress: ReceiverStream::new(rx),
+    }
 }
 
 #[instrument(level = Level::INFO)]
 async fn _execute_plan(process: Arc<Process>) -> Result<CommandExit, ExecuteError> {
     process.start().await;
+
+    // Intermediates take job slots of their own, so they're made before the plan takes one
+    let process = if process.plan.flags.contains(&Flag::Mezzanine) {
+        Arc::new(process.transcode_intermediates().await?)
+    } else {
+        process
+    };
+
+    let _permit = process.wait_for_slot().await?;
+
+    process.create_target_dir().await?;
+    process.find_trims().await?;
     let catfile_path = process.prepare_catfile().await?;
-    process.execute(catfile_path).await
+    let exit = process.clone().execute(catfile_path).await?;
+
+    let succeeded = exit.exit_code.as_ref().is_some_and(|code| code.success);
+    // Encoding gives the output fresh timestamps, copying carries over whatever the sources had
+    if succeeded && !process.plan.is_encode() {
+        process.check_timestamps().await;
+    }
+    if succeeded && process.options.detect_gaps {
+        process.detect_gaps().await;
+    }
+    Ok(exit)
+}
+
+/// Resolution, framerate, encoding and audio layout every intermediate shares, so they can be concat copied
+#[derive(Debug, Clone)]
+pub struct IntermediateFormat {
+    pub resolution: (u32, u32),
+    /// Sources without audio get silence when any other source has some
+    pub with_audio: bool,
+    pub frame_rate_conversion: FrameRateConversion,
+    pub video_encoding: VideoEncoding,
+}
+
+/// Width and height of the first of `plan`'s sources that has video
+pub async fn first_resolution(
+    plan: &Plan,
+    ct: CancellationToken,
+) -> Result<Option<(u32, u32)>, ExecuteError> {
+    for source in plan.sources.iter() {
+        let resolution = probe::resolution(source.path.clone(), ct.child_token())
+            .await
+            .map_err(|e| ExecuteError::ResolutionFailures {
+                inner_errors: vec![e],
+            })?;
+        if resolution.is_some() {
+            return Ok(resolution);
+        }
+    }
+    Ok(None)
+}
+
+/// The format a mezzanine plan's intermediates are transcoded to, everything is scaled to fit the first source with video
+pub async fn intermediate_format(
+    plan: &Plan,
+    source_has_audio: &HashMap<String, bool>,
+    frame_rate_conversion: FrameRateConversion,
+    video_encoding: VideoEncoding,
+    ct: CancellationToken,
+) -> Result<IntermediateFormat, ExecuteError> {
+    let resolution = first_resolution(plan, ct).await?;
+
+    Ok(IntermediateFormat {
+        resolution: resolution.unwrap_or(limits::FALLBACK_RESOLUTION),
+        with_audio: source_has_audio.values().any(|&has_audio| has_audio),
+        frame_rate_conversion,
+        video_encoding,
+    })
+}
+
+/// Names the cached intermediate of `source` after a SHA-256 of the settings it's transcoded with and its file's path,
+/// size and modification time. The metadata stands in for the file's content, hashing every source in full on every
+/// run would take about as long as reading it
+fn intermediate_key(
+    source: &PlanPath,
+    has_audio: bool,
+    format: &IntermediateFormat,
+    color_conversion: Option<&str>,
+) -> String {
+    let mut hasher = Sha256::new();
+
+    // The arguments cover the source's path and every encode setting. The output is left out, it's named after the key
+    let mut cmd = tokio::process::Command::new("ffmpeg");
+    configure_intermediate(
+        &mut cmd,
+        source,
+        has_audio,
+        format,
+        color_conversion,
+        Path::new(""),
+    );
+    for arg in cmd.as_std().get_args() {
+        hasher.update(arg.as_encoded_bytes());
+        hasher.update([0]);
+    }
+
+    // Image sequences have no file of their own, their directory changes as frames come and go
+    let file = match source.framerate {
+        Some(_) => {
+            hasher.update(source.count_frames().to_le_bytes());
+            source.path.parent()
+        }
+        None => Some(source.path.as_path()),
+    };
+    if let Some(meta) = file.and_then(|file| std::fs::metadata(file).ok()) {
+        hasher.update(meta.len().to_le_bytes());
+        if let Some(modified) = meta
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        {
+            hasher.update(modified.as_nanos().to_le_bytes());
+        }
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Adds the arguments to transcode `source` to an intermediate at `output` to an ffmpeg `cmd`.
+/// `color_conversion` is the standard `source` is converted from, when it doesn't match the others
+pub fn configure_intermediate(
+    cmd: &mut tokio::process::Command,
+    source: &PlanPath,
+    has_audio: bool,
+    format: &IntermediateFormat,
+    color_conversion: Option<&str>,
+    output: &Path,
+) {
+    let (width, height) = format.resolution;
+
+    if let Some(framerate) = source.framerate {
+        cmd.arg("-framerate").arg(framerate.to_string());
+    }
+    cmd.arg("-i").arg(&source.path);
+    if format.with_audio && !has_audio {
+        cmd.arg("-f").arg("lavfi");
+        cmd.arg("-i").arg("anullsrc=r=48000:cl=stereo");
+        cmd.arg("-shortest");
+    }
+
+    cmd.arg("-map").arg(source.video_specifier(0));
+    cmd.arg("-vf").arg(format!(
+        "{}scale={width}:{height}:force_original_aspect_ratio=decrease,pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1,{},format=yuv420p",
+        color_conversion
+            .map(color::conversion_filter)
+            .unwrap_or_default(),
+        format.frame_rate_conversion.filter()
+    ));
+    format.video_encoding.configure(cmd);
+    if color_conversion.is_some() {
+        color::tag_output(cmd);
+    }
+
+    if format.with_audio {
+        cmd.arg("-map")
+            .arg(if has_audio { "0:a:0" } else { "1:a:0" });
+        cmd.arg("-c:a").arg("aac");
+        cmd.arg("-b:a").arg("128k");
+        cmd.arg("-ar").arg("48000");
+        cmd.arg("-ac").arg("2");
+    } else {
+        cmd.arg("-an");
+    }
+
+    cmd.arg(output);
+    cmd.arg("-y");
 }
@@ -0,0 +1,46 @@
+//! Process exit codes, stable across releases so scripts can branch on why stitch failed
+
+use thiserror::Error;
+
+use crate::parse::ParseError;
+
+/// Anything without a code of its own
+pub const FAILURE: i32 = 1;
+// 2 is clap's, for invalid arguments
+/// A spec couldn't be read or parsed
+pub const SPEC: i32 = 3;
+/// A spec parsed, but a target or source in it isn't valid
+pub const VALIDATION: i32 = 4;
+/// At least one target failed
+pub const TARGETS_FAILED: i32 = 5;
+/// The run was cancelled with Ctrl+C before anything failed
+pub const INTERRUPTED: i32 = 130;
+
+/// A run that didn't finish every target
+#[derive(Debug, Error)]
+#[error("{failed} of {target_count} targets failed{}", if *cancelled == 0 { String::new() } else { format!(", {cancelled} cancelled") })]
+pub struct TargetsFailed {
+    pub failed: usize,
+    pub cancelled: usize,
+    pub target_count: usize,
+}
+
+/// The exit code for `error`, from the first error in its chain that has one
+pub fn code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<ParseError>() {
+            return match error {
+                ParseError::Validation { .. } => VALIDATION,
+                _ => SPEC,
+            };
+        }
+        if let Some(error) = cause.downcast_ref::<TargetsFailed>() {
+            return if error.failed == 0 {
+                INTERRUPTED
+            } else {
+                TARGETS_FAILED
+            };
+        }
+    }
+    FAILURE
+}
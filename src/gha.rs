@@ -0,0 +1,166 @@
+//! GitHub Actions workflow commands, so problems show up inline on the run's summary and in pull requests
+//!
+//! https://docs.github.com/en/actions/reference/workflow-commands-for-github-actions
+
+use std::{collections::HashMap, fmt::Display, path::Path, sync::OnceLock};
+
+use crate::{
+    execute::{ExecuteError, ExecuteProgress, ExecuteProgressPayload},
+    parse::{Lint, ParseError, locate},
+};
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Turns annotations on when `--gha` is given or stitch is running in a GitHub Actions job
+pub fn set(enabled: bool) {
+    let in_actions = std::env::var_os("GITHUB_ACTIONS").is_some_and(|value| value == "true");
+    let _ = ENABLED.set(enabled || in_actions);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.get().copied().unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Level {
+    Error,
+    Warning,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+fn escape_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(property: &str) -> String {
+    escape_data(property)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+/// Workflow commands are read from stdout
+fn annotate(level: Level, file: Option<&Path>, line: Option<usize>, title: &str, message: &str) {
+    let mut properties = Vec::new();
+    if let Some(file) = file {
+        properties.push(format!(
+            "file={}",
+            escape_property(&file.display().to_string())
+        ));
+    }
+    if let Some(line) = line {
+        properties.push(format!("line={line}"));
+    }
+    properties.push(format!("title={}", escape_property(title)));
+
+    println!(
+        "::{level} {}::{}",
+        properties.join(","),
+        escape_data(message)
+    );
+}
+
+fn lint_line(text: &str, lint: &Lint) -> Option<usize> {
+    match lint {
+        Lint::DuplicateFlag { target_name, .. } | Lint::CopyOfSource { target_name } => {
+            locate(text, target_name, None)
+        }
+        Lint::MixedIndentation { line_number, .. } => Some(*line_number),
+    }
+}
+
+/// Annotates lints found in `spec`, as warnings
+pub fn spec_lints(spec: &Path, lints: &[Lint]) {
+    let text = std::fs::read_to_string(spec).unwrap_or_default();
+    for lint in lints {
+        annotate(
+            Level::Warning,
+            Some(spec),
+            lint_line(&text, lint),
+            "Spec lint",
+            &lint.to_string(),
+        );
+    }
+}
+
+/// Annotates why `spec` failed to load, on the line each problem is on where it can be found
+pub fn spec_error(spec: &Path, error: &ParseError) {
+    let text = std::fs::read_to_string(spec).unwrap_or_default();
+    match error {
+        ParseError::Lines { errors } => {
+            for error in errors {
+                annotate(
+                    Level::Error,
+                    Some(spec),
+                    Some(error.line_number),
+                    "Invalid spec",
+                    &error.error.to_string(),
+                );
+            }
+        }
+        ParseError::Validation { errors } => {
+            for error in errors {
+                let (target_name, source_name) = error.subject();
+                annotate(
+                    Level::Error,
+                    Some(spec),
+                    locate(&text, target_name, source_name),
+                    "Validation failed",
+                    &error.to_string(),
+                );
+            }
+        }
+        ParseError::Lints { lints } => {
+            for lint in lints {
+                annotate(
+                    Level::Error,
+                    Some(spec),
+                    lint_line(&text, lint),
+                    "Spec lint",
+                    &lint.to_string(),
+                );
+            }
+        }
+        error => annotate(
+            Level::Error,
+            Some(spec),
+            None,
+            "Invalid spec",
+            &error.to_string(),
+        ),
+    }
+}
+
+/// Annotates each target that fails
+pub fn annotate_failures(
+    rx: tokio::sync::mpsc::Receiver<ExecuteProgress>,
+) -> tokio::sync::mpsc::Receiver<ExecuteProgress> {
+    let mut targets = HashMap::new();
+    crate::monitor::tap(rx, move |delivery| match &delivery.payload {
+        ExecuteProgressPayload::Start { target_name } => {
+            targets.insert(delivery.id, target_name.clone());
+        }
+        // Cancelling is asked for, it isn't a problem to point out
+        ExecuteProgressPayload::Failed(ExecuteError::Cancelled) => {}
+        ExecuteProgressPayload::Failed(error) => {
+            let target_name = targets.get(&delivery.id).map_or("target", String::as_str);
+            annotate(
+                Level::Error,
+                None,
+                None,
+                &format!("Failed to stitch {target_name}"),
+                &error.to_string(),
+            );
+        }
+        _ => {}
+    })
+}
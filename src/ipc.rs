@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::execute::ExecuteProgress;
+
+/// Deliveries buffered for each client before a slow one starts missing some
+#[cfg(unix)]
+const EVENT_BUFFER: usize = 1024;
+
+/// How long clients get to read what's left once the run is over
+#[cfg(unix)]
+const DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// A unix socket bound by [bind], waiting for [broadcast] to stream to it
+#[cfg(unix)]
+pub struct Socket {
+    path: PathBuf,
+    listener: tokio::net::UnixListener,
+}
+
+/// Binds a unix socket at `path` up front, so a bad path fails before any plan starts
+#[cfg(unix)]
+pub fn bind(path: PathBuf) -> anyhow::Result<Socket> {
+    use std::os::unix::fs::FileTypeExt;
+
+    use anyhow::Context;
+    use tokio::net::UnixListener;
+
+    // A socket left behind by a run that didn't exit cleanly would stop us binding
+    if let Ok(meta) = std::fs::symlink_metadata(&path) {
+        if !meta.file_type().is_socket() {
+            anyhow::bail!("{} exists and isn't a socket", path.display());
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    tracing::info!(path =% path.display(), "Streaming progress over unix socket");
+
+    Ok(Socket { path, listener })
+}
+
+/// Streams every delivery as newline delimited JSON to each client connected to `socket`, on the way to the
+/// returned receiver. The socket is removed once the channel closes and clients have drained
+#[cfg(unix)]
+pub fn broadcast(
+    socket: Socket,
+    rx: mpsc::Receiver<ExecuteProgress>,
+) -> (mpsc::Receiver<ExecuteProgress>, JoinHandle<()>) {
+    use tokio::{io::AsyncWriteExt, sync::broadcast, task::JoinSet};
+    use tokio_util::sync::CancellationToken;
+
+    let Socket { path, listener } = socket;
+    let (events, _) = broadcast::channel::<ExecuteProgress>(EVENT_BUFFER);
+    let accepting = CancellationToken::new();
+
+    let accept = {
+        let events = events.clone();
+        let accepting = accepting.clone();
+        tokio::spawn(async move {
+            let mut clients = JoinSet::new();
+            loop {
+                let stream = tokio::select! {
+                    _ = accepting.cancelled() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            tracing::warn!(error =% e, error_context =? e, "Failed to accept socket client");
+                            continue;
+                        }
+                    },
+                };
+
+                tracing::debug!("Socket client connected");
+                let mut events = events.subscribe();
+                clients.spawn(async move {
+                    let mut stream = stream;
+                    loop {
+                        let delivery = match events.recv().await {
+                            Ok(delivery) => delivery,
+                            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                                tracing::warn!(
+                                    missed = missed,
+                                    "Socket client fell behind, skipping deliveries"
+                                );
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+                        let Ok(mut line) = serde_json::to_vec(&delivery) else {
+                            continue;
+                        };
+                        line.push(b'\n');
+                        if stream.write_all(&line).await.is_err() {
+                            tracing::debug!("Socket client disconnected");
+                            break;
+                        }
+                    }
+                });
+            }
+            // Only the clients hold subscriptions now, so they finish once the sender is dropped
+            drop(events);
+            clients
+        })
+    };
+
+    let (forwarded, events) = crate::monitor::tap_with(rx, events, |events, delivery| {
+        // Nobody listening isn't an error
+        let _ = events.send(delivery.clone());
+    });
+
+    let handle = tokio::spawn(async move {
+        // Dropping the sender once the channel closes lets the clients finish
+        let _ = events.await;
+        accepting.cancel();
+        match accept.await {
+            Ok(mut clients) => {
+                if tokio::time::timeout(DRAIN_TIMEOUT, async {
+                    while clients.join_next().await.is_some() {}
+                })
+                .await
+                .is_err()
+                {
+                    tracing::warn!("Socket clients didn't drain in time");
+                }
+            }
+            Err(join_error) => {
+                tracing::error!(error =% join_error, error_context =? join_error, "Failed to join socket listener");
+            }
+        }
+
+        if let Err(e) = std::fs::remove_file(&path) {
+            tracing::warn!(path =% path.display(), error =% e, error_context =? e, "Failed to remove socket");
+        }
+    });
+
+    (forwarded, handle)
+}
+
+/// Can't be made off unix, [bind] always fails there
+#[cfg(not(unix))]
+pub enum Socket {}
+
+#[cfg(not(unix))]
+pub fn bind(_path: PathBuf) -> anyhow::Result<Socket> {
+    anyhow::bail!("--ipc-socket is only supported on unix")
+}
+
+#[cfg(not(unix))]
+pub fn broadcast(
+    socket: Socket,
+    _rx: mpsc::Receiver<ExecuteProgress>,
+) -> (mpsc::Receiver<ExecuteProgress>, JoinHandle<()>) {
+    match socket {}
+}
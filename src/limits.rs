@@ -1,4 +1,297 @@
-use tokio::sync::Semaphore;
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
 
-// TODO: Configurable?
-pub static LIMIT_PROCESSES: Semaphore = Semaphore::const_new(8);
+use tokio::sync::{Mutex, Semaphore};
+use tokio_util::{future::FutureExt, sync::CancellationToken};
+
+/// Used when the number of CPUs can't be determined
+pub const FALLBACK_PROCESS_LIMIT: usize = 8;
+
+/// Stream copies are bound by disk rather than CPU, more of them at once just thrash the disk
+pub const COPY_PROCESS_LIMIT: usize = 4;
+
+static LIMIT_PROCESSES: OnceLock<Semaphore> = OnceLock::new();
+
+/// Picks a process limit from the number of available CPUs, capped if every plan is a stream copy
+pub fn default_process_limit(all_copy: bool) -> usize {
+    let cpus = match std::thread::available_parallelism() {
+        Ok(cpus) => cpus.get(),
+        Err(e) => {
+            tracing::warn!(error =% e, error_context =? e, fallback = FALLBACK_PROCESS_LIMIT, "Failed to detect available parallelism");
+            FALLBACK_PROCESS_LIMIT
+        }
+    };
+
+    if all_copy {
+        cpus.min(COPY_PROCESS_LIMIT)
+    } else {
+        cpus
+    }
+}
+
+pub fn set_process_limit(limit: usize) {
+    tracing::info!(limit = limit, "Limiting to {} concurrent plans", limit);
+    LIMIT_PROCESSES.get_or_init(|| Semaphore::new(limit));
+}
+
+/// Gates how many plans may run their ffprobe/ffmpeg processes at once
+pub fn process_limit<'a>() -> &'a Semaphore {
+    LIMIT_PROCESSES.get_or_init(|| Semaphore::new(default_process_limit(false)))
+}
+
+/// Frames each filter_complex input is assumed to hold in decoder and filter queues
+const FILTER_BUFFERED_FRAMES: u64 = 16;
+
+/// Resolution assumed for inputs that couldn't be probed
+pub const FALLBACK_RESOLUTION: (u32, u32) = (1920, 1080);
+
+const MIB: u64 = 1024 * 1024;
+
+/// Memory budget shared by filter_complex plans, one permit per MiB
+static MEMORY_BUDGET: OnceLock<(Semaphore, u32)> = OnceLock::new();
+
+/// Parses a byte size like `512M`, `8G` or `1073741824`
+pub fn parse_byte_size(given: &str) -> Result<u64, String> {
+    let given = given.trim();
+    let split = given
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(given.len());
+    let (digits, suffix) = given.split_at(split);
+
+    let multiplier = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => MIB,
+        "G" | "GB" | "GIB" => 1024 * MIB,
+        "T" | "TB" | "TIB" => 1024 * 1024 * MIB,
+        other => return Err(format!("Unknown size suffix \"{other}\"")),
+    };
+
+    let value = digits
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid size \"{given}\": {e}"))?;
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size \"{given}\" is too large"))
+}
+
+/// Formats a byte count for humans, e.g. `1.5 GiB`
+pub fn format_byte_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+pub fn set_max_memory(bytes: u64) {
+    let mib = u32::try_from(bytes / MIB).unwrap_or(u32::MAX).max(1);
+    tracing::info!(
+        max_memory_mib = mib,
+        "Limiting filter_complex plans to an estimated {} MiB",
+        mib
+    );
+    MEMORY_BUDGET.get_or_init(|| (Semaphore::new(mib as usize), mib));
+}
+
+/// The memory budget semaphore and its size in MiB, if `--max-memory` was given
+pub fn memory_budget<'a>() -> Option<(&'a Semaphore, u32)> {
+    MEMORY_BUDGET
+        .get()
+        .map(|(semaphore, mib)| (semaphore, *mib))
+}
+
+/// Rough estimate of the memory a filter_complex plan needs, in MiB, from its input resolutions
+pub fn estimate_filter_memory_mib(resolutions: &[(u32, u32)]) -> u32 {
+    let bytes = resolutions
+        .iter()
+        .map(|(width, height)| {
+            // yuv420p is 1.5 bytes per pixel
+            (*width as u64) * (*height as u64) * 3 / 2 * FILTER_BUFFERED_FRAMES
+        })
+        .sum::<u64>();
+
+    u32::try_from(bytes.div_ceil(MIB)).unwrap_or(u32::MAX)
+}
+
+/// How often to re-check the load average while waiting for it to drop
+const LOAD_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Minimum time between launches while throttling on load, the load average lags behind new processes
+const LOAD_SETTLE_INTERVAL: Duration = Duration::from_secs(2);
+
+static MAX_LOAD: OnceLock<f64> = OnceLock::new();
+
+/// When the last plan was let through by [`wait_for_load`]
+static LOAD_GATE: Mutex<Option<Instant>> = Mutex::const_new(None);
+
+pub fn set_max_load(max_load: f64) {
+    if load_average().is_none() {
+        tracing::warn!(
+            max_load = max_load,
+            "Load average is unavailable on this platform, --max-load will be ignored"
+        );
+    }
+    MAX_LOAD.get_or_init(|| max_load);
+}
+
+/// The 1 minute load average, if the platform exposes one
+pub fn load_average() -> Option<f64> {
+    #[cfg(unix)]
+    {
+        let mut loads = [0f64; 3];
+        // SAFETY: `loads` has room for the 3 samples we ask for
+        let samples = unsafe { libc::getloadavg(loads.as_mut_ptr(), loads.len() as libc::c_int) };
+        (samples >= 1).then_some(loads[0])
+    }
+
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+pub fn is_overloaded() -> bool {
+    match (MAX_LOAD.get(), load_average()) {
+        (Some(max_load), Some(load)) => load >= *max_load,
+        _ => false,
+    }
+}
+
+/// Waits until the load average is below `--max-load`, letting one plan through at a time.
+/// Returns false if `token` is cancelled while waiting
+pub async fn wait_for_load(token: &CancellationToken) -> bool {
+    let Some(max_load) = MAX_LOAD.get().copied() else {
+        return true;
+    };
+
+    let Some(mut last_launch) = LOAD_GATE.lock().with_cancellation_token(token).await else {
+        return false;
+    };
+
+    loop {
+        if let Some(since_last_launch) = last_launch.map(|last| last.elapsed())
+            && since_last_launch < LOAD_SETTLE_INTERVAL
+            && tokio::time::sleep(LOAD_SETTLE_INTERVAL - since_last_launch)
+                .with_cancellation_token(token)
+                .await
+                .is_none()
+        {
+            return false;
+        }
+
+        let Some(load) = load_average() else {
+            break;
+        };
+
+        if load < max_load {
+            break;
+        }
+
+        tracing::debug!(
+            load = load,
+            max_load = max_load,
+            "System is overloaded, delaying launch"
+        );
+
+        if tokio::time::sleep(LOAD_POLL_INTERVAL)
+            .with_cancellation_token(token)
+            .await
+            .is_none()
+        {
+            return false;
+        }
+    }
+
+    *last_launch = Some(Instant::now());
+    true
+}
+
+/// Niceness applied to spawned processes when running at low priority
+#[cfg(unix)]
+const LOW_PRIORITY_NICENESS: libc::c_int = 10;
+
+static LOW_PRIORITY: OnceLock<bool> = OnceLock::new();
+
+pub fn set_low_priority(low_priority: bool) {
+    LOW_PRIORITY.get_or_init(|| low_priority);
+}
+
+pub fn is_low_priority() -> bool {
+    LOW_PRIORITY.get().copied().unwrap_or_default()
+}
+
+/// Lowers the CPU (and where supported, IO) priority of `cmd` if low priority mode is enabled
+pub fn apply_priority(cmd: &mut tokio::process::Command) {
+    if !is_low_priority() {
+        return;
+    }
+
+    #[cfg(unix)]
+    {
+        // SAFETY: setpriority and ioprio_set are async-signal-safe, and we dont touch any memory
+        unsafe {
+            cmd.pre_exec(|| {
+                // Failing to deprioritize shouldnt prevent the encode from running
+                libc::setpriority(libc::PRIO_PROCESS, 0, LOW_PRIORITY_NICENESS);
+
+                #[cfg(target_os = "linux")]
+                {
+                    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+                    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+                    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+                    libc::syscall(
+                        libc::SYS_ioprio_set,
+                        IOPRIO_WHO_PROCESS,
+                        0,
+                        IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+                    );
+                }
+
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+        cmd.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_sizes() {
+        assert_eq!(parse_byte_size("512"), Ok(512));
+        assert_eq!(parse_byte_size("512B"), Ok(512));
+        assert_eq!(parse_byte_size("4k"), Ok(4 * 1024));
+        assert_eq!(parse_byte_size("500M"), Ok(500 * MIB));
+        assert_eq!(parse_byte_size("8G"), Ok(8 * 1024 * MIB));
+        assert_eq!(parse_byte_size("8 GiB"), Ok(8 * 1024 * MIB));
+        assert_eq!(parse_byte_size(" 2tb "), Ok(2 * 1024 * 1024 * MIB));
+    }
+
+    #[test]
+    fn invalid_byte_sizes() {
+        for given in ["", "G", "8X", "1.5G", "-1G", "99999999999T"] {
+            assert!(parse_byte_size(given).is_err(), "{given}");
+        }
+    }
+}
@@ -1,4 +1,12 @@
-use std::io::stdout;
+use std::{
+    collections::HashMap,
+    io::{Write, stdout},
+    path::PathBuf,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
 
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{
@@ -7,30 +15,367 @@ use tracing_subscriber::{
     layer::SubscriberExt,
     util::SubscriberInitExt,
 };
+use uuid::Uuid;
 
-pub fn register_tracing_subscriber(quiet: bool) {
-    let stdout_layer = fmt::layer()
-        .pretty()
-        .with_writer(stdout)
-        .with_filter(EnvFilter::from_default_env());
+use crate::execute::{ExecuteProgress, ExecuteProgressPayload};
+
+static FILE_LOG: OnceLock<bool> = OnceLock::new();
+
+/// Whether this run is writing log files
+pub fn file_log_enabled() -> bool {
+    FILE_LOG.get().copied().unwrap_or_default()
+}
+
+/// Flushes anything still buffered for export when dropped, keep it alive until exit
+pub struct LoggingGuard {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+impl Drop for LoggingGuard {
+    fn drop(&mut self) {
+        // Symlinks need extra privileges on Windows, so latest.json is a copy taken once the log is complete
+        #[cfg(not(unix))]
+        if file_log_enabled() {
+            let latest = crate::path::latest_log_path();
+            if let Err(e) = std::fs::copy(crate::path::logs_path(), &latest) {
+                eprintln!("Failed to update {}: {e}", latest.display());
+            }
+        }
+
+        #[cfg(feature = "otlp")]
+        if let Some(tracer_provider) = self.tracer_provider.take()
+            && let Err(e) = tracer_provider.shutdown()
+        {
+            eprintln!("Failed to flush spans to the OTLP endpoint: {e}");
+        }
+    }
+}
+
+/// Exports spans over OTLP/HTTP to `endpoint`, e.g. `http://localhost:4318`
+#[cfg(feature = "otlp")]
+fn otlp_tracer_provider(
+    endpoint: &str,
+) -> anyhow::Result<opentelemetry_sdk::trace::SdkTracerProvider> {
+    use anyhow::Context;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+
+    // The HTTP exporter wants the traces path, accept the collector's base URL like OTEL_EXPORTER_OTLP_ENDPOINT does
+    let endpoint = endpoint.trim_end_matches('/');
+    let endpoint = if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{endpoint}/v1/traces")
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .context("Failed to build OTLP exporter")?;
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build())
+}
+
+/// Points latest.json at this run's log
+#[cfg(unix)]
+fn link_latest_log(log_path: &std::path::Path) -> std::io::Result<()> {
+    let latest = crate::path::latest_log_path();
+    match std::fs::remove_file(&latest) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    // Relative, so the logs directory can be moved
+    let target = log_path.file_name().map(PathBuf::from).unwrap_or_default();
+    std::os::unix::fs::symlink(target, latest)
+}
+
+fn open_log_file() -> anyhow::Result<std::fs::File> {
+    use anyhow::Context;
 
     let log_path = crate::path::logs_path();
-    let log_file = std::fs::OpenOptions::new()
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let file = std::fs::OpenOptions::new()
         .create(true)
         .append(true)
-        .open(log_path)
-        .expect("Failed to open log file");
-    let json_layer = fmt::layer()
-        .json()
-        .with_writer(log_file)
-        .with_filter(LevelFilter::DEBUG);
-
-    if !quiet {
-        tracing_subscriber::registry()
-            .with(json_layer)
-            .with(stdout_layer)
-            .init();
-    } else {
-        tracing_subscriber::registry().with(json_layer).init();
+        .open(&log_path)
+        .with_context(|| format!("Failed to open {}", log_path.display()))?;
+
+    #[cfg(unix)]
+    if let Err(e) = link_latest_log(&log_path) {
+        eprintln!(
+            "Failed to update {}: {e}",
+            crate::path::latest_log_path().display()
+        );
+    }
+
+    Ok(file)
+}
+
+/// `-v`, `-vv` and `-vvv` show info, debug and trace. The file log is always at least debug
+fn verbosity_level(verbosity: u8) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::OFF,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
     }
 }
+
+pub fn register_tracing_subscriber(
+    verbosity: u8,
+    ansi: bool,
+    filter: Option<&str>,
+    otlp_endpoint: Option<&str>,
+    file_log: bool,
+) -> LoggingGuard {
+    let level = verbosity_level(verbosity);
+
+    // RUST_LOG, or else the configured filter, refines the level picked with -v
+    let directives = std::env::var(EnvFilter::DEFAULT_ENV)
+        .ok()
+        .or_else(|| filter.map(str::to_string))
+        .unwrap_or_default();
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .parse_lossy(directives);
+    let stdout_layer = fmt::layer()
+        .pretty()
+        .with_ansi(ansi)
+        .with_writer(stdout)
+        .with_filter(filter);
+
+    let json_layer = match file_log.then(open_log_file).transpose() {
+        Ok(log_file) => log_file.map(|log_file| {
+            FILE_LOG.get_or_init(|| true);
+            fmt::layer()
+                .json()
+                .with_writer(log_file)
+                .with_filter(level.max(LevelFilter::DEBUG))
+        }),
+        // Logging isn't set up yet, and a missing log file shouldn't stop a run
+        Err(e) => {
+            eprintln!("Not writing a log file: {e:#}");
+            None
+        }
+    };
+
+    #[cfg(feature = "otlp")]
+    let (otlp_layer, tracer_provider) = {
+        use opentelemetry::trace::TracerProvider;
+
+        match otlp_endpoint.map(otlp_tracer_provider) {
+            Some(Ok(tracer_provider)) => {
+                let layer = tracing_opentelemetry::layer()
+                    .with_tracer(tracer_provider.tracer(env!("CARGO_PKG_NAME")))
+                    .with_filter(LevelFilter::DEBUG);
+                (Some(layer), Some(tracer_provider))
+            }
+            Some(Err(e)) => {
+                eprintln!("Not exporting spans: {e:#}");
+                (None, None)
+            }
+            None => (None, None),
+        }
+    };
+    #[cfg(not(feature = "otlp"))]
+    let otlp_layer: Option<LevelFilter> = None;
+
+    tracing_subscriber::registry()
+        .with(json_layer)
+        .with((verbosity > 0).then_some(stdout_layer))
+        .with(otlp_layer)
+        .init();
+
+    #[cfg(not(feature = "otlp"))]
+    if otlp_endpoint.is_some() {
+        tracing::warn!("stitch was built without the `otlp` feature, ignoring --otlp-endpoint");
+    }
+
+    LoggingGuard {
+        #[cfg(feature = "otlp")]
+        tracer_provider,
+    }
+}
+
+/// Which logs from previous runs to keep, a log outside of any limit is removed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogRetention {
+    /// Logs to keep, counting this run's
+    pub max_count: Option<usize>,
+    pub max_age: Option<Duration>,
+    /// Combined size of the logs directory
+    pub max_size: Option<u64>,
+}
+impl LogRetention {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_count.is_none() && self.max_age.is_none() && self.max_size.is_none()
+    }
+}
+
+/// Removes the oldest logs outside of `retention`, never this run's. Returns how many were removed and their size
+pub fn prune_logs(retention: LogRetention) -> anyhow::Result<(usize, u64)> {
+    let logs_root = crate::path::logs_root();
+    if !logs_root.exists() {
+        return Ok((0, 0));
+    }
+
+    let current = crate::path::logs_path();
+    let latest = crate::path::latest_log_path();
+    let current_size = std::fs::metadata(&current)
+        .map(|meta| meta.len())
+        .unwrap_or_default();
+
+    let mut logs: Vec<(PathBuf, Duration, u64)> = Vec::new();
+    for entry in std::fs::read_dir(&logs_root)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        let path = entry.path();
+        if !meta.is_file()
+            || path == current
+            || path == latest
+            || path.extension().is_none_or(|extension| extension != "json")
+        {
+            continue;
+        }
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or_default();
+        logs.push((path, age, meta.len()));
+    }
+    // Oldest first
+    logs.sort_by_key(|(_, age, _)| std::cmp::Reverse(*age));
+
+    let mut remaining_count = logs.len() + 1;
+    let mut remaining_size: u64 = current_size + logs.iter().map(|(_, _, size)| size).sum::<u64>();
+    let mut removed = 0usize;
+    let mut reclaimed = 0u64;
+    for (path, age, size) in logs {
+        let too_many = retention
+            .max_count
+            .is_some_and(|max_count| remaining_count > max_count);
+        let too_old = retention.max_age.is_some_and(|max_age| age >= max_age);
+        let too_big = retention
+            .max_size
+            .is_some_and(|max_size| remaining_size > max_size);
+        if !too_many && !too_old && !too_big {
+            continue;
+        }
+
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                tracing::debug!(path =% path.display(), size = size, "Removed log file");
+                removed += 1;
+                reclaimed += size;
+                remaining_count -= 1;
+                remaining_size -= size;
+
+                if let Some(target_logs) = crate::path::run_logs_root_of(&path)
+                    && target_logs.is_dir()
+                    && let Err(e) = std::fs::remove_dir_all(&target_logs)
+                {
+                    tracing::warn!(path =% target_logs.display(), error =% e, error_context =? e, "Failed to remove target logs");
+                }
+            }
+            Err(e) => {
+                tracing::warn!(path =% path.display(), error =% e, error_context =? e, "Failed to remove log file");
+            }
+        }
+    }
+
+    Ok((removed, reclaimed))
+}
+
+/// A line for a target's own log, skipping the noisy payloads
+fn target_log_line(payload: &ExecuteProgressPayload) -> Option<String> {
+    Some(match payload {
+        ExecuteProgressPayload::Start { target_name } => format!("Started {target_name}"),
+        ExecuteProgressPayload::Prepared { cat_path } => {
+            format!("Wrote catfile {}", cat_path.display())
+        }
+        ExecuteProgressPayload::Info {
+            source_count,
+            total_duration_seconds,
+            has_audio,
+            mode,
+            resolution,
+        } => {
+            let resolution = resolution
+                .map(|(width, height)| format!(", {width}x{height}"))
+                .unwrap_or_default();
+            format!(
+                "{source_count} sources, {total_duration_seconds:.1}s total, audio: {has_audio}, mode: {mode}{resolution}"
+            )
+        }
+        ExecuteProgressPayload::Phase { phase } => format!("Phase: {phase}"),
+        ExecuteProgressPayload::Queued { reason } => format!("Queued: {reason}"),
+        ExecuteProgressPayload::Command { command_line } => format!("Running: {command_line}"),
+        ExecuteProgressPayload::Log { line } => line.clone(),
+        ExecuteProgressPayload::Warning { message } => format!("Warning: {message}"),
+        ExecuteProgressPayload::Finished(_) => "Finished".to_string(),
+        ExecuteProgressPayload::Failed(error) => format!("Failed: {error}"),
+        ExecuteProgressPayload::Progress { .. }
+        | ExecuteProgressPayload::OutputSize { .. }
+        | ExecuteProgressPayload::Stats { .. }
+        | ExecuteProgressPayload::Spawned => return None,
+    })
+}
+
+/// Writes each plan's ffmpeg log and key events to `<target>.log` under this run's log directory,
+/// on the way to the returned receiver
+pub fn write_target_logs(rx: mpsc::Receiver<ExecuteProgress>) -> mpsc::Receiver<ExecuteProgress> {
+    let dir = crate::path::run_logs_root();
+    let mut files: HashMap<Uuid, (std::fs::File, Instant)> = HashMap::new();
+
+    crate::monitor::tap(rx, move |delivery| {
+        if let ExecuteProgressPayload::Start { target_name } = &delivery.payload {
+            // Targets can be given with directories or drive letters, their logs all live directly in the run's directory
+            let path = dir.join(format!(
+                "{}.log",
+                target_name.replace(['/', '\\', ':'], "_")
+            ));
+            let file = std::fs::create_dir_all(&dir).and_then(|()| {
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+            });
+            match file {
+                Ok(file) => {
+                    files.insert(delivery.id, (file, Instant::now()));
+                }
+                Err(e) => {
+                    tracing::warn!(path =% path.display(), error =% e, error_context =? e, "Failed to open target log");
+                }
+            }
+        }
+
+        if let Some((file, started)) = files.get_mut(&delivery.id)
+            && let Some(line) = target_log_line(&delivery.payload)
+            && let Err(e) = writeln!(file, "[{:>8.1}s] {line}", started.elapsed().as_secs_f64())
+        {
+            tracing::warn!(error =% e, error_context =? e, "Failed to write target log");
+            files.remove(&delivery.id);
+        }
+
+        if matches!(
+            delivery.payload,
+            ExecuteProgressPayload::Finished(_) | ExecuteProgressPayload::Failed(_)
+        ) {
+            files.remove(&delivery.id);
+        }
+    })
+}
@@ -1,22 +1,38 @@
 use std::path::PathBuf;
 
-use clap::Parser;
-use tokio::task::JoinSet;
+use clap::{Parser, Subcommand};
 use tokio_util::sync::CancellationToken;
-use valuable::Valuable;
 
-use crate::{
-    env::find_binaries,
-    execute::{ExecuteProgress, ExecuteProgressPayload, execute_plan},
-    parse::{ParseError, parse_spec},
+use crate::commands::{
+    clean::CleanArgs, completions::CompletionsArgs, doctor::DoctorArgs, estimate::EstimateArgs,
+    fmt::FmtArgs, history::HistoryArgs, init::InitArgs, list::ListArgs, man::ManArgs,
+    plan::PlanArgs, probe::ProbeArgs, run::RunArgs, schema::SchemaArgs, serve::ServeArgs,
+    validate::ValidateArgs,
 };
 
+pub mod color;
+pub mod commands;
+pub mod config;
+pub mod container;
 pub mod env;
 pub mod execute;
+pub mod exit;
+pub mod gha;
+pub mod ipc;
 pub mod limits;
 pub mod logging;
+pub mod metrics;
+pub mod monitor;
 pub mod parse;
 pub mod path;
+pub mod pause;
+pub mod probe;
+pub mod report;
+pub mod runs;
+pub mod server;
+pub mod theme;
+pub mod throughput;
+pub mod webhook;
 
 /// ffmpeg wrapper to bulk stitch video files together based on a specification file
 #[derive(Parser)]
@@ -25,6 +41,8 @@ pub mod path;
     author,
     about,
     long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
     help_template = "\
 {name} ({version})
 {author-with-newline}
@@ -34,263 +52,193 @@ pub mod path;
 {all-args}"
 )]
 pub struct Args {
-    /// Path to the specification file containing stitch instructions
-    #[arg(value_name = "SPEC_FILE")]
-    pub spec: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
-    /// Output directory for stitched video files (default: current directory)
-    #[arg(short = 'o', long, value_name = "DIR", help_heading = "Directories")]
-    pub target_dir: Option<PathBuf>,
+    /// `stitch <SPEC_FILE>` is shorthand for `stitch run <SPEC_FILE>`
+    // Not an `Option`, clap leaves flattened args out of their struct's group, so it would always be `None`
+    #[command(flatten)]
+    pub run: RunArgs,
 
-    /// Input directory containing source video files (default: current directory)
-    #[arg(short = 'i', long, value_name = "DIR", help_heading = "Directories")]
-    pub sources_dir: Option<PathBuf>,
+    /// Log to stdout, repeat for more detail: -v info, -vv debug, -vvv trace (refine with RUST_LOG)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
 
-    /// Enable verbose logging (configure with RUST_LOG environment variable)
-    #[arg(short, long)]
-    pub verbose: bool,
+    /// Config file with defaults for any flags not given (default: ~/.config/stitch/config.toml)
+    #[arg(env = "STITCH_CONFIG", long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
 
-    #[arg(env = "STITCH_BIN_FFMPEG", long, help_heading = "Binaries")]
-    pub ffmpeg_path: Option<PathBuf>,
+    /// Directory to keep catfiles and intermediates in (default: the system temp directory)
+    #[arg(env = "STITCH_TMP_DIR", long, value_name = "DIR", global = true)]
+    pub tmp_dir: Option<PathBuf>,
 
-    #[arg(env = "STITCH_BIN_FFPROBE", long, help_heading = "Binaries")]
-    pub ffprobe_path: Option<PathBuf>,
-}
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    logging::register_tracing_subscriber(!args.verbose);
-    let cancellation_token = CancellationToken::new();
+    /// Directory to keep logs and other data in (default: the platform data directory)
+    #[arg(env = "STITCH_DATA_DIR", long, value_name = "DIR", global = true)]
+    pub data_dir: Option<PathBuf>,
 
-    libsignal::cancel_after_signal(cancellation_token.clone());
+    /// Directory to write JSON logs to (default: `logs` in the data directory)
+    #[arg(env = "STITCH_LOG_DIR", long, value_name = "DIR", global = true)]
+    pub log_dir: Option<PathBuf>,
 
-    let span = tracing::info_span!("main").entered();
+    /// Don't write a JSON log file for this invocation
+    #[arg(env = "STITCH_NO_FILE_LOG", long, global = true)]
+    pub no_file_log: bool,
 
-    find_binaries(args.ffmpeg_path, args.ffprobe_path)?;
+    /// When to color output, NO_COLOR turns it off for auto (default: auto)
+    #[arg(long, value_name = "WHEN", global = true)]
+    pub color: Option<theme::ColorChoice>,
 
-    let cwd = std::env::current_dir().expect(
-        "Failed to get current directory, please pass an directories with --target-dir and --sources-dir",
-    );
+    /// Colors for the progress display and summary (default: default)
+    #[arg(env = "STITCH_THEME", long, value_name = "THEME", global = true)]
+    pub theme: Option<theme::Theme>,
 
-    let target_dir = args.target_dir.unwrap_or(cwd.clone());
-    let sources_dir = args.sources_dir.unwrap_or(cwd.clone());
+    /// Export spans to an OpenTelemetry collector over OTLP/HTTP, e.g. http://localhost:4318 (requires the `otlp` feature)
+    #[arg(env = "STITCH_OTLP_ENDPOINT", long, value_name = "URL", global = true)]
+    pub otlp_endpoint: Option<String>,
 
-    if !target_dir.exists() {
-        std::fs::create_dir_all(&target_dir).expect("Failed to create target directory");
-    }
+    /// Emit GitHub Actions annotations for invalid specs and failed targets, on by default when GITHUB_ACTIONS is set
+    #[arg(long, global = true)]
+    pub gha: bool,
+}
 
-    let spec = match parse_spec(args.spec, target_dir, sources_dir) {
-        Ok(spec) => spec,
+#[derive(Subcommand)]
+pub enum Command {
+    /// Stitch every target in a specification file
+    Run(RunArgs),
+    /// Check that a specification file parses and all of its sources exist
+    Validate(ValidateArgs),
+    /// Print each target with its sources as stitch understood the spec
+    List(ListArgs),
+    /// Print the ffmpeg commands each target would run, without running them
+    Plan(PlanArgs),
+    /// Print the duration, resolution and audio presence of every source
+    Probe(ProbeArgs),
+    /// Predict output durations, sizes and how long a run will take, without encoding anything
+    Estimate(EstimateArgs),
+    /// Remove temporary files left behind by previous runs and cached intermediates, and prune old logs
+    Clean(CleanArgs),
+    /// Create a specification file from the video files in a directory
+    Init(InitArgs),
+    /// Rewrite specification files in canonical form, or check that they are
+    Fmt(FmtArgs),
+    /// Show past runs, or every build of a target, with their outcomes and how long they took
+    History(HistoryArgs),
+    /// Check that ffmpeg, ffprobe and stitch's directories are ready for a run
+    Doctor(DoctorArgs),
+    /// Accept jobs over an HTTP API
+    Serve(ServeArgs),
+    /// Print a shell completion script
+    #[command(hide = true)]
+    Completions(CompletionsArgs),
+    /// Generate man pages
+    #[command(hide = true)]
+    Man(ManArgs),
+    /// Print the JSON Schema of the progress events or the run report
+    #[command(hide = true)]
+    Schema(SchemaArgs),
+}
 
-        Err(e) => match &e {
-            ParseError::Validation { errors } => {
-                if !args.verbose {
-                    eprintln!("Validation failed:");
-                    for error in errors {
-                        eprintln!("\t{error}")
-                    }
-                    eprintln!();
-                }
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
 
-                return Err(e.into());
-            }
-            _ => return Err(e.into()),
-        },
+    let config = config::load(args.config.as_deref())?;
+    let verbosity = match config.verbose {
+        Some(true) => args.verbose.max(1),
+        _ => args.verbose,
     };
+    let verbose = verbosity > 0;
 
-    let mut executions = JoinSet::new();
-    let (tx, rx) = tokio::sync::mpsc::channel(100);
-
-    for plan in spec {
-        let tx = tx.clone();
-        let tmp_root = path::run_tmp_root();
-        executions.spawn(execute_plan(
-            plan,
-            tx,
-            tmp_root,
-            cancellation_token.child_token(),
-        ));
+    // clap requires SPEC_FILE when no subcommand is given
+    let mut command = args.command.unwrap_or(Command::Run(args.run));
+    config.apply(&mut command);
+    if let Some(tmp_dir) = args.tmp_dir.or_else(|| config.tmp_dir.clone()) {
+        path::set_tmp_dir(tmp_dir);
     }
-
-    let handle = tokio::spawn(monitor(rx, args.verbose));
-
-    executions.join_next().await;
-
-    // Drop the original sender so channel closes
-    drop(tx);
-
-    // Monitor will exit naturally when channel closes, just wait for it
-    match handle.await {
-        Ok(_) => { /* monitor closed normally */ }
-        Err(join_error) => {
-            tracing::error!(error =% join_error, error_context =? join_error,"Failed to join monitor thread")
-        }
+    if let Some(data_dir) = args.data_dir.or_else(|| config.data_dir.clone()) {
+        path::set_data_dir(data_dir);
     }
-
-    span.exit();
-
-    Ok(())
-}
-
-async fn monitor(mut rx: tokio::sync::mpsc::Receiver<ExecuteProgress>, verbose: bool) {
-    use crossterm::{
-        ExecutableCommand, cursor,
-        terminal::{Clear, ClearType},
-    };
-    use std::collections::HashMap;
-    use std::io::{Write, stdout};
-    use uuid::Uuid;
-
-    struct ProcessState {
-        name: String,
-        progress_pct: f64,
-        current_seconds: Option<f64>,
-        total_seconds: Option<f64>,
-        phase: Option<String>,
-        warning: Option<String>,
-        error: Option<String>,
-        finished: bool,
-        failed: bool,
+    if let Some(log_dir) = args.log_dir.or_else(|| config.log_dir.clone()) {
+        path::set_log_dir(log_dir);
     }
+    let file_log = !(args.no_file_log || config.no_file_log.unwrap_or_default());
 
-    fn render_progress_bar(pct: f64, width: usize) -> String {
-        let filled = ((pct / 100.0) * width as f64) as usize;
-        let empty = width.saturating_sub(filled);
-        format!("[{}{}]", "█".repeat(filled), "░".repeat(empty))
-    }
-
-    fn render_compact(processes: &HashMap<Uuid, ProcessState>) -> String {
-        let mut output = String::new();
-
-        for process in processes.values() {
-            // Status icon
-            let icon = if process.failed {
-                "✗"
-            } else if process.finished {
-                "✓"
-            } else {
-                "⟳"
-            };
-
-            // Name and status line
-            output.push_str(&format!("{} {} ", icon, process.name));
-
-            if let Some(phase) = &process.phase {
-                output.push_str(&format!("({}) ", phase));
-            }
-
-            output.push('\n');
-
-            // Progress bar (always present)
-            output.push_str(&format!(
-                "  {} {:>5.1}%\n",
-                render_progress_bar(process.progress_pct, 50),
-                process.progress_pct
-            ));
-
-            // Time info (always present, use placeholders if not available)
-            match (process.current_seconds, process.total_seconds) {
-                (Some(current), Some(total)) => {
-                    let remaining = total - current;
-                    output.push_str(&format!(
-                        "  Time: {:.1}s / {:.1}s  (remaining: {:.1}s)\n",
-                        current, total, remaining
-                    ));
-                }
-                _ => {
-                    output.push_str("  Time: -/- (remaining: -)\n");
-                }
-            }
-
-            // Warning (always present, use placeholder if not available)
-            if let Some(warning) = &process.warning {
-                output.push_str(&format!("  ⚠️  {}\n", warning));
-            } else {
-                output.push_str("  \n");
+    theme::set(
+        args.color.or(config.color).unwrap_or_default(),
+        args.theme.or(config.theme).unwrap_or_default(),
+    );
+    gha::set(args.gha);
+    let ansi = !matches!(&command, Command::Run(run) if run.no_progress) && theme::is_colored();
+    let _logging = logging::register_tracing_subscriber(
+        verbosity,
+        ansi,
+        config.log_filter.as_deref(),
+        args.otlp_endpoint.as_deref(),
+        file_log,
+    );
+    let retention = config.log_retention();
+    if file_log && !retention.is_unlimited() {
+        match logging::prune_logs(retention) {
+            Ok((removed, reclaimed)) if removed > 0 => {
+                tracing::debug!(removed = removed, reclaimed = reclaimed, "Pruned old logs");
             }
-
-            // Error (always present, use placeholder if not available)
-            if let Some(error) = &process.error {
-                output.push_str(&format!("  ❌ {}\n", error));
-            } else {
-                output.push_str("  \n");
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(error =% e, error_context =? e, "Failed to prune old logs");
             }
-
-            output.push('\n');
         }
-
-        output
     }
+    let cancellation_token = CancellationToken::new();
 
-    let mut processes: HashMap<Uuid, ProcessState> = HashMap::new();
-
-    while let Some(delivery) = rx.recv().await {
-        tracing::info!(id =% delivery.id, seq = delivery.seq, delivery = delivery.payload.as_value(), "Received delivery");
-
-        let entry = processes.entry(delivery.id).or_insert(ProcessState {
-            name: "Unknown".into(),
-            progress_pct: 0.0,
-            current_seconds: None,
-            total_seconds: None,
-            phase: None,
-            warning: None,
-            error: None,
-            finished: false,
-            failed: false,
-        });
+    libsignal::cancel_after_signal(cancellation_token.clone());
 
-        match delivery.payload {
-            ExecuteProgressPayload::Start { target_name } => {
-                entry.name = target_name;
-            }
-            ExecuteProgressPayload::Info {
-                total_duration_seconds,
-                ..
-            } => {
-                entry.total_seconds = Some(total_duration_seconds);
-            }
-            ExecuteProgressPayload::Phase { phase } => {
-                entry.phase = Some(phase);
+    // Crashed and killed runs never clean up after themselves
+    if matches!(command, Command::Run(_) | Command::Serve(_)) {
+        let max_age = config.tmp_max_age.unwrap_or(path::DEFAULT_ORPHAN_MAX_AGE);
+        match path::remove_run_dirs(Some(max_age)) {
+            Ok((removed, reclaimed)) if removed > 0 => {
+                tracing::info!(
+                    removed = removed,
+                    reclaimed = reclaimed,
+                    "Removed orphaned run directories"
+                );
             }
-            ExecuteProgressPayload::Warning { message } => {
-                entry.warning = Some(message);
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(error =% e, error_context =? e, "Failed to remove orphaned run directories");
             }
-            ExecuteProgressPayload::Progress {
-                total_seconds,
-                current_seconds,
-            } => {
-                entry.total_seconds = Some(total_seconds);
-                entry.current_seconds = Some(current_seconds);
-                entry.progress_pct = (current_seconds / total_seconds * 100.0).min(100.0);
-            }
-            ExecuteProgressPayload::Finished(_) => {
-                entry.finished = true;
-                entry.progress_pct = 100.0;
-                entry.phase = Some("Complete".to_string());
-            }
-            ExecuteProgressPayload::Failed(err) => {
-                entry.failed = true;
-                entry.error = Some(err.to_string());
-            }
-            _ => {}
         }
+    }
 
-        if !verbose {
-            let mut stdout = stdout();
-            let _ = stdout.execute(cursor::MoveTo(0, 0));
-            let _ = stdout.execute(Clear(ClearType::All));
-            print!("{}", render_compact(&processes));
-            let _ = stdout.flush();
+    let span = tracing::info_span!("main", run_id = path::run_id()).entered();
+
+    let result = match command {
+        Command::Run(run) => commands::run::run(run, verbose, cancellation_token).await,
+        Command::Validate(validate) => commands::validate::validate(validate, verbose),
+        Command::List(list) => commands::list::list(list, verbose),
+        Command::Plan(plan) => commands::plan::plan(plan, verbose, cancellation_token).await,
+        Command::Probe(probe) => commands::probe::probe(probe, verbose, cancellation_token).await,
+        Command::Estimate(estimate) => {
+            commands::estimate::estimate(estimate, verbose, cancellation_token).await
         }
-    }
+        Command::Clean(clean) => commands::clean::clean(clean),
+        Command::Init(init) => commands::init::init(init),
+        Command::Fmt(fmt) => commands::fmt::fmt(fmt),
+        Command::History(history) => commands::history::history(history),
+        Command::Doctor(doctor) => commands::doctor::doctor(doctor, cancellation_token).await,
+        Command::Serve(serve) => commands::serve::serve(serve, cancellation_token).await,
+        Command::Completions(completions) => commands::completions::completions(completions),
+        Command::Man(man) => commands::man::man(man),
+        Command::Schema(schema) => commands::schema::schema(schema),
+    };
 
-    // Final display
-    if !verbose {
-        let mut stdout = stdout();
-        let _ = stdout.execute(cursor::MoveTo(0, 0));
-        let _ = stdout.execute(Clear(ClearType::All));
-        print!("{}", render_compact(&processes));
-        let _ = stdout.flush();
-    }
+    span.exit();
+
+    // Exiting skips destructors, so logs are flushed first
+    let Err(e) = result else {
+        return Ok(());
+    };
+    eprintln!("Error: {e:?}");
+    drop(_logging);
+    std::process::exit(exit::code(&e))
 }
@@ -0,0 +1,164 @@
+use std::{collections::HashMap, fmt::Write, net::SocketAddr, sync::Mutex, time::Instant};
+
+use anyhow::Context;
+use axum::{Router, http::header, response::IntoResponse, routing::get};
+use lazy_static::lazy_static;
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::execute::{ExecuteError, ExecuteProgress, ExecuteProgressPayload};
+
+struct RunningPlan {
+    started: Instant,
+    current_seconds: f64,
+}
+
+#[derive(Default)]
+struct Metrics {
+    running: HashMap<Uuid, RunningPlan>,
+    completed: u64,
+    failed: u64,
+    cancelled: u64,
+    encoded_seconds: f64,
+}
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+/// Updates the process wide metrics from a delivery
+pub fn record(delivery: &ExecuteProgress) {
+    let Ok(mut metrics) = METRICS.lock() else {
+        return;
+    };
+
+    match &delivery.payload {
+        ExecuteProgressPayload::Start { .. } => {
+            metrics.running.insert(
+                delivery.id,
+                RunningPlan {
+                    started: Instant::now(),
+                    current_seconds: 0.0,
+                },
+            );
+        }
+        ExecuteProgressPayload::Progress {
+            current_seconds, ..
+        } => {
+            let Some(plan) = metrics.running.get_mut(&delivery.id) else {
+                return;
+            };
+            let encoded = (current_seconds - plan.current_seconds).max(0.0);
+            plan.current_seconds = plan.current_seconds.max(*current_seconds);
+            metrics.encoded_seconds += encoded;
+        }
+        ExecuteProgressPayload::Finished(_) => {
+            metrics.running.remove(&delivery.id);
+            metrics.completed += 1;
+        }
+        ExecuteProgressPayload::Failed(ExecuteError::Cancelled) => {
+            metrics.running.remove(&delivery.id);
+            metrics.cancelled += 1;
+        }
+        ExecuteProgressPayload::Failed(_) => {
+            metrics.running.remove(&delivery.id);
+            metrics.failed += 1;
+        }
+        _ => {}
+    }
+}
+
+/// Prometheus text exposition of the current metrics
+pub fn render() -> String {
+    let Ok(metrics) = METRICS.lock() else {
+        return String::new();
+    };
+
+    // Seconds of media encoded per wall clock second, summed over every running plan
+    let speed: f64 = metrics
+        .running
+        .values()
+        .map(|plan| plan.current_seconds / plan.started.elapsed().as_secs_f64().max(1.0))
+        .sum();
+
+    let mut out = String::new();
+    for (name, kind, help, value) in [
+        (
+            "stitch_plans_running",
+            "gauge",
+            "Plans currently running",
+            metrics.running.len() as f64,
+        ),
+        (
+            "stitch_plans_completed_total",
+            "counter",
+            "Plans that finished successfully",
+            metrics.completed as f64,
+        ),
+        (
+            "stitch_plans_failed_total",
+            "counter",
+            "Plans that failed",
+            metrics.failed as f64,
+        ),
+        (
+            "stitch_plans_cancelled_total",
+            "counter",
+            "Plans that were cancelled",
+            metrics.cancelled as f64,
+        ),
+        (
+            "stitch_encoded_seconds_total",
+            "counter",
+            "Seconds of media encoded",
+            metrics.encoded_seconds,
+        ),
+        (
+            "stitch_encode_speed",
+            "gauge",
+            "Seconds of media encoded per second across running plans",
+            speed,
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        let _ = writeln!(out, "{name} {value}");
+    }
+
+    out
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(),
+    )
+}
+
+/// Routes serving `/metrics`, to merge into another router
+pub fn router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+/// Records every delivery on the way to the returned receiver
+pub fn record_all(rx: mpsc::Receiver<ExecuteProgress>) -> mpsc::Receiver<ExecuteProgress> {
+    crate::monitor::tap(rx, record)
+}
+
+/// Listens on `listen` up front, so a bad address fails before any plan starts
+pub async fn bind(listen: SocketAddr) -> anyhow::Result<TcpListener> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {listen}"))?;
+    tracing::info!(listen =% listen, "Serving metrics on http://{listen}/metrics");
+    Ok(listener)
+}
+
+/// Serves `/metrics` on its own until `token` is cancelled
+pub async fn serve(listener: TcpListener, token: CancellationToken) -> anyhow::Result<()> {
+    axum::serve(listener, router::<()>())
+        .with_graceful_shutdown(async move { token.cancelled().await })
+        .await
+        .context("Metrics server failed")
+}
@@ -0,0 +1,1340 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Write, stdout},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Gauge, List, ListItem, ListState, Paragraph, Wrap},
+};
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use uuid::Uuid;
+use valuable::Valuable;
+
+use crate::{
+    commands::estimate::format_duration,
+    execute::{ExecuteError, ExecuteProgress, ExecuteProgressPayload},
+    limits, pause,
+    theme::{self, Status},
+    throughput::ThroughputHistory,
+};
+
+/// ffmpeg log lines kept per task for the detail pane
+const LOG_LINES: usize = 200;
+/// How often the display is redrawn when no deliveries arrive
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the input thread blocks waiting for a key before checking whether it should stop
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Widest the progress bars in the final summary get
+const SUMMARY_BAR_WIDTH: usize = 50;
+/// Width assumed when the terminal size can't be read
+const FALLBACK_TERMINAL_WIDTH: u16 = 80;
+/// How long a task has to encode for before its own speed is trusted over past runs' for the ETA
+const ETA_WARMUP: Duration = Duration::from_secs(10);
+/// How often running tasks are listed when progress is written as plain lines
+const PLAIN_STATUS_INTERVAL: Duration = Duration::from_secs(10);
+/// Most warnings shown at once in the detail pane, older ones are scrolled to
+const WARNING_ROWS: usize = 5;
+
+/// How progress is reported while a batch runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressFormat {
+    /// Live updating display
+    #[default]
+    Tui,
+    /// Periodic plain text status lines, used instead of `tui` when stdout isn't a terminal
+    Plain,
+    /// Only a line as each task starts, finishes or fails
+    Lines,
+    /// One JSON object per progress event on stdout
+    Json,
+}
+
+/// Writes every delivery to stdout as newline delimited JSON
+pub async fn json_monitor(mut rx: tokio::sync::mpsc::Receiver<ExecuteProgress>) {
+    let mut stdout = stdout();
+
+    while let Some(delivery) = rx.recv().await {
+        tracing::info!(id =% delivery.id, seq = delivery.seq, delivery = delivery.payload.as_value(), "Received delivery");
+
+        match serde_json::to_string(&delivery) {
+            Ok(line) => {
+                let _ = writeln!(stdout, "{line}");
+                let _ = stdout.flush();
+            }
+            Err(e) => {
+                tracing::error!(id =% delivery.id, seq = delivery.seq, error =% e, error_context =? e, "Failed to serialize delivery");
+            }
+        }
+    }
+}
+
+/// Calls `f` with every delivery on the way to the returned receiver
+pub fn tap(
+    rx: tokio::sync::mpsc::Receiver<ExecuteProgress>,
+    mut f: impl FnMut(&ExecuteProgress) + Send + 'static,
+) -> tokio::sync::mpsc::Receiver<ExecuteProgress> {
+    tap_with(rx, (), move |(), delivery| f(delivery)).0
+}
+
+/// Calls `f` with `state` and every delivery on the way to the returned receiver. The handle gives `state` back
+/// once the channel closes
+pub fn tap_with<S: Send + 'static>(
+    mut rx: tokio::sync::mpsc::Receiver<ExecuteProgress>,
+    mut state: S,
+    mut f: impl FnMut(&mut S, &ExecuteProgress) + Send + 'static,
+) -> (
+    tokio::sync::mpsc::Receiver<ExecuteProgress>,
+    tokio::task::JoinHandle<S>,
+) {
+    let (tx, forwarded) = tokio::sync::mpsc::channel(100);
+
+    let handle = tokio::spawn(async move {
+        while let Some(delivery) = rx.recv().await {
+            f(&mut state, &delivery);
+            // The monitor going away shouldn't stop the stages before it
+            let _ = tx.send(delivery).await;
+        }
+        state
+    });
+
+    (forwarded, handle)
+}
+
+/// Appends every delivery to `path` as newline delimited JSON on the way to the returned receiver,
+/// so the file can be tailed whichever monitor is showing progress
+pub fn write_progress_file(
+    path: &Path,
+    rx: tokio::sync::mpsc::Receiver<ExecuteProgress>,
+) -> anyhow::Result<tokio::sync::mpsc::Receiver<ExecuteProgress>> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open progress file {}", path.display()))?;
+
+    Ok(tap(rx, move |delivery| {
+        match serde_json::to_string(delivery) {
+            Ok(line) => {
+                if let Err(e) = writeln!(file, "{line}") {
+                    tracing::error!(error =% e, error_context =? e, "Failed to write to progress file");
+                }
+            }
+            Err(e) => {
+                tracing::error!(id =% delivery.id, seq = delivery.seq, error =% e, error_context =? e, "Failed to serialize delivery");
+            }
+        }
+    }))
+}
+
+/// Writes a line whenever a task settles, and periodically lists the tasks still running
+pub async fn plain_monitor(
+    mut rx: tokio::sync::mpsc::Receiver<ExecuteProgress>,
+    history: Arc<ThroughputHistory>,
+) {
+    let mut state = MonitorState::new(history);
+    let mut ticks = tokio::time::interval(PLAIN_STATUS_INTERVAL);
+    let started = Instant::now();
+
+    loop {
+        tokio::select! {
+            delivery = rx.recv() => {
+                let Some(delivery) = delivery else {
+                    break;
+                };
+
+                let id = delivery.id;
+                let settled = matches!(
+                    delivery.payload,
+                    ExecuteProgressPayload::Finished(_) | ExecuteProgressPayload::Failed(_)
+                );
+                state.apply(delivery);
+
+                if settled && let Some(process) = state.processes.get(&id) {
+                    println!("{}", process.status_line());
+                }
+            }
+            _ = ticks.tick() => {
+                let unsettled: Vec<_> = state.processes().filter(|p| !p.settled()).collect();
+                if unsettled.is_empty() {
+                    continue;
+                }
+                let queued = unsettled.iter().filter(|p| p.queued).count();
+
+                println!(
+                    "Overall {:>5.1}%, {} of {} targets running, {queued} queued, {} elapsed",
+                    state.overall_pct(),
+                    unsettled.len() - queued,
+                    state.order.len(),
+                    format_duration(started.elapsed().as_secs_f64())
+                );
+                for process in unsettled {
+                    println!("{}", process.status_line());
+                }
+            }
+        }
+    }
+}
+
+/// Writes a line as each task starts, finishes or fails, and nothing else
+pub async fn line_monitor(mut rx: tokio::sync::mpsc::Receiver<ExecuteProgress>) {
+    let mut state = MonitorState::default();
+    let mut started_at = HashMap::new();
+
+    while let Some(delivery) = rx.recv().await {
+        let id = delivery.id;
+        let is_start = matches!(delivery.payload, ExecuteProgressPayload::Start { .. });
+        let settled = matches!(
+            delivery.payload,
+            ExecuteProgressPayload::Finished(_) | ExecuteProgressPayload::Failed(_)
+        );
+        state.apply(delivery);
+
+        let Some(process) = state.processes.get(&id) else {
+            continue;
+        };
+
+        if is_start {
+            started_at.insert(id, Instant::now());
+            println!("started {}", process.name);
+        } else if settled {
+            let elapsed = started_at
+                .get(&id)
+                .map(|started_at| format!(" after {:.1}s", started_at.elapsed().as_secs_f64()))
+                .unwrap_or_default();
+
+            match &process.error {
+                Some(error) => println!(
+                    "{} {}{elapsed}: {error}",
+                    theme::paint(Status::Failure, "failed"),
+                    process.name
+                ),
+                None if process.cancelled => println!(
+                    "{} {}{elapsed}",
+                    theme::paint(Status::Warning, "cancelled"),
+                    process.name
+                ),
+                None => println!(
+                    "{} {}{elapsed}",
+                    theme::paint(Status::Success, "finished"),
+                    process.name
+                ),
+            }
+        }
+    }
+}
+
+struct ProcessState {
+    name: String,
+    progress_pct: f64,
+    current_seconds: Option<f64>,
+    total_seconds: Option<f64>,
+    /// Media seconds per wall clock second past runs managed for this kind of plan
+    predicted_speed: Option<f64>,
+    started: Instant,
+    /// Wall clock time from starting to settling
+    settled_after: Option<Duration>,
+    encoding_since: Option<Instant>,
+    /// Frames encoded per second, from ffmpeg's stats line
+    fps: Option<f64>,
+    /// Media seconds encoded per wall clock second, from ffmpeg's stats line
+    speed: Option<f64>,
+    /// Size of the target so far, and when it was checked
+    output_size: Option<(u64, Instant)>,
+    /// Bytes per second the target grew by between its last two sizes
+    output_growth: Option<f64>,
+    phase: Option<String>,
+    command_line: Option<String>,
+    /// Every warning in the order they were sent
+    warnings: Vec<String>,
+    error: Option<String>,
+    log: VecDeque<String>,
+    /// Waiting its turn rather than doing anything, so 0% isn't mistaken for stuck
+    queued: bool,
+    finished: bool,
+    failed: bool,
+    /// Stopped because it was asked to, from the monitor or with the whole run
+    cancelled: bool,
+}
+
+impl ProcessState {
+    fn new() -> Self {
+        Self {
+            name: "Unknown".into(),
+            progress_pct: 0.0,
+            current_seconds: None,
+            total_seconds: None,
+            predicted_speed: None,
+            started: Instant::now(),
+            settled_after: None,
+            encoding_since: None,
+            fps: None,
+            speed: None,
+            output_size: None,
+            output_growth: None,
+            phase: None,
+            command_line: None,
+            warnings: vec![],
+            error: None,
+            log: VecDeque::new(),
+            queued: false,
+            finished: false,
+            failed: false,
+            cancelled: false,
+        }
+    }
+
+    fn apply(&mut self, payload: ExecuteProgressPayload, history: &ThroughputHistory) {
+        // Anything that moves the task along means it's got past whatever it was queued behind
+        if !matches!(
+            payload,
+            ExecuteProgressPayload::Log { .. }
+                | ExecuteProgressPayload::Warning { .. }
+                | ExecuteProgressPayload::Stats { .. }
+                | ExecuteProgressPayload::OutputSize { .. }
+        ) {
+            self.queued = false;
+        }
+
+        match payload {
+            ExecuteProgressPayload::Start { target_name } => {
+                self.name = target_name;
+                self.started = Instant::now();
+            }
+            ExecuteProgressPayload::Info {
+                total_duration_seconds,
+                mode,
+                resolution,
+                ..
+            } => {
+                self.total_seconds = Some(total_duration_seconds);
+                self.predicted_speed = history.predict_speed(&mode, resolution);
+            }
+            ExecuteProgressPayload::Phase { phase } => {
+                self.phase = Some(phase);
+            }
+            ExecuteProgressPayload::Queued { reason } => {
+                self.queued = true;
+                self.phase = Some(reason);
+            }
+            ExecuteProgressPayload::Command { command_line } => {
+                self.command_line = Some(command_line);
+                self.encoding_since = Some(Instant::now());
+            }
+            ExecuteProgressPayload::Log { line } => {
+                if self.log.len() == LOG_LINES {
+                    self.log.pop_front();
+                }
+                self.log.push_back(line);
+            }
+            ExecuteProgressPayload::Warning { message } => {
+                self.warnings.push(message);
+            }
+            ExecuteProgressPayload::Progress {
+                total_seconds,
+                current_seconds,
+            } => {
+                self.total_seconds = Some(total_seconds);
+                self.current_seconds = Some(current_seconds);
+                self.progress_pct = (current_seconds / total_seconds * 100.0).min(100.0);
+            }
+            ExecuteProgressPayload::OutputSize { bytes } => {
+                let now = Instant::now();
+                if let Some((last_bytes, last_checked)) = self.output_size {
+                    let elapsed = now.duration_since(last_checked).as_secs_f64();
+                    if elapsed > 0.0 {
+                        self.output_growth =
+                            Some(bytes.saturating_sub(last_bytes) as f64 / elapsed);
+                    }
+                }
+                self.output_size = Some((bytes, now));
+            }
+            ExecuteProgressPayload::Stats { fps, speed } => {
+                self.fps = fps;
+                self.speed = speed;
+            }
+            ExecuteProgressPayload::Finished(_) => {
+                self.finished = true;
+                self.settled_after = Some(self.started.elapsed());
+                self.progress_pct = 100.0;
+                self.phase = Some("Complete".to_string());
+            }
+            ExecuteProgressPayload::Failed(ExecuteError::Cancelled) => {
+                self.cancelled = true;
+                self.settled_after = Some(self.started.elapsed());
+                self.phase = Some("Cancelled".to_string());
+            }
+            ExecuteProgressPayload::Failed(err) => {
+                self.failed = true;
+                self.settled_after = Some(self.started.elapsed());
+                self.error = Some(err.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    fn settled(&self) -> bool {
+        self.finished || self.failed || self.cancelled
+    }
+
+    fn icon(&self) -> &'static str {
+        if self.failed {
+            "✗"
+        } else if self.cancelled {
+            "⊘"
+        } else if self.finished {
+            "✓"
+        } else if self.queued {
+            "⧖"
+        } else {
+            "⟳"
+        }
+    }
+
+    /// How fast the task is encoding, e.g. `2.3x, 71 fps`, while it's running
+    fn rate(&self) -> Option<String> {
+        if self.settled() {
+            return None;
+        }
+        let speed = self.speed.map(|speed| format!("{speed:.1}x"));
+        let fps = self.fps.map(|fps| format!("{fps:.0} fps"));
+        match (speed, fps) {
+            (Some(speed), Some(fps)) => Some(format!("{speed}, {fps}")),
+            (speed, fps) => speed.or(fps),
+        }
+    }
+
+    /// The target's size so far and how fast it's growing while it's being written, e.g. `1.2 GiB (+3.4 MiB/s)`
+    fn output(&self) -> Option<String> {
+        let (bytes, _) = self.output_size?;
+        let size = limits::format_byte_size(bytes);
+        match self.output_growth {
+            Some(growth) if !self.settled() => Some(format!(
+                "{size} (+{}/s)",
+                limits::format_byte_size(growth as u64)
+            )),
+            _ => Some(size),
+        }
+    }
+
+    /// Single line summary for output that can't be redrawn
+    fn status_line(&self) -> String {
+        let mut line = format!(
+            "{} {} {:>5.1}%",
+            self.painted_icon(),
+            self.name,
+            self.progress_pct
+        );
+
+        if let Some(phase) = &self.phase {
+            line.push_str(&format!(" ({phase})"));
+        }
+        if let Some(rate) = self.rate() {
+            line.push_str(&format!(" [{rate}]"));
+        }
+        if let Some(output) = self.output()
+            && !self.settled()
+        {
+            line.push_str(&format!(" {output}"));
+        }
+
+        if let Some(error) = &self.error {
+            line.push_str(&format!(": {error}"));
+        } else if !self.finished && !self.cancelled {
+            line.push_str(&format!("  {}", self.time()));
+        }
+
+        line
+    }
+
+    fn status(&self) -> Option<Status> {
+        if self.failed {
+            Some(Status::Failure)
+        } else if self.cancelled {
+            Some(Status::Warning)
+        } else if self.finished {
+            Some(Status::Success)
+        } else {
+            None
+        }
+    }
+
+    fn style(&self) -> Style {
+        match self.status() {
+            Some(status) => theme::style(status),
+            None if self.queued => Style::new().add_modifier(Modifier::DIM),
+            None => Style::new(),
+        }
+    }
+
+    /// The icon colored for output printed outside the TUI
+    fn painted_icon(&self) -> String {
+        match self.status() {
+            Some(status) => theme::paint(status, self.icon()),
+            None => self.icon().to_string(),
+        }
+    }
+
+    /// Wall clock time since the task started, media time and wall time diverge wildly between copies and re-encodes
+    fn elapsed(&self) -> Duration {
+        self.settled_after.unwrap_or_else(|| self.started.elapsed())
+    }
+
+    fn time(&self) -> String {
+        let media = match (self.current_seconds, self.total_seconds) {
+            (Some(current), Some(total)) => {
+                let eta = self
+                    .eta(current, total)
+                    .map(|eta| format!(", ETA ~{}", format_duration(eta)))
+                    .unwrap_or_default();
+                format!(
+                    "{:.1}s / {:.1}s  (remaining: {:.1}s{eta})",
+                    current,
+                    total,
+                    total - current
+                )
+            }
+            _ => "-/- (remaining: -)".to_string(),
+        };
+        format!(
+            "{media}  {} elapsed",
+            format_duration(self.elapsed().as_secs_f64())
+        )
+    }
+
+    /// Wall clock seconds left, from the speed so far once there's enough of it to go on, otherwise from past runs
+    fn eta(&self, current: f64, total: f64) -> Option<f64> {
+        let observed_speed = self
+            .encoding_since
+            .map(|since| since.elapsed().as_secs_f64())
+            .filter(|&elapsed| elapsed >= ETA_WARMUP.as_secs_f64() && current > 0.0)
+            .map(|elapsed| current / elapsed);
+        let speed = observed_speed.or(self.predicted_speed)?;
+        Some((total - current).max(0.0) / speed)
+    }
+}
+
+/// What the task list is ordered by, cycled with `s`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum SortOrder {
+    /// The order tasks started in
+    #[default]
+    Started,
+    /// Running, then queued, then failed, then finished
+    State,
+    /// Furthest along first
+    Progress,
+    Name,
+}
+impl SortOrder {
+    fn next(self) -> Self {
+        match self {
+            Self::Started => Self::State,
+            Self::State => Self::Progress,
+            Self::Progress => Self::Name,
+            Self::Name => Self::Started,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Started => "started",
+            Self::State => "state",
+            Self::Progress => "progress",
+            Self::Name => "name",
+        }
+    }
+}
+
+/// A row of the task list. Settled tasks share a single row unless they've been expanded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Row {
+    Task(Uuid),
+    Settled,
+}
+
+#[derive(Default)]
+struct MonitorState {
+    processes: HashMap<Uuid, ProcessState>,
+    /// Tasks in the order they were first seen, so rows don't move around between redraws
+    order: Vec<Uuid>,
+    list_state: ListState,
+    /// When the first delivery arrived
+    started: Option<Instant>,
+    /// Whether settled tasks get a row each instead of sharing one
+    expanded: bool,
+    sort_order: SortOrder,
+    /// How many warnings back from the latest the selected task's warnings are scrolled
+    warning_scroll: usize,
+    cancelling: bool,
+    /// Past throughput, for estimating how long tasks have left before ffmpeg reports a speed
+    history: Arc<ThroughputHistory>,
+}
+
+impl MonitorState {
+    fn new(history: Arc<ThroughputHistory>) -> Self {
+        Self {
+            history,
+            ..Self::default()
+        }
+    }
+
+    fn apply(&mut self, delivery: ExecuteProgress) {
+        tracing::info!(id =% delivery.id, seq = delivery.seq, delivery = delivery.payload.as_value(), "Received delivery");
+
+        self.started.get_or_insert_with(Instant::now);
+        let selected = self.selected_row();
+        let Self {
+            processes,
+            order,
+            history,
+            ..
+        } = self;
+        processes
+            .entry(delivery.id)
+            .or_insert_with(|| {
+                order.push(delivery.id);
+                ProcessState::new()
+            })
+            .apply(delivery.payload, history);
+
+        match selected {
+            Some(row) => self.reselect(row),
+            None => self.list_state.select_first(),
+        }
+    }
+
+    /// Settled tasks first, collapsed into one row unless expanded, then the running ones, each in the sort order
+    fn rows(&self) -> Vec<Row> {
+        let settled = |id: &Uuid| {
+            self.processes
+                .get(id)
+                .is_some_and(|process| process.settled())
+        };
+        let mut tasks: Vec<(Uuid, &ProcessState)> = self
+            .order
+            .iter()
+            .filter(|id| self.expanded || !settled(id))
+            .filter_map(|id| self.processes.get(id).map(|process| (*id, process)))
+            .collect();
+
+        // Sorts are stable, so ties keep the order they started in
+        match self.sort_order {
+            SortOrder::Started => {}
+            SortOrder::State => tasks.sort_by_key(|(_, process)| {
+                if process.failed || process.cancelled {
+                    2
+                } else if process.finished {
+                    3
+                } else if process.queued {
+                    1
+                } else {
+                    0
+                }
+            }),
+            SortOrder::Progress => {
+                tasks.sort_by(|(_, a), (_, b)| b.progress_pct.total_cmp(&a.progress_pct))
+            }
+            SortOrder::Name => tasks.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name)),
+        }
+
+        let mut rows = vec![];
+        if !self.expanded && self.order.iter().any(settled) {
+            rows.push(Row::Settled);
+        }
+        rows.extend(tasks.into_iter().map(|(id, _)| Row::Task(id)));
+        rows
+    }
+
+    /// Scrolls the selected task's warnings `by` further back, stopping at the oldest
+    fn scroll_warnings(&mut self, by: usize) {
+        let max = self
+            .selected()
+            .map(|process| process.warnings.len().saturating_sub(WARNING_ROWS))
+            .unwrap_or(0);
+        self.warning_scroll = (self.warning_scroll + by).min(max);
+    }
+
+    fn cycle_sort_order(&mut self) {
+        let selected = self.selected_row();
+        self.sort_order = self.sort_order.next();
+        if let Some(row) = selected {
+            self.reselect(row);
+        }
+    }
+
+    fn selected_row(&self) -> Option<Row> {
+        self.rows().get(self.list_state.selected()?).copied()
+    }
+
+    /// Selects `row` wherever it is now, or the settled row once a selected task has been collapsed into it
+    fn reselect(&mut self, row: Row) {
+        let rows = self.rows();
+        let index = rows
+            .iter()
+            .position(|r| *r == row)
+            .or_else(|| rows.iter().position(|r| *r == Row::Settled));
+        if let Some(index) = index {
+            self.list_state.select(Some(index));
+        }
+    }
+
+    fn toggle_expanded(&mut self) {
+        let selected = self.selected_row();
+        self.expanded = !self.expanded;
+        match selected {
+            // The first settled task stands in for the row they shared
+            Some(Row::Settled) => {
+                let first = self.settled().next().map(|(id, _)| *id);
+                if let Some(id) = first {
+                    self.reselect(Row::Task(id));
+                }
+            }
+            Some(row) => self.reselect(row),
+            None => {}
+        }
+    }
+
+    fn settled(&self) -> impl Iterator<Item = (&Uuid, &ProcessState)> {
+        self.order.iter().filter_map(|id| {
+            self.processes
+                .get(id)
+                .filter(|process| process.settled())
+                .map(|process| (id, process))
+        })
+    }
+
+    fn processes(&self) -> impl Iterator<Item = &ProcessState> {
+        self.order.iter().filter_map(|id| self.processes.get(id))
+    }
+
+    /// Progress through the whole run, weighted by each task's total duration. Settled tasks count as all the way through
+    fn overall_pct(&self) -> f64 {
+        overall_pct(self.processes())
+    }
+
+    fn selected(&self) -> Option<&ProcessState> {
+        match self.selected_row()? {
+            Row::Task(id) => self.processes.get(&id),
+            Row::Settled => None,
+        }
+    }
+
+    fn handle_key(
+        &mut self,
+        key: KeyEvent,
+        cancellation_token: &CancellationToken,
+        plan_tokens: &HashMap<String, CancellationToken>,
+    ) {
+        // Each task's warnings start scrolled to the latest
+        if matches!(
+            key.code,
+            KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::Home
+                | KeyCode::End
+                | KeyCode::Char('k' | 'j' | 'g' | 'G')
+        ) {
+            self.warning_scroll = 0;
+        }
+
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.list_state.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.list_state.select_next(),
+            KeyCode::Home | KeyCode::Char('g') => self.list_state.select_first(),
+            KeyCode::End | KeyCode::Char('G') => self.list_state.select_last(),
+            KeyCode::Char('[') => self.scroll_warnings(1),
+            KeyCode::Char(']') => self.warning_scroll = self.warning_scroll.saturating_sub(1),
+            KeyCode::Enter | KeyCode::Char('e') => self.toggle_expanded(),
+            KeyCode::Char('s') => self.cycle_sort_order(),
+            KeyCode::Char('p') => pause::toggle(),
+            KeyCode::Char('x') => self.cancel_selected(plan_tokens),
+            KeyCode::Char('q') => self.cancel(cancellation_token),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.cancel(cancellation_token)
+            }
+            _ => {}
+        }
+    }
+
+    /// Cancels only the selected plan, the rest keep running
+    fn cancel_selected(&mut self, plan_tokens: &HashMap<String, CancellationToken>) {
+        let Some(Row::Task(id)) = self.selected_row() else {
+            return;
+        };
+        let Some(process) = self.processes.get_mut(&id) else {
+            return;
+        };
+        if process.settled() {
+            return;
+        }
+
+        if let Some(token) = plan_tokens.get(&process.name) {
+            tracing::info!(
+                target_name = process.name,
+                "Cancelling plan from the monitor"
+            );
+            process.phase = Some("Cancelling…".to_string());
+            token.cancel();
+        }
+    }
+
+    /// Raw mode swallows SIGINT, so quitting from the keyboard cancels the run the same way ^C otherwise would
+    fn cancel(&mut self, cancellation_token: &CancellationToken) {
+        if !self.cancelling {
+            tracing::info!("Cancelling from the monitor");
+        }
+        self.cancelling = true;
+        cancellation_token.cancel();
+    }
+}
+
+/// Progress through every one of `processes`, weighted by each task's total duration. Settled tasks count as all the way through
+fn overall_pct<'a>(processes: impl Iterator<Item = &'a ProcessState>) -> f64 {
+    let (done_seconds, total_seconds) =
+        processes.fold((0.0, 0.0), |(done_seconds, total_seconds), process| {
+            // Tasks that haven't been probed yet have nothing to weigh
+            let Some(weight) = process.total_seconds else {
+                return (done_seconds, total_seconds);
+            };
+            let pct = if process.settled() {
+                100.0
+            } else {
+                process.progress_pct
+            };
+            (done_seconds + weight * pct / 100.0, total_seconds + weight)
+        });
+
+    if total_seconds > 0.0 {
+        (done_seconds / total_seconds * 100.0).min(100.0)
+    } else {
+        0.0
+    }
+}
+
+/// Shows the run's progress in the terminal's title, e.g. `stitch: 7/20 done, 54%`, on the way to the returned receiver,
+/// so it can be followed from a background tab. The title from before the run is put back once the channel closes
+pub fn update_title(
+    target_count: usize,
+    history: Arc<ThroughputHistory>,
+    rx: tokio::sync::mpsc::Receiver<ExecuteProgress>,
+) -> tokio::sync::mpsc::Receiver<ExecuteProgress> {
+    // Written to stderr, so it stays out of progress piped from stdout
+    let mut stderr = std::io::stderr();
+    // Saves the title on xterm's title stack, terminals without one ignore it
+    let _ = write!(stderr, "\x1b[22;0t");
+
+    let mut processes = HashMap::new();
+    let mut shown = String::new();
+    let (forwarded, handle) = tap_with(rx, stderr, move |stderr, delivery| {
+        processes
+            .entry(delivery.id)
+            .or_insert_with(ProcessState::new)
+            .apply(delivery.payload.clone(), &history);
+
+        let done = processes
+            .values()
+            .filter(|process| process.settled())
+            .count();
+        let title = format!(
+            "stitch: {done}/{target_count} done, {:.0}%",
+            overall_pct(processes.values())
+        );
+        if title != shown {
+            let _ = crossterm::execute!(stderr, crossterm::terminal::SetTitle(&title));
+            shown = title;
+        }
+    });
+
+    tokio::spawn(async move {
+        if let Ok(mut stderr) = handle.await {
+            let _ = write!(stderr, "\x1b[23;0t");
+            let _ = stderr.flush();
+        }
+    });
+
+    forwarded
+}
+
+/// Shortens `text` to at most `width` columns by cutting out the middle, so both the start and the extension stay readable
+fn truncate_middle(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    // One column goes to the ellipsis
+    let head_width = (width - 1).div_ceil(2);
+    let tail_width = width - 1 - head_width;
+
+    let take = |chars: &mut dyn Iterator<Item = char>, budget: usize| {
+        let mut used = 0;
+        chars
+            .take_while(|c| {
+                used += c.width().unwrap_or(0);
+                used <= budget
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let head: String = take(&mut text.chars(), head_width).into_iter().collect();
+    let tail: String = take(&mut text.chars().rev(), tail_width)
+        .into_iter()
+        .rev()
+        .collect();
+
+    format!("{head}…{tail}")
+}
+
+fn render_progress_bar(pct: f64, width: usize) -> String {
+    let filled = ((pct / 100.0) * width as f64) as usize;
+    let empty = width.saturating_sub(filled);
+    format!("[{}{}]", "█".repeat(filled), "░".repeat(empty))
+}
+
+/// Plain text summary of every task, printed once the TUI has been torn down
+fn render_compact(state: &MonitorState, width: usize) -> String {
+    let mut output = String::new();
+    // Leaves room for the indent and percentage either side of the bar
+    let bar_width = width.saturating_sub(12).clamp(10, SUMMARY_BAR_WIDTH);
+
+    for process in state.processes() {
+        // Name and status line
+        output.push_str(&format!(
+            "{} {} ",
+            process.painted_icon(),
+            truncate_middle(&process.name, width.saturating_sub(3))
+        ));
+
+        // Finished tasks get a line each so long runs don't scroll the failures away, only their warnings still need reading
+        if process.finished {
+            output.push_str(&format!(
+                "after {}",
+                format_duration(process.elapsed().as_secs_f64())
+            ));
+            if let Some(output_size) = process.output() {
+                output.push_str(&format!(", {output_size}"));
+            }
+            output.push('\n');
+            for warning in process.warnings.iter() {
+                output.push_str(&format!("  ⚠️  {}\n", warning));
+            }
+            continue;
+        }
+
+        if let Some(phase) = &process.phase {
+            output.push_str(&format!("({}) ", phase));
+        }
+
+        output.push('\n');
+
+        output.push_str(&format!(
+            "  {} {:>5.1}%\n",
+            render_progress_bar(process.progress_pct, bar_width),
+            process.progress_pct
+        ));
+
+        output.push_str(&format!("  Time: {}\n", process.time()));
+
+        for warning in process.warnings.iter() {
+            output.push_str(&format!("  ⚠️  {}\n", warning));
+        }
+
+        if let Some(error) = &process.error {
+            output.push_str(&format!("  ❌ {}\n", error));
+        }
+
+        // Command line, only for failures so they can be reproduced
+        if process.failed
+            && let Some(command_line) = &process.command_line
+        {
+            output.push_str(&format!("  $ {}\n", command_line));
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+fn render(frame: &mut Frame, state: &mut MonitorState) {
+    let [header_area, body_area, footer_area] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [list_area, detail_area] =
+        Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+            .areas(body_area);
+
+    render_header(frame, header_area, state);
+    render_list(frame, list_area, state);
+    match state.selected_row() {
+        Some(Row::Settled) => render_settled(frame, detail_area, state),
+        _ => render_detail(frame, detail_area, state.selected(), state.warning_scroll),
+    }
+
+    frame.render_widget(
+        Line::from(format!(
+            " ↑/↓ select   enter {} finished   s sort   x cancel selected   p pause/resume   q cancel all",
+            if state.expanded { "collapse" } else { "expand" }
+        ))
+        .dim(),
+        footer_area,
+    );
+}
+
+fn render_header(frame: &mut Frame, area: Rect, state: &MonitorState) {
+    let total = state.order.len();
+    let finished = state.processes().filter(|p| p.finished).count();
+    let failed = state.processes().filter(|p| p.failed).count();
+    let cancelled = state.processes().filter(|p| p.cancelled).count();
+    let queued = state
+        .processes()
+        .filter(|p| p.queued && !p.settled())
+        .count();
+    let running = total - finished - failed - cancelled - queued;
+    let overall_pct = state.overall_pct();
+
+    let cancelled = if cancelled > 0 {
+        format!(" · {cancelled} cancelled")
+    } else {
+        String::new()
+    };
+    let mut status = vec![Span::raw(format!(
+        "{queued} queued · {running} running · {finished} finished · {failed} failed{cancelled} · {total} total"
+    ))];
+    if let Some(started) = state.started {
+        let elapsed = started.elapsed().as_secs_f64();
+        // Tasks still waiting to be probed aren't weighed yet, so this is rough early on
+        let eta = (overall_pct > 0.0 && overall_pct < 100.0)
+            .then(|| elapsed * (100.0 - overall_pct) / overall_pct)
+            .map(|eta| format!(", ETA ~{}", format_duration(eta)))
+            .unwrap_or_default();
+        status.push(Span::raw(format!(
+            " · {} elapsed{eta}",
+            format_duration(elapsed)
+        )));
+    }
+    if state.cancelling {
+        status.push(Span::raw("   "));
+        status.push(Span::styled(
+            "Cancelling…",
+            theme::style(Status::Failure).bold(),
+        ));
+    } else if pause::is_paused() {
+        status.push(Span::raw("   "));
+        status.push(Span::styled(
+            "⏸ Paused (p to resume)",
+            theme::style(Status::Warning).bold(),
+        ));
+    }
+
+    let block = Block::bordered().title(" stitch ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let [status_area, gauge_area] =
+        Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(inner);
+    frame.render_widget(Line::from(status), status_area);
+    frame.render_widget(
+        Gauge::default()
+            .ratio((overall_pct / 100.0).clamp(0.0, 1.0))
+            .label(format!("{overall_pct:.1}%"))
+            .gauge_style(theme::style(Status::Progress)),
+        gauge_area,
+    );
+}
+
+fn render_list(frame: &mut Frame, area: Rect, state: &mut MonitorState) {
+    // Borders, the highlight symbol, and the icon and percentage before the name
+    let name_width = (area.width as usize).saturating_sub(2 + 2 + 9);
+
+    let items: Vec<ListItem> = state
+        .rows()
+        .into_iter()
+        .filter_map(|row| {
+            let id = match row {
+                Row::Task(id) => id,
+                Row::Settled => {
+                    let (finished, failed, cancelled) = state.settled().fold(
+                        (0, 0, 0),
+                        |(finished, failed, cancelled), (_, process)| {
+                            if process.failed {
+                                (finished, failed + 1, cancelled)
+                            } else if process.cancelled {
+                                (finished, failed, cancelled + 1)
+                            } else {
+                                (finished + 1, failed, cancelled)
+                            }
+                        },
+                    );
+                    let style = theme::style(if failed > 0 {
+                        Status::Failure
+                    } else {
+                        Status::Success
+                    });
+                    let cancelled = if cancelled > 0 {
+                        format!(" · ⊘ {cancelled} cancelled")
+                    } else {
+                        String::new()
+                    };
+                    return Some(
+                        ListItem::new(Line::from(format!(
+                            "✓ {finished} finished · ✗ {failed} failed{cancelled}"
+                        )))
+                        .style(style),
+                    );
+                }
+            };
+            let process = state.processes.get(&id)?;
+            // The rate goes after the name, which gives up the room for it
+            let rate = process
+                .rate()
+                .map(|rate| format!("  {rate}"))
+                .unwrap_or_default();
+            let item = ListItem::new(Line::from(vec![
+                Span::raw(format!(
+                    "{} {:>5.1}% {}",
+                    process.icon(),
+                    process.progress_pct,
+                    truncate_middle(&process.name, name_width.saturating_sub(rate.width()))
+                )),
+                Span::raw(rate).dim(),
+            ]))
+            .style(process.style());
+            Some(item)
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(Block::bordered().title(format!(" Targets by {} ", state.sort_order.label())))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+
+    frame.render_stateful_widget(list, area, &mut state.list_state);
+}
+
+/// One line per settled task, for when they share a row in the list
+fn render_settled(frame: &mut Frame, area: Rect, state: &MonitorState) {
+    let block = Block::bordered().title(" Settled targets (enter to expand) ");
+    let width = block.inner(area).width as usize;
+    let lines: Vec<Line> = state
+        .settled()
+        .map(|(_, process)| {
+            Line::from(truncate_middle(&process.status_line(), width)).style(process.style())
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// `warning_scroll` is how many warnings back from the latest the warnings pane is scrolled
+fn render_detail(
+    frame: &mut Frame,
+    area: Rect,
+    process: Option<&ProcessState>,
+    warning_scroll: usize,
+) {
+    let Some(process) = process else {
+        frame.render_widget(Block::bordered().title(" Waiting for targets "), area);
+        return;
+    };
+
+    let block = Block::bordered()
+        .title(format!(
+            " {} ",
+            truncate_middle(&process.name, (area.width as usize).saturating_sub(4))
+        ))
+        .border_style(process.style());
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let warning_rows = process.warnings.len().min(WARNING_ROWS);
+    let [gauge_area, info_area, warnings_area, log_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(8),
+        // Borders, only while there's something to show
+        Constraint::Length(if warning_rows > 0 {
+            warning_rows as u16 + 2
+        } else {
+            0
+        }),
+        Constraint::Min(0),
+    ])
+    .areas(inner);
+
+    frame.render_widget(
+        Gauge::default()
+            .ratio((process.progress_pct / 100.0).clamp(0.0, 1.0))
+            .label(format!("{:.1}%", process.progress_pct))
+            .gauge_style(process.style().patch(theme::style(Status::Progress))),
+        gauge_area,
+    );
+
+    let mut info = vec![
+        Line::from(format!(
+            "Phase: {}",
+            process.phase.as_deref().unwrap_or("-")
+        )),
+        Line::from(format!("Time: {}", process.time())),
+        Line::from(format!(
+            "Speed: {}",
+            process.rate().as_deref().unwrap_or("-")
+        )),
+        Line::from(format!(
+            "Output: {}",
+            process.output().as_deref().unwrap_or("-")
+        )),
+    ];
+    if let Some(error) = &process.error {
+        info.push(Line::from(format!("❌ {error}")).style(theme::style(Status::Failure)));
+    }
+    if let Some(command_line) = &process.command_line {
+        info.push(Line::from(format!("$ {command_line}")).dim());
+    }
+    frame.render_widget(Paragraph::new(info).wrap(Wrap { trim: false }), info_area);
+
+    if warning_rows > 0 {
+        let scroll = warning_scroll.min(process.warnings.len() - warning_rows);
+        let end = process.warnings.len() - scroll;
+        let lines: Vec<Line> = process.warnings[end - warning_rows..end]
+            .iter()
+            .map(|warning| {
+                Line::from(format!("⚠️  {warning}")).style(theme::style(Status::Warning))
+            })
+            .collect();
+        let title = if process.warnings.len() > warning_rows {
+            format!(
+                " Warnings {}-{} of {} ([/] to scroll) ",
+                end - warning_rows + 1,
+                end,
+                process.warnings.len()
+            )
+        } else {
+            " Warnings ".to_string()
+        };
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            warnings_area,
+        );
+    }
+
+    let log_block = Block::bordered().title(" ffmpeg log ");
+    let visible = log_block.inner(log_area).height as usize;
+    let lines: Vec<Line> = process
+        .log
+        .iter()
+        .skip(process.log.len().saturating_sub(visible))
+        .map(|line| Line::from(line.as_str()))
+        .collect();
+    frame.render_widget(Paragraph::new(lines).block(log_block), log_area);
+}
+
+/// Crossterm's event reading blocks, so key presses and resizes are read on their own thread and forwarded
+fn read_input(tx: tokio::sync::mpsc::UnboundedSender<Event>, stop: CancellationToken) {
+    while !stop.is_cancelled() {
+        match event::poll(INPUT_POLL_INTERVAL) {
+            Ok(false) => continue,
+            Ok(true) => {}
+            Err(e) => {
+                tracing::warn!(error =% e, error_context =? e, "Failed to poll for input, keyboard controls are unavailable");
+                return;
+            }
+        }
+
+        match event::read() {
+            Ok(
+                event @ Event::Key(KeyEvent {
+                    kind: KeyEventKind::Press,
+                    ..
+                }),
+            )
+            | Ok(event @ Event::Resize(..)) => {
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(error =% e, error_context =? e, "Failed to read input, keyboard controls are unavailable");
+                return;
+            }
+        }
+    }
+}
+
+/// `plan_tokens` are each plan's token by target name, for cancelling the selected plan
+pub async fn monitor(
+    mut rx: tokio::sync::mpsc::Receiver<ExecuteProgress>,
+    verbose: bool,
+    cancellation_token: CancellationToken,
+    plan_tokens: HashMap<String, CancellationToken>,
+    history: Arc<ThroughputHistory>,
+) {
+    let mut state = MonitorState::new(history);
+
+    // Logs go to the terminal in verbose mode, so there is nothing to draw on
+    if verbose {
+        while let Some(delivery) = rx.recv().await {
+            state.apply(delivery);
+        }
+        return;
+    }
+
+    let mut terminal = ratatui::init();
+
+    let (input_tx, mut input_rx) = tokio::sync::mpsc::unbounded_channel();
+    let stop_input = CancellationToken::new();
+    let input_thread = {
+        let stop_input = stop_input.clone();
+        std::thread::spawn(move || read_input(input_tx, stop_input))
+    };
+
+    let mut ticks = tokio::time::interval(TICK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            delivery = rx.recv() => match delivery {
+                Some(delivery) => state.apply(delivery),
+                None => break,
+            },
+            Some(event) = input_rx.recv() => {
+                // Resizes only need the redraw below, which picks up the new size
+                if let Event::Key(key) = event {
+                    state.handle_key(key, &cancellation_token, &plan_tokens);
+                }
+            }
+            _ = ticks.tick() => {}
+        }
+
+        if let Err(e) = terminal.draw(|frame| render(frame, &mut state)) {
+            tracing::error!(error =% e, error_context =? e, "Failed to draw the monitor");
+        }
+    }
+
+    stop_input.cancel();
+    let _ = input_thread.join();
+    ratatui::restore();
+
+    // Final display
+    let width = crossterm::terminal::size()
+        .map(|(width, _)| width)
+        .unwrap_or(FALLBACK_TERMINAL_WIDTH);
+    print!("{}", render_compact(&state, width as usize));
+    let _ = stdout().flush();
+}
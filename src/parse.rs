@@ -1,4 +1,9 @@
-use std::{collections::HashSet, io::BufRead, path::PathBuf};
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
 
 use lazy_static::lazy_static;
 use liberror::AnyError;
@@ -10,29 +15,354 @@ use valuable::Valuable;
 
 lazy_static! {
     static ref RE_TARGET: Regex =
-        Regex::new(r#"^(.+):(.*)$"#).expect("Failed to compile RE_TARGET");
+        Regex::new(r#"^(\S.*):(.*)$"#).expect("Failed to compile RE_TARGET");
     static ref RE_SOURCE: Regex = Regex::new(r#"^\s+(.+)$"#).expect("Failed to compile RE_SOURCE");
+    static ref RE_FRAME_NUMBER: Regex =
+        Regex::new(r#"%0?\d*d"#).expect("Failed to compile RE_FRAME_NUMBER");
+    static ref RE_VIDEO_STREAM: Regex =
+        Regex::new(r#"^(.+?)\s+v:(\S+)$"#).expect("Failed to compile RE_VIDEO_STREAM");
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Valuable, strum::EnumString)]
+/// Starts the optional first line naming the spec's version, e.g. `#stitch-spec v2`
+const SPEC_HEADER: &str = "#stitch-spec";
+
+/// Versions of the spec format. Specs without a header are v1, and keep parsing exactly as they always have
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum SpecVersion {
+    #[default]
+    V1,
+    /// Lines starting with `#` are comments
+    V2,
+}
+impl SpecVersion {
+    /// Reads a header line, `None` when the line isn't a header
+    fn from_header(line: &str) -> Result<Option<Self>, ParseError> {
+        let Some(given) = line.trim().strip_prefix(SPEC_HEADER) else {
+            return Ok(None);
+        };
+        match given.trim() {
+            "v1" => Ok(Some(SpecVersion::V1)),
+            "v2" => Ok(Some(SpecVersion::V2)),
+            given => Err(ParseError::UnsupportedVersion {
+                version: given.to_string(),
+            }),
+        }
+    }
+
+    fn header(self) -> String {
+        match self {
+            SpecVersion::V1 => format!("{SPEC_HEADER} v1"),
+            SpecVersion::V2 => format!("{SPEC_HEADER} v2"),
+        }
+    }
+}
+
+/// Containers that hold audio alone, stitched without a video stream
+const AUDIO_EXTENSIONS: &[&str] = &["wav", "mp3", "flac", "m4a", "aac", "ogg", "opus"];
+
+/// MPEG-TS sources, which can be joined byte for byte with the concat protocol
+const MPEG_TS_EXTENSIONS: &[&str] = &["ts", "mts", "m2ts"];
+
+/// Matroska files, the only containers that carry attachments like subtitle fonts
+const MATROSKA_EXTENSIONS: &[&str] = &["mkv", "mka", "mks"];
+
+/// MP4 style containers, whose index can be moved to the front or fragmented for streaming
+const MP4_EXTENSIONS: &[&str] = &["mp4", "m4v", "m4a", "mov"];
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    Valuable,
+    strum::EnumString,
+    strum::IntoStaticStr,
+)]
+#[serde(rename_all = "kebab-case")]
 pub enum Flag {
     #[strum(serialize = "concat-filter", serialize = "catf")]
     ConcatFilter,
+    /// Transcode each source to a uniform intermediate, then concat copy those.
+    /// Also spelled `segment-parallel`, since it spreads one large target's encode over many processes
+    #[strum(
+        serialize = "mezzanine",
+        serialize = "mezz",
+        serialize = "segment-parallel",
+        serialize = "segp"
+    )]
+    #[serde(alias = "segment-parallel")]
+    Mezzanine,
+    /// Join MPEG-TS sources with the `concat:` protocol, e.g. HLS segments
+    #[strum(serialize = "concat-protocol", serialize = "catp")]
+    ConcatProtocol,
+    /// Regenerate missing presentation timestamps when copying, `-fflags +genpts`
+    #[strum(serialize = "gen-pts", serialize = "genpts")]
+    GenPts,
+    /// Shift the output to start at zero when copying, `-avoid_negative_ts make_zero`
+    #[strum(serialize = "make-zero", serialize = "zero")]
+    MakeZero,
+    /// Both `gen-pts` and `make-zero`, for camera files with broken timestamps
+    #[strum(serialize = "fix-timestamps", serialize = "fixts")]
+    FixTimestamps,
+    /// Copy data streams through, e.g. GoPro GPS and gyro telemetry, `-map 0:d? -copy_unknown`
+    #[strum(serialize = "keep-data", serialize = "data")]
+    KeepData,
+    /// Trim leading and trailing black video and silence off each source, e.g. security camera and screen recordings
+    #[strum(serialize = "auto-trim", serialize = "@auto-trim")]
+    AutoTrim,
+    /// Trim like `auto-trim` while still copying, cutting each source with the concat demuxer's `inpoint` and `outpoint`.
+    /// Copied cuts snap back to the keyframe before them
+    #[strum(serialize = "copy-trim", serialize = "@copy-trim")]
+    CopyTrim,
+}
+
+impl Flag {
+    /// Whether `flags` asks for timestamps to be regenerated when copying
+    pub fn gen_pts(flags: &[Flag]) -> bool {
+        flags
+            .iter()
+            .any(|flag| matches!(flag, Flag::GenPts | Flag::FixTimestamps))
+    }
+
+    /// Whether `flags` asks for the output to start at zero when copying
+    pub fn make_zero(flags: &[Flag]) -> bool {
+        flags
+            .iter()
+            .any(|flag| matches!(flag, Flag::MakeZero | Flag::FixTimestamps))
+    }
 }
 
-#[derive(Debug, Clone, Valuable)]
+#[derive(Debug, Clone, Serialize, Valuable)]
 pub struct Plan {
     pub target_path: PlanPath,
     pub flags: Vec<Flag>,
     pub sources: Vec<PlanPath>,
+    /// Seconds to fade in at the start and out at the end, given as `fade=N` with the flags
+    pub fade: Option<f64>,
+}
+
+impl Plan {
+    /// The target is an audio container, so only audio streams are stitched
+    pub fn is_audio(&self) -> bool {
+        self.target_path.is_audio()
+    }
+
+    /// A single source going into a different container is copied straight across, no concatenation needed.
+    /// Audio codecs rarely fit another audio container, so audio targets are always concatenated
+    pub fn is_remux(&self) -> bool {
+        let extension = |path: &PathBuf| {
+            path.extension()
+                .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        };
+
+        match self.sources.as_slice() {
+            [source] => {
+                !self.is_audio()
+                    && !self.is_concat_protocol()
+                    && !self.flags.contains(&Flag::ConcatFilter)
+                    && !self.flags.contains(&Flag::CopyTrim)
+                    && extension(&source.path) != extension(&self.target_path.path)
+            }
+            _ => false,
+        }
+    }
+
+    /// How the plan is stitched, as reported in its `Info` delivery
+    pub fn mode(&self) -> &'static str {
+        if self.flags.contains(&Flag::ConcatFilter) {
+            "filter_complex"
+        } else if self.flags.contains(&Flag::Mezzanine) {
+            "mezzanine"
+        } else if self.is_remux() {
+            "remux"
+        } else if self.is_concat_protocol() {
+            "concat_protocol"
+        } else {
+            "concat"
+        }
+    }
+
+    /// Whether stitching encodes video rather than copying it
+    pub fn is_encode(&self) -> bool {
+        self.flags.contains(&Flag::ConcatFilter) || self.flags.contains(&Flag::Mezzanine)
+    }
+
+    /// MPEG-TS sources joined with the concat protocol, unless the concat filter takes over or `copy-trim` needs the
+    /// demuxer to cut them
+    pub fn is_concat_protocol(&self) -> bool {
+        self.flags.contains(&Flag::ConcatProtocol)
+            && !self.flags.contains(&Flag::ConcatFilter)
+            && !self.flags.contains(&Flag::CopyTrim)
+    }
+
+    /// Whether black video and silence are trimmed off the sources, exactly with the concat filter or at keyframes when copying
+    pub fn is_trimmed(&self) -> bool {
+        self.flags.contains(&Flag::AutoTrim) || self.flags.contains(&Flag::CopyTrim)
+    }
+
+    /// Fades are applied in the filter graph, so they switch on the concat filter
+    fn set_fade(&mut self, seconds: f64) {
+        if !self.flags.contains(&Flag::ConcatFilter) {
+            tracing::debug!(
+                target_name = self.target_path.leaf,
+                "Using the concat filter to fade"
+            );
+            self.flags.push(Flag::ConcatFilter);
+        }
+        self.fade = Some(seconds);
+    }
+
+    /// Sources are cut at any frame, not just keyframes, so trimming switches on the concat filter
+    fn set_auto_trim(&mut self) {
+        if !self.flags.contains(&Flag::AutoTrim) {
+            self.flags.push(Flag::AutoTrim);
+        }
+        if !self.flags.contains(&Flag::ConcatFilter) {
+            tracing::debug!(
+                target_name = self.target_path.leaf,
+                "Using the concat filter to trim"
+            );
+            self.flags.push(Flag::ConcatFilter);
+        }
+    }
+
+    /// Image sequences, audio files in a video target, and sources using different video streams can only be stitched
+    /// with the concat filter, so they switch it on. Intermediates are uniform already, so mezzanine plans are left as they are
+    fn push_source(&mut self, source: PlanPath) {
+        let other_video_stream = self.sources.first().is_some_and(|first| {
+            first.video_stream.unwrap_or_default() != source.video_stream.unwrap_or_default()
+        });
+        let needs_filter = source.framerate.is_some()
+            || (source.is_audio() && !self.is_audio())
+            || other_video_stream;
+        if needs_filter
+            && !self.flags.contains(&Flag::ConcatFilter)
+            && !self.flags.contains(&Flag::Mezzanine)
+        {
+            tracing::debug!(
+                target_name = self.target_path.leaf,
+                source_name = source.leaf,
+                "Using the concat filter for a source the concat demuxer can't take"
+            );
+            self.flags.push(Flag::ConcatFilter);
+        }
+        self.sources.push(source);
+    }
 }
 
-#[derive(Debug, Clone, Valuable)]
+#[derive(Debug, Clone, Serialize, Valuable)]
 pub struct PlanPath {
     pub path: PathBuf,
     pub leaf: String,
+    /// Set for image sequence sources, given as e.g. `frames/%05d.png @fps 24`
+    pub framerate: Option<f64>,
+    /// Which of the source's video streams to use, given as e.g. `multicam.mkv v:1`. The first when unset
+    pub video_stream: Option<usize>,
 }
 impl PlanPath {
+    /// A source line, either a file, a file followed by `v:<N>` to pick its video stream, or an image sequence pattern followed by `@fps <N>`
+    pub fn new_source(from: &str, relative_to: PathBuf) -> Result<Self, ParseError> {
+        // The selector stays in the name, so two streams of one file are told apart
+        if let Some(caps) = RE_VIDEO_STREAM.captures(from) {
+            let video_stream =
+                caps[2]
+                    .parse::<usize>()
+                    .map_err(|_| ParseError::InvalidVideoStream {
+                        source_name: from.to_string(),
+                        video_stream: caps[2].to_string(),
+                    })?;
+            return Ok(Self {
+                leaf: from.to_string(),
+                video_stream: Some(video_stream),
+                ..Self::new_relative_to(caps[1].trim(), relative_to)?
+            });
+        }
+
+        let Some((pattern, framerate)) = from.rsplit_once("@fps") else {
+            return Self::new_relative_to(from, relative_to);
+        };
+
+        let framerate = framerate
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|framerate| framerate.is_finite() && *framerate > 0.0)
+            .ok_or_else(|| ParseError::InvalidFramerate {
+                source_name: from.to_string(),
+                framerate: framerate.trim().to_string(),
+            })?;
+
+        Ok(Self {
+            framerate: Some(framerate),
+            ..Self::new_relative_to(pattern.trim(), relative_to)?
+        })
+    }
+
+    pub fn is_audio(&self) -> bool {
+        self.has_extension(AUDIO_EXTENSIONS)
+    }
+
+    pub fn is_mpeg_ts(&self) -> bool {
+        self.has_extension(MPEG_TS_EXTENSIONS)
+    }
+
+    pub fn is_matroska(&self) -> bool {
+        self.has_extension(MATROSKA_EXTENSIONS)
+    }
+
+    pub fn is_mp4(&self) -> bool {
+        self.has_extension(MP4_EXTENSIONS)
+    }
+
+    /// The ffmpeg stream specifier for this source's video when it's input number `input`
+    pub fn video_specifier(&self, input: usize) -> String {
+        format!("{input}:v:{}", self.video_stream.unwrap_or_default())
+    }
+
+    fn has_extension(&self, extensions: &[&str]) -> bool {
+        self.path.extension().is_some_and(|extension| {
+            extensions.contains(&&*extension.to_string_lossy().to_ascii_lowercase())
+        })
+    }
+
+    /// Files on disk matching an image sequence's pattern, where `%d` or `%0Nd` stands for the frame number
+    pub fn count_frames(&self) -> usize {
+        let (Some(dir), Some(pattern)) = (
+            self.path.parent(),
+            self.path.file_name().and_then(|name| name.to_str()),
+        ) else {
+            return 0;
+        };
+
+        let Ok(re_pattern) = Regex::new(&format!(
+            "^{}$",
+            RE_FRAME_NUMBER
+                .split(pattern)
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(r"\d+")
+        )) else {
+            return 0;
+        };
+
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| {
+                        entry
+                            .file_name()
+                            .to_str()
+                            .is_some_and(|name| re_pattern.is_match(name))
+                    })
+                    .count()
+            })
+            .unwrap_or_default()
+    }
+
     pub fn new_relative_to(from: &str, relative_to: PathBuf) -> Result<Self, ParseError> {
         let given_path = PathBuf::from(from);
         let path = if given_path.is_absolute() {
@@ -54,20 +384,58 @@ impl PlanPath {
                     inner_error: e.into(),
                 })?;
 
-            base.join(from)
+            // Joining component by component keeps separators native, `/` isn't a separator after a `\\?\` prefix
+            given_path
+                .components()
+                .fold(base, |path, component| path.join(component))
         };
 
         Ok(Self {
-            path,
+            path: without_verbatim_prefix(path),
             leaf: from.to_string(),
+            framerate: None,
+            video_stream: None,
         })
     }
 }
 
+/// Paths longer than this need the `\\?\` prefix on Windows
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Canonicalizing on Windows adds a `\\?\` prefix that plenty of tools (ffmpeg's concat demuxer included) choke on.
+/// Drops it unless the path is too long to work without it
+#[cfg(windows)]
+fn without_verbatim_prefix(path: PathBuf) -> PathBuf {
+    let Some(raw) = path.to_str() else {
+        return path;
+    };
+    if raw.len() >= MAX_PATH {
+        return path;
+    }
+
+    if let Some(unc) = raw.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{unc}"))
+    } else if let Some(local) = raw.strip_prefix(r"\\?\")
+        && local.as_bytes().get(1) == Some(&b':')
+    {
+        PathBuf::from(local)
+    } else {
+        path
+    }
+}
+
+#[cfg(not(windows))]
+fn without_verbatim_prefix(path: PathBuf) -> PathBuf {
+    path
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Valuable, Error)]
 pub enum ParseError {
     #[error("Unknown flag \"{flag}\"")]
     InvalidFlag { flag: String },
+    #[error("Invalid fade \"{fade}\", expected a number of seconds above 0")]
+    InvalidFade { fade: String },
     #[error("Failed to locate spec at \"{path}\": {inner_error}")]
     SpecNotFound { path: String, inner_error: AnyError },
     #[error("Failed to open spec at \"{path}\": {inner_error}")]
@@ -82,14 +450,35 @@ pub enum ParseError {
         src: String,
         target: String,
     },
+    #[error("Invalid framerate \"{framerate}\" for image sequence \"{source_name}\"")]
+    InvalidFramerate {
+        source_name: String,
+        framerate: String,
+    },
+    #[error("Invalid video stream \"{video_stream}\" for \"{source_name}\", expected e.g. v:1")]
+    InvalidVideoStream {
+        source_name: String,
+        video_stream: String,
+    },
     #[error("No sources defined for target \"{target_name}\"")]
     MissingSources { target_name: String },
+    #[error("Found more than one file for \"{source_name}\": {}", candidates.join(", "))]
+    AmbiguousSource {
+        source_name: String,
+        candidates: Vec<String>,
+    },
     #[error("Unknown target for source file \"{source_name}\"")]
     MissingTarget { source_name: String },
     #[error("Validation failed")]
     Validation { errors: Vec<ValidationError> },
     #[error("Unable to parse line: \"{line}\"")]
     InvalidLine { line: String },
+    #[error("{} lines failed to parse", errors.len())]
+    Lines { errors: Vec<LineError> },
+    #[error("Unsupported spec version \"{version}\", expected v1 or v2")]
+    UnsupportedVersion { version: String },
+    #[error("{} lints found with --strict", lints.len())]
+    Lints { lints: Vec<Lint> },
     #[error("Unable to create base directory {base} for {from}: {inner_error}")]
     CreateBaseDir {
         from: String,
@@ -104,6 +493,108 @@ pub enum ParseError {
     },
 }
 
+impl ParseError {
+    /// Stable across releases for scripts to branch on, codes aren't reused once a variant is removed. Spec errors are 1xx
+    pub fn code(&self) -> u16 {
+        match self {
+            ParseError::InvalidFlag { .. } => 101,
+            ParseError::InvalidFade { .. } => 102,
+            ParseError::SpecNotFound { .. } => 103,
+            ParseError::Open { .. } => 104,
+            ParseError::ReadLine { .. } => 105,
+            ParseError::UnexpectedSourceAndTarget { .. } => 106,
+            ParseError::InvalidFramerate { .. } => 107,
+            ParseError::InvalidVideoStream { .. } => 108,
+            ParseError::MissingSources { .. } => 109,
+            ParseError::AmbiguousSource { .. } => 110,
+            ParseError::MissingTarget { .. } => 111,
+            ParseError::Validation { .. } => 112,
+            ParseError::InvalidLine { .. } => 113,
+            ParseError::Lines { .. } => 114,
+            ParseError::UnsupportedVersion { .. } => 115,
+            ParseError::Lints { .. } => 116,
+            ParseError::CreateBaseDir { .. } => 117,
+            ParseError::InvalidPath { .. } => 118,
+        }
+    }
+}
+
+impl ValidationError {
+    /// Stable across releases like [ParseError::code], validation errors are 2xx
+    pub fn code(&self) -> u16 {
+        match self {
+            ValidationError::DuplicateSource { .. } => 201,
+            ValidationError::MissingSource { .. } => 202,
+            ValidationError::DuplicateTarget { .. } => 203,
+            ValidationError::TargetOverwritesSource { .. } => 204,
+            ValidationError::DuplicateTargetAcrossSpecs { .. } => 205,
+            ValidationError::EmptyImageSequence { .. } => 206,
+            ValidationError::ConcatProtocolSource { .. } => 207,
+            ValidationError::ImageSequenceForAudioTarget { .. } => 208,
+        }
+    }
+
+    /// The target the error is about, and its source when it's about one
+    pub fn subject(&self) -> (&str, Option<&str>) {
+        match self {
+            ValidationError::DuplicateTarget { target_name }
+            | ValidationError::DuplicateTargetAcrossSpecs { target_name, .. } => {
+                (target_name, None)
+            }
+            ValidationError::DuplicateSource {
+                source_name,
+                target_name,
+            }
+            | ValidationError::MissingSource {
+                source_name,
+                target_name,
+                ..
+            }
+            | ValidationError::TargetOverwritesSource {
+                source_name,
+                target_name,
+            }
+            | ValidationError::EmptyImageSequence {
+                source_name,
+                target_name,
+            }
+            | ValidationError::ConcatProtocolSource {
+                source_name,
+                target_name,
+            }
+            | ValidationError::ImageSequenceForAudioTarget {
+                source_name,
+                target_name,
+            } => (target_name, Some(source_name)),
+        }
+    }
+}
+
+/// A line of the spec that failed to parse
+#[derive(Debug, Clone, Serialize, Deserialize, Valuable, Error)]
+#[error("Line {line_number}: {error}")]
+pub struct LineError {
+    pub line_number: usize,
+    pub error: Box<ParseError>,
+}
+
+/// Suspicious but valid spec contents, warned about, or rejected with `--strict`
+#[derive(Debug, Clone, Serialize, Deserialize, Valuable, Error)]
+pub enum Lint {
+    #[error("Flag \"{flag}\" is given more than once for target \"{target_name}\"")]
+    DuplicateFlag { flag: String, target_name: String },
+    #[error(
+        "Target \"{target_name}\" has a single source in the same container, so it's only a copy of it"
+    )]
+    CopyOfSource { target_name: String },
+    #[error("Line {line_number} is indented with {found} where earlier sources use {expected}")]
+    MixedIndentation {
+        line_number: usize,
+        found: String,
+        expected: String,
+    },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Valuable, Error)]
 pub enum ValidationError {
     #[error("Duplicate source \"{source_name}\" for target \"{target_name}\"")]
@@ -112,16 +603,47 @@ pub enum ValidationError {
         target_name: String,
     },
     #[error(
-        "Failed to resolve source file \"{source_name}\" at \"{source_path}\" for target \"{target_name}\": {inner_error}"
+        "Failed to resolve source file \"{source_name}\" at \"{source_path}\" for target \"{target_name}\": {inner_error}{}",
+        did_you_mean(suggestions)
     )]
     MissingSource {
         source_name: String,
         source_path: String,
         target_name: String,
         inner_error: AnyError,
+        /// Similarly named files next to where the source was expected
+        suggestions: Vec<String>,
     },
     #[error("Duplicate target \"{target_name}\"")]
     DuplicateTarget { target_name: String },
+    #[error("Target \"{target_name}\" would overwrite its source \"{source_name}\"")]
+    TargetOverwritesSource {
+        source_name: String,
+        target_name: String,
+    },
+    #[error("Target \"{target_name}\" is in both {first_spec} and {second_spec}")]
+    DuplicateTargetAcrossSpecs {
+        target_name: String,
+        first_spec: String,
+        second_spec: String,
+    },
+    #[error("No frames match image sequence \"{source_name}\" for target \"{target_name}\"")]
+    EmptyImageSequence {
+        source_name: String,
+        target_name: String,
+    },
+    #[error(
+        "Source \"{source_name}\" for target \"{target_name}\" can't be joined with the concat protocol, it needs MPEG-TS sources without a | in their path"
+    )]
+    ConcatProtocolSource {
+        source_name: String,
+        target_name: String,
+    },
+    #[error("Image sequence \"{source_name}\" can't go into audio target \"{target_name}\"")]
+    ImageSequenceForAudioTarget {
+        source_name: String,
+        target_name: String,
+    },
 }
 
 fn get_spec_reader(
@@ -151,12 +673,147 @@ fn try_get_nth_capture(line: &str, regex: &Regex, n: usize) -> Result<Option<Str
     Ok(caps.get(n).map(|c| c.as_str().trim().to_string()))
 }
 
+/// Checks for duplicate targets, duplicate sources and sources that don't exist
+pub fn validate_plans(plans: &[Plan]) -> Result<(), ParseError> {
+    tracing::info!(plans = plans.as_value(), "Validating targets");
+
+    let mut validation_errors = vec![];
+
+    let mut sources_set = HashSet::new();
+    let mut targets_set = HashSet::new();
+    for plan in plans.iter() {
+        if targets_set.contains(&plan.target_path.leaf) {
+            tracing::error!(
+                target_name = plan.target_path.leaf,
+                "Found duplicate target"
+            );
+
+            validation_errors.push(ValidationError::DuplicateTarget {
+                target_name: plan.target_path.leaf.clone(),
+            })
+        } else {
+            targets_set.insert(&plan.target_path.leaf);
+        }
+
+        // Symlinks and differently cased names on case insensitive filesystems resolve to the same file
+        let resolve = |path: &PathBuf| path.canonicalize().unwrap_or_else(|_| path.clone());
+        let target_path = resolve(&plan.target_path.path);
+
+        sources_set.clear();
+        sources_set.reserve(plan.sources.len());
+        for source in plan.sources.iter() {
+            // ffmpeg runs with -y, so it would write over the source while reading it
+            if resolve(&source.path).starts_with(&target_path) {
+                tracing::error!(
+                    target_name = plan.target_path.leaf,
+                    source_name = source.leaf,
+                    "Found target that would overwrite its source"
+                );
+                validation_errors.push(ValidationError::TargetOverwritesSource {
+                    source_name: source.leaf.clone(),
+                    target_name: plan.target_path.leaf.clone(),
+                });
+            }
+
+            if sources_set.contains(&source.leaf) {
+                tracing::error!(
+                    target_name = plan.target_path.leaf,
+                    source_name = source.leaf,
+                    "Found duplicate source"
+                );
+                validation_errors.push(ValidationError::DuplicateSource {
+                    source_name: source.leaf.clone(),
+                    target_name: plan.target_path.leaf.clone(),
+                })
+            } else {
+                sources_set.insert(&source.leaf);
+            }
+
+            if plan.is_concat_protocol()
+                && (!source.is_mpeg_ts() || source.path.to_string_lossy().contains('|'))
+            {
+                tracing::error!(
+                    target_name = plan.target_path.leaf,
+                    source_name = source.leaf,
+                    "Found source the concat protocol can't join"
+                );
+                validation_errors.push(ValidationError::ConcatProtocolSource {
+                    source_name: source.leaf.clone(),
+                    target_name: plan.target_path.leaf.clone(),
+                });
+            }
+
+            if plan.is_audio() && source.framerate.is_some() {
+                tracing::error!(
+                    target_name = plan.target_path.leaf,
+                    source_name = source.leaf,
+                    "Found image sequence for audio target"
+                );
+                validation_errors.push(ValidationError::ImageSequenceForAudioTarget {
+                    source_name: source.leaf.clone(),
+                    target_name: plan.target_path.leaf.clone(),
+                });
+            }
+
+            if source.framerate.is_some() {
+                if source.count_frames() == 0 {
+                    tracing::error!(
+                        target_name = plan.target_path.leaf,
+                        source_name = source.leaf,
+                        "No frames match image sequence"
+                    );
+                    validation_errors.push(ValidationError::EmptyImageSequence {
+                        source_name: source.leaf.clone(),
+                        target_name: plan.target_path.leaf.clone(),
+                    });
+                }
+            } else if let Err(e) = source.path.canonicalize() {
+                tracing::error!(
+                    target_name = plan.target_path.leaf,
+                    source_name = source.leaf,
+                    error =% e,
+                    error_context =? e,
+                    "Source file not found"
+                );
+                validation_errors.push(ValidationError::MissingSource {
+                    source_name: source.leaf.clone(),
+                    source_path: source.path.display().to_string(),
+                    target_name: plan.target_path.leaf.clone(),
+                    inner_error: e.into(),
+                    suggestions: suggest_sources(source),
+                })
+            }
+        }
+    }
+
+    if !validation_errors.is_empty() {
+        return Err(ParseError::Validation {
+            errors: validation_errors,
+        });
+    }
+
+    Ok(())
+}
+
+/// How a spec is parsed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    /// Turns lints into an error
+    pub strict: bool,
+    /// Looks for sources missing from the sources directory in its subdirectories
+    pub recursive_sources: bool,
+    /// Matches sources to files whose names only differ in case, e.g. `clip.mp4` to `CLIP.MP4`
+    pub case_insensitive_sources: bool,
+}
+
+/// Parses and validates the spec at `spec_path`, returning its plans along with any lints
 #[instrument(level = Level::INFO)]
 pub fn parse_spec(
     spec_path: PathBuf,
     target_dir: PathBuf,
     sources_dir: PathBuf,
-) -> Result<Vec<Plan>, ParseError> {
+    options: ParseOptions,
+) -> Result<(Vec<Plan>, Vec<Lint>), ParseError> {
     let spec_path_raw = spec_path.display().to_string();
     tracing::debug!(given_path = spec_path_raw, "Canonicalizing spec path");
 
@@ -174,117 +831,239 @@ pub fn parse_spec(
 
     let mut plans = Vec::new();
     let mut plan: Option<Plan> = None;
+    let mut version = SpecVersion::default();
+    let mut lints = Vec::new();
+    let mut errors = Vec::new();
+    let mut target_line_number = 0;
+    let mut skip_sources = false;
+    // Every `{date}` and `{time}` in the spec is the time it was parsed
+    let now = local_date_time();
+    let mut numbered = HashSet::new();
+    // Listed on the first source that isn't found directly under the sources directory
+    let mut nested_files = None;
+    // Whatever the first source is indented with, the rest should match
+    let mut indentation = None;
 
     let reader = get_spec_reader(spec_path)?;
 
-    for line in reader {
+    for (index, line) in reader.enumerate() {
         let line = line.map_err(|e| ParseError::ReadLine {
             inner_error: e.into(),
         })?;
+        let line_number = index + 1;
+
+        if index == 0
+            && let Some(given) = SpecVersion::from_header(&line)?
+        {
+            version = given;
+            tracing::debug!(version =? version, "Read spec version");
+            continue;
+        }
+        if version >= SpecVersion::V2 && line.trim_start().starts_with('#') {
+            continue;
+        }
 
         let target_result = try_get_nth_capture(&line, &RE_TARGET, 1)?;
         let flags_result = try_get_nth_capture(&line, &RE_TARGET, 2)?;
         let source_result = try_get_nth_capture(&line, &RE_SOURCE, 1)?;
+        let is_target = target_result.is_some() && source_result.is_none();
 
-        match (target_result, source_result) {
-            (Some(target), None) => {
-                if let Some(plan) = plan.take() {
-                    if plan.sources.is_empty() {
+        // Errors are collected so the whole spec can be fixed in one go
+        let result = (|| -> Result<(), ParseError> {
+            match (target_result, source_result) {
+                (Some(target), None) => {
+                    let target = expand_placeholders(&target, &target_dir, &now, &mut numbered);
+                    if let Some(plan) = plan.take() {
+                        if plan.sources.is_empty() {
+                            tracing::warn!(
+                                target = target,
+                                line = line,
+                                plan = plan.as_value(),
+                                "Invalid spec - there are no sources defined for the currently active target"
+                            );
+                            errors.push(LineError {
+                                line_number: target_line_number,
+                                error: Box::new(ParseError::MissingSources {
+                                    target_name: plan.target_path.leaf.clone(),
+                                }),
+                            });
+                        } else {
+                            tracing::debug!(
+                                line = line,
+                                plan = plan.as_value(),
+                                push_reason = "target_no_source",
+                                "Pushing completed plan"
+                            );
+
+                            plans.push(plan);
+                        }
+                    }
+                    target_line_number = line_number;
+
+                    let mut fade = None;
+                    let flags = match flags_result {
+                        Some(flagspec) if !flagspec.trim().is_empty() => flagspec
+                            .split(",")
+                            .map(str::trim)
+                            .filter(|flag| match flag.strip_prefix("fade=") {
+                                Some(seconds) => {
+                                    fade = Some(seconds.to_string());
+                                    false
+                                }
+                                None => true,
+                            })
+                            .map(|flag| {
+                                Flag::try_from(flag).map_err(|_| ParseError::InvalidFlag {
+                                    flag: flag.to_string(),
+                                })
+                            })
+                            .collect::<Result<Vec<_>, ParseError>>()?,
+                        _ => vec![],
+                    };
+
+                    for (i, flag) in flags.iter().enumerate() {
+                        if flags[..i].contains(flag) && !flags[i + 1..].contains(flag) {
+                            lints.push(Lint::DuplicateFlag {
+                                flag: <&str>::from(flag).to_string(),
+                                target_name: target.clone(),
+                            });
+                        }
+                    }
+
+                    let mut new_plan = Plan {
+                        target_path: PlanPath::new_relative_to(&target, target_dir.clone())?,
+                        flags,
+                        sources: vec![],
+                        fade: None,
+                    };
+                    if let Some(fade) = fade {
+                        new_plan.set_fade(parse_fade(&fade)?);
+                    }
+                    if new_plan.flags.contains(&Flag::AutoTrim) {
+                        new_plan.set_auto_trim();
+                    }
+                    plan = Some(new_plan);
+                }
+                (None, Some(source)) => {
+                    let Some(plan) = plan.as_mut() else {
+                        if skip_sources {
+                            return Ok(());
+                        }
                         tracing::warn!(
-                            target = target,
+                            source = source,
                             line = line,
                             plan = plan.as_value(),
-                            "Invalid spec - there are no sources defined for the currently active target"
+                            "Invalid spec - We have encountered a source directive when not processing a target"
                         );
-                        return Err(ParseError::MissingSources {
-                            target_name: plan.target_path.leaf.clone(),
+                        return Err(ParseError::MissingTarget {
+                            source_name: source.to_string(),
                         });
+                    };
+
+                    let indent = line.chars().next();
+                    match indentation {
+                        None => indentation = indent,
+                        Some(expected) if indent != Some(expected) => {
+                            let name = |c: Option<char>| match c {
+                                Some('\t') => "tabs".to_string(),
+                                _ => "spaces".to_string(),
+                            };
+                            lints.push(Lint::MixedIndentation {
+                                line_number,
+                                found: name(indent),
+                                expected: name(Some(expected)),
+                            });
+                        }
+                        Some(_) => {}
+                    }
+
+                    let mut source_path = PlanPath::new_source(&source, sources_dir.clone())?;
+                    if options.case_insensitive_sources
+                        && source_path.framerate.is_none()
+                        && !source_path.path.exists()
+                        && let Some(found) = find_case_insensitive(&source_path)?
+                    {
+                        tracing::debug!(
+                            source = source,
+                            path =% found.display(),
+                            "Found source with a differently cased name"
+                        );
+                        source_path.path = found;
+                    }
+                    if options.recursive_sources
+                        && source_path.framerate.is_none()
+                        && !source_path.path.exists()
+                    {
+                        let (base, files) =
+                            nested_files.get_or_insert_with(|| list_nested_files(&sources_dir));
+                        if let Some(found) = find_nested_source(
+                            &source_path,
+                            base,
+                            files,
+                            options.case_insensitive_sources,
+                        )? {
+                            tracing::debug!(
+                                source = source,
+                                path =% found.display(),
+                                "Found source in a subdirectory"
+                            );
+                            source_path.path = found;
+                        }
                     }
 
                     tracing::debug!(
                         line = line,
                         plan = plan.as_value(),
-                        push_reason = "target_no_source",
-                        "Pushing completed plan"
+                        source = source,
+                        "Adding source"
                     );
 
-                    plans.push(plan);
+                    plan.push_source(source_path);
                 }
-
-                let flags = match flags_result {
-                    Some(flagspec) if !flagspec.trim().is_empty() => flagspec
-                        .split(",")
-                        .map(|flag| {
-                            Flag::try_from(flag.trim()).map_err(|_| ParseError::InvalidFlag {
-                                flag: flag.trim().to_string(),
-                            })
-                        })
-                        .collect::<Result<Vec<_>, ParseError>>()?,
-                    _ => vec![],
-                };
-
-                plan = Some(Plan {
-                    target_path: PlanPath::new_relative_to(&target, target_dir.clone())?,
-                    flags,
-                    sources: vec![],
-                });
-            }
-            (None, Some(source)) => {
-                let Some(plan) = plan.as_mut() else {
+                (Some(target), Some(source)) => {
                     tracing::warn!(
                         source = source,
+                        target = target,
                         line = line,
-                        plan = plan.as_value(),
-                        "Invalid spec - We have encountered a source directive when not processing a target"
+                        "Invalid spec - We have somehow matched both source and target, this is likely unreachable"
                     );
-                    return Err(ParseError::MissingTarget {
-                        source_name: source.to_string(),
-                    });
-                };
-
-                let source_path = PlanPath::new_relative_to(&source, sources_dir.clone())?;
-
-                tracing::debug!(
-                    line = line,
-                    plan = plan.as_value(),
-                    source = source,
-                    "Adding source"
-                );
-
-                plan.sources.push(source_path);
-            }
-            (Some(target), Some(source)) => {
-                tracing::warn!(
-                    source = source,
-                    target = target,
-                    line = line,
-                    "Invalid spec - We have somehow matched both source and target, this is likely unreachable"
-                );
-                return Err(ParseError::UnexpectedSourceAndTarget {
-                    line: line.to_string(),
-                    src: source,
-                    target,
-                });
-            }
-            (None, None) => {
-                // No match, with content
-                if !line.trim().is_empty() {
-                    tracing::warn!(line = line, "Invalid spec - Unrecognized line");
-                    return Err(ParseError::InvalidLine {
+                    return Err(ParseError::UnexpectedSourceAndTarget {
                         line: line.to_string(),
+                        src: source,
+                        target,
                     });
                 }
+                (None, None) => {
+                    // No match, with content
+                    if !line.trim().is_empty() {
+                        tracing::warn!(line = line, "Invalid spec - Unrecognized line");
+                        return Err(ParseError::InvalidLine {
+                            line: line.to_string(),
+                        });
+                    }
 
-                if let Some(plan) = plan.take() {
-                    tracing::debug!(
-                        line = line,
-                        plan = plan.as_value(),
-                        push_reason = "empty_line",
-                        "Pushing completed plan"
-                    );
-                    plans.push(plan)
+                    if let Some(plan) = plan.take() {
+                        tracing::debug!(
+                            line = line,
+                            plan = plan.as_value(),
+                            push_reason = "empty_line",
+                            "Pushing completed plan"
+                        );
+                        plans.push(plan)
+                    }
                 }
             }
+            Ok(())
+        })();
+        if is_target || line.trim().is_empty() {
+            // The sources of a target that failed to parse would all be reported as missing their target
+            skip_sources = result.is_err();
+        }
+        if let Err(e) = result {
+            errors.push(LineError {
+                line_number,
+                error: Box::new(e),
+            });
         }
     }
 
@@ -297,74 +1076,848 @@ pub fn parse_spec(
         plans.push(plan)
     }
 
+    if !errors.is_empty() {
+        return Err(ParseError::Lines { errors });
+    }
+
     tracing::info!(plans = plans.as_value(), "Parsed {} targets", plans.len());
 
-    tracing::info!(plans = plans.as_value(), "Validating targets");
+    validate_plans(&plans)?;
 
-    let mut validation_errors = vec![];
+    tracing::info!(
+        plans = plans.as_value(),
+        "Successfully validated {} targets",
+        plans.len()
+    );
 
-    let mut sources_set = HashSet::new();
-    let mut targets_set = HashSet::new();
+    let extension = |path: &PathBuf| {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+    };
     for plan in plans.iter() {
-        if targets_set.contains(&plan.target_path.leaf) {
-            tracing::error!(
-                target_name = plan.target_path.leaf,
-                "Found duplicate target"
-            );
-
-            validation_errors.push(ValidationError::DuplicateTarget {
+        if let [source] = plan.sources.as_slice()
+            && plan.flags.is_empty()
+            && plan.fade.is_none()
+            && source.framerate.is_none()
+            && extension(&source.path) == extension(&plan.target_path.path)
+        {
+            lints.push(Lint::CopyOfSource {
                 target_name: plan.target_path.leaf.clone(),
-            })
-        } else {
-            targets_set.insert(&plan.target_path.leaf);
+            });
         }
+    }
 
-        sources_set.clear();
-        sources_set.reserve(plan.sources.len());
-        for source in plan.sources.iter() {
-            if sources_set.contains(&source.leaf) {
-                tracing::error!(
-                    target_name = plan.target_path.leaf,
-                    source_name = source.leaf,
-                    "Found duplicate source"
-                );
-                validation_errors.push(ValidationError::DuplicateSource {
-                    source_name: source.leaf.clone(),
-                    target_name: plan.target_path.leaf.clone(),
-                })
-            } else {
-                sources_set.insert(&source.leaf);
+    if options.strict && !lints.is_empty() {
+        return Err(ParseError::Lints { lints });
+    }
+    for lint in lints.iter() {
+        tracing::warn!(lint = lint.as_value(), "{lint}");
+    }
+
+    Ok((plans, lints))
+}
+
+/// Most suggestions given for a missing source
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Shortest stem that counts as the start of another name, so `p.ndjson` isn't suggested for `part.mp4`
+const MIN_STEM_PREFIX: usize = 3;
+
+/// Files next to where a missing source was expected with nearly the same name, closest first. Near means a few typos
+/// apart, or one name starting with the other's stem, like `clip` and `clip_001.mp4`
+fn suggest_sources(source: &PlanPath) -> Vec<String> {
+    let (Some(dir), Some(name)) = (source.path.parent(), source.path.file_name()) else {
+        return vec![];
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let name = name.to_string_lossy().to_lowercase();
+    let stem = name
+        .rsplit_once('.')
+        .map_or(name.as_str(), |(stem, _)| stem);
+    let mut suggestions = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let other = file_name.to_lowercase();
+            let other_stem = other
+                .rsplit_once('.')
+                .map_or(other.as_str(), |(stem, _)| stem);
+            let distance = edit_distance(&name, &other);
+            let near = distance <= (name.chars().count() / 4).max(2)
+                || (stem.len() >= MIN_STEM_PREFIX && other_stem.starts_with(stem))
+                || (other_stem.len() >= MIN_STEM_PREFIX && stem.starts_with(other_stem));
+            near.then_some((distance, file_name))
+        })
+        .collect::<Vec<_>>();
+    suggestions.sort();
+
+    // Written the way the spec wrote the source, so the suggestion can be pasted in
+    let written = Path::new(&source.leaf)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    suggestions
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, file_name)| match written {
+            Some(parent) => parent.join(file_name).display().to_string(),
+            None => file_name,
+        })
+        .collect()
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    match suggestions {
+        [] => String::new(),
+        suggestions => format!(
+            ", did you mean {}?",
+            suggestions
+                .iter()
+                .map(|suggestion| format!("\"{suggestion}\""))
+                .collect::<Vec<_>>()
+                .join(" or ")
+        ),
+    }
+}
+
+/// Every file under `sources_dir` and its subdirectories, along with the directory they're under
+fn list_nested_files(sources_dir: &Path) -> (PathBuf, Vec<PathBuf>) {
+    let base = sources_dir
+        .canonicalize()
+        .map(without_verbatim_prefix)
+        .unwrap_or_else(|_| sources_dir.to_path_buf());
+
+    let mut files = Vec::new();
+    let mut dirs = vec![base.clone()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            tracing::warn!(dir =% dir.display(), "Failed to read directory, skipping it");
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => dirs.push(entry.path()),
+                Ok(file_type) if file_type.is_file() => files.push(entry.path()),
+                _ => {}
             }
+        }
+    }
+    files.sort();
+    (base, files)
+}
 
-            if let Err(e) = source.path.canonicalize() {
-                tracing::error!(
-                    target_name = plan.target_path.leaf,
-                    source_name = source.leaf,
-                    error =% e,
-                    error_context =? e,
-                    "Source file not found"
-                );
-                validation_errors.push(ValidationError::MissingSource {
-                    source_name: source.leaf.clone(),
-                    source_path: source.path.display().to_string(),
-                    target_name: plan.target_path.leaf.clone(),
-                    inner_error: e.into(),
+/// The one file under a subdirectory of `base` that ends with the source's path, e.g. `DCIM/100GOPRO/clip.mp4` for
+/// `clip.mp4`. Sources outside `base` aren't looked for, and more than one match is an error rather than a guess
+fn find_nested_source(
+    source: &PlanPath,
+    base: &Path,
+    files: &[PathBuf],
+    ignore_case: bool,
+) -> Result<Option<PathBuf>, ParseError> {
+    let Ok(relative) = source.path.strip_prefix(base) else {
+        return Ok(None);
+    };
+
+    let found = files
+        .iter()
+        .filter(|file| {
+            let mut file = file.components().rev();
+            relative.components().rev().all(|component| {
+                file.next().is_some_and(|other| {
+                    same_name(component.as_os_str(), other.as_os_str(), ignore_case)
                 })
+            })
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    only_match(source, found)
+}
+
+/// The file on disk whose path only differs from the source's in case, resolved one component at a time
+fn find_case_insensitive(source: &PlanPath) -> Result<Option<PathBuf>, ParseError> {
+    let mut resolved = PathBuf::new();
+    for component in source.path.components() {
+        let exact = resolved.join(component);
+        if exact.exists() {
+            resolved = exact;
+            continue;
+        }
+
+        let Ok(entries) = std::fs::read_dir(&resolved) else {
+            return Ok(None);
+        };
+        let mut found = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| same_name(&entry.file_name(), component.as_os_str(), true))
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+        found.sort();
+        match only_match(source, found)? {
+            Some(found) => resolved = found,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(resolved))
+}
+
+fn same_name(a: &OsStr, b: &OsStr, ignore_case: bool) -> bool {
+    match ignore_case {
+        true => a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase(),
+        false => a == b,
+    }
+}
+
+/// The single path found for a source, more than one is an error rather than a guess
+fn only_match(source: &PlanPath, found: Vec<PathBuf>) -> Result<Option<PathBuf>, ParseError> {
+    match found.as_slice() {
+        [] => Ok(None),
+        [_] => Ok(found.into_iter().next()),
+        found => Err(ParseError::AmbiguousSource {
+            source_name: source.leaf.clone(),
+            candidates: found
+                .iter()
+                .map(|file| file.display().to_string())
+                .collect(),
+        }),
+    }
+}
+
+/// Expands `{date}`, `{time}` and `{n}` in a target name. `{n}` is the lowest number from 1 that names neither an
+/// existing file nor another target, so recurring exports don't overwrite the last one
+fn expand_placeholders(
+    target: &str,
+    target_dir: &Path,
+    (date, time): &(String, String),
+    numbered: &mut HashSet<PathBuf>,
+) -> String {
+    let target = target.replace("{date}", date).replace("{time}", time);
+    if !target.contains("{n}") {
+        return target;
+    }
+
+    (1..)
+        .map(|n| target.replace("{n}", &n.to_string()))
+        .find(|expanded| {
+            let path = target_dir.join(expanded);
+            !path.exists() && numbered.insert(path)
+        })
+        .expect("Ran out of numbers")
+}
+
+/// The local date and time, as `YYYY-MM-DD` and `HH-MM-SS` so they can go in file names
+fn local_date_time() -> (String, String) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .expect("Why are you in the past?")
+        .as_secs() as i64;
+    date_time_at(now)
+}
+
+/// The local date and time `epoch_secs` after the unix epoch, as `YYYY-MM-DD` and `HH-MM-SS`
+#[cfg(unix)]
+pub fn date_time_at(epoch_secs: i64) -> (String, String) {
+    let time = epoch_secs as libc::time_t;
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+
+    (
+        format!(
+            "{:04}-{:02}-{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday
+        ),
+        format!("{:02}-{:02}-{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec),
+    )
+}
+
+/// The UTC date and time `epoch_secs` after the unix epoch, as `YYYY-MM-DD` and `HH-MM-SS`
+#[cfg(not(unix))]
+pub fn date_time_at(epoch_secs: i64) -> (String, String) {
+    let (days, secs) = (epoch_secs.div_euclid(86400), epoch_secs.rem_euclid(86400));
+
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        format!("{year:04}-{month:02}-{day:02}"),
+        format!(
+            "{:02}-{:02}-{:02}",
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        ),
+    )
+}
+
+fn parse_fade(fade: &str) -> Result<f64, ParseError> {
+    fade.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+        .ok_or_else(|| ParseError::InvalidFade {
+            fade: fade.to_string(),
+        })
+}
+
+/// The line number of `target_name` in the text of a spec, or of its `source_name` when given, to point at where an error came
+/// from. Targets named with placeholders aren't found
+pub fn locate(spec: &str, target_name: &str, source_name: Option<&str>) -> Option<usize> {
+    let mut in_target = false;
+    for (index, line) in spec.lines().enumerate() {
+        if RE_SOURCE.is_match(line) {
+            let source = RE_SOURCE
+                .captures(line)
+                .map(|caps| caps[1].trim().to_string());
+            if in_target
+                && source
+                    .as_deref()
+                    .is_some_and(|source| Some(source) == source_name)
+            {
+                return Some(index + 1);
+            }
+        } else if let Some(caps) = RE_TARGET.captures(line) {
+            in_target = caps[1].trim() == target_name;
+            if in_target && source_name.is_none() {
+                return Some(index + 1);
             }
         }
     }
+    None
+}
 
-    if !validation_errors.is_empty() {
-        return Err(ParseError::Validation {
-            errors: validation_errors,
-        });
+/// A target as written in the spec, for formatting
+struct SpecBlock {
+    /// v2 comments above the target
+    comments: Vec<String>,
+    target: String,
+    flags: Vec<String>,
+    /// Sources, along with v2 comments between them
+    sources: Vec<String>,
+}
+
+/// Rewrites a spec in canonical form: one tab before each source, `, ` between flags and a blank line between targets.
+/// Targets keep their order unless `sort` is set, which orders them by name. Comments stay with the target below them
+pub fn format_spec(spec: &str, sort: bool) -> Result<String, ParseError> {
+    let mut version = None;
+    let mut blocks: Vec<SpecBlock> = Vec::new();
+    let mut comments = Vec::new();
+
+    for (index, line) in spec.lines().enumerate() {
+        if index == 0
+            && let Some(given) = SpecVersion::from_header(line)?
+        {
+            version = Some(given);
+            continue;
+        }
+        if version >= Some(SpecVersion::V2) && line.trim_start().starts_with('#') {
+            match blocks.last_mut() {
+                // Indented comments belong to the sources around them
+                Some(block) if comments.is_empty() && line.starts_with(char::is_whitespace) => {
+                    block.sources.push(line.trim().to_string())
+                }
+                _ => comments.push(line.trim().to_string()),
+            }
+            continue;
+        }
+
+        let target_result = try_get_nth_capture(line, &RE_TARGET, 1)?;
+        let flags_result = try_get_nth_capture(line, &RE_TARGET, 2)?;
+        let source_result = try_get_nth_capture(line, &RE_SOURCE, 1)?;
+
+        match (target_result, source_result) {
+            (Some(target), None) => blocks.push(SpecBlock {
+                comments: std::mem::take(&mut comments),
+                target,
+                flags: flags_result
+                    .iter()
+                    .flat_map(|flags| flags.split(','))
+                    .map(str::trim)
+                    .filter(|flag| !flag.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                sources: vec![],
+            }),
+            (None, Some(source)) => match blocks.last_mut() {
+                Some(block) => {
+                    // Comments between sources stay between them
+                    block.sources.append(&mut comments);
+                    block.sources.push(source)
+                }
+                None => {
+                    return Err(ParseError::MissingTarget {
+                        source_name: source,
+                    });
+                }
+            },
+            (Some(target), Some(source)) => {
+                return Err(ParseError::UnexpectedSourceAndTarget {
+                    line: line.to_string(),
+                    src: source,
+                    target,
+                });
+            }
+            (None, None) if line.trim().is_empty() => {}
+            (None, None) => {
+                return Err(ParseError::InvalidLine {
+                    line: line.to_string(),
+                });
+            }
+        }
     }
 
-    tracing::info!(
-        plans = plans.as_value(),
-        "Successfully validated {} targets",
-        plans.len()
-    );
+    if sort {
+        blocks.sort_by(|a, b| a.target.cmp(&b.target));
+    }
+
+    let mut sections = Vec::new();
+    if let Some(version) = version {
+        sections.push(version.header());
+    }
+    for block in blocks {
+        let mut section = String::new();
+        for comment in block.comments {
+            section.push_str(&format!("{comment}\n"));
+        }
+        section.push_str(&format!("{}:", block.target));
+        if !block.flags.is_empty() {
+            section.push_str(&format!(" {}", block.flags.join(", ")));
+        }
+        for source in block.sources {
+            section.push_str(&format!("\n\t{source}"));
+        }
+        sections.push(section);
+    }
+    // Comments after the last target
+    if !comments.is_empty() {
+        sections.push(comments.join("\n"));
+    }
+
+    let mut formatted = sections.join("\n\n");
+    formatted.push('\n');
+    Ok(formatted)
+}
+
+/// Builds a plan without a spec file, resolving paths and validating it the same way as [`parse_spec`]
+#[derive(Debug, Clone)]
+pub struct PlanBuilder {
+    target: String,
+    target_dir: PathBuf,
+    sources_dir: PathBuf,
+    flags: Vec<Flag>,
+    sources: Vec<String>,
+    fade: Option<f64>,
+}
+impl PlanBuilder {
+    /// Relative paths resolve against the working directory unless given a directory
+    pub fn new(target: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+            target_dir: PathBuf::from("."),
+            sources_dir: PathBuf::from("."),
+            flags: vec![],
+            sources: vec![],
+            fade: None,
+        }
+    }
+
+    pub fn target_dir(mut self, target_dir: impl Into<PathBuf>) -> Self {
+        self.target_dir = target_dir.into();
+        self
+    }
+
+    pub fn sources_dir(mut self, sources_dir: impl Into<PathBuf>) -> Self {
+        self.sources_dir = sources_dir.into();
+        self
+    }
+
+    pub fn flag(mut self, flag: Flag) -> Self {
+        if !self.flags.contains(&flag) {
+            self.flags.push(flag);
+        }
+        self
+    }
+
+    pub fn fade(mut self, seconds: f64) -> Self {
+        self.fade = Some(seconds);
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.sources.push(source.into());
+        self
+    }
+
+    pub fn sources<S: Into<String>>(mut self, sources: impl IntoIterator<Item = S>) -> Self {
+        self.sources.extend(sources.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn build(self) -> Result<Plan, ParseError> {
+        if self.sources.is_empty() {
+            return Err(ParseError::MissingSources {
+                target_name: self.target,
+            });
+        }
+
+        let mut plan = Plan {
+            target_path: PlanPath::new_relative_to(&self.target, self.target_dir)?,
+            flags: self.flags,
+            sources: vec![],
+            fade: None,
+        };
+        if let Some(fade) = self.fade {
+            plan.set_fade(parse_fade(&fade.to_string())?);
+        }
+        if plan.flags.contains(&Flag::AutoTrim) {
+            plan.set_auto_trim();
+        }
+        for source in self.sources.iter() {
+            plan.push_source(PlanPath::new_source(source, self.sources_dir.clone())?);
+        }
+        validate_plans(std::slice::from_ref(&plan))?;
+
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir holding an empty file for each of `files`
+    fn scratch(files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stitch-test-{}", uuid::Uuid::new_v4()));
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 
-    Ok(plans)
+    /// Parses `spec` with its targets and sources in `dir`
+    fn parse(
+        dir: &Path,
+        spec: &str,
+        options: ParseOptions,
+    ) -> Result<(Vec<Plan>, Vec<Lint>), ParseError> {
+        let spec_path = dir.join("test.stitchspec");
+        std::fs::write(&spec_path, spec).unwrap();
+        parse_spec(spec_path, dir.to_path_buf(), dir.to_path_buf(), options)
+    }
+
+    #[test]
+    fn video_stream_selector() {
+        let dir = scratch(&["multicam.mkv"]);
+        let source = PlanPath::new_source("multicam.mkv v:1", dir.clone()).unwrap();
+
+        assert_eq!(source.video_stream, Some(1));
+        assert_eq!(source.leaf, "multicam.mkv v:1");
+        assert!(source.path.ends_with("multicam.mkv"));
+        assert_eq!(source.video_specifier(2), "2:v:1");
+    }
+
+    #[test]
+    fn no_video_stream_selector_uses_the_first() {
+        let dir = scratch(&["clip.mp4"]);
+        let source = PlanPath::new_source("clip.mp4", dir).unwrap();
+
+        assert_eq!(source.video_stream, None);
+        assert_eq!(source.video_specifier(0), "0:v:0");
+    }
+
+    #[test]
+    fn invalid_video_stream_selector() {
+        let dir = scratch(&["multicam.mkv"]);
+
+        assert!(matches!(
+            PlanPath::new_source("multicam.mkv v:one", dir),
+            Err(ParseError::InvalidVideoStream { video_stream, .. }) if video_stream == "one"
+        ));
+    }
+
+    #[test]
+    fn different_video_streams_switch_on_the_concat_filter() {
+        let dir = scratch(&["multicam.mkv"]);
+        let (plans, _) = parse(
+            &dir,
+            "out.mp4:\n  multicam.mkv v:0\n  multicam.mkv v:1\n",
+            ParseOptions::default(),
+        )
+        .unwrap();
+
+        assert!(plans[0].flags.contains(&Flag::ConcatFilter));
+    }
+
+    #[test]
+    fn image_sequence_framerate() {
+        let dir = scratch(&["frames/00001.png", "frames/00002.png", "frames/notes.txt"]);
+        let source = PlanPath::new_source("frames/%05d.png @fps 24", dir).unwrap();
+
+        assert_eq!(source.framerate, Some(24.0));
+        assert!(source.path.ends_with("frames/%05d.png"));
+        assert_eq!(source.count_frames(), 2);
+    }
+
+    #[test]
+    fn invalid_image_sequence_framerate() {
+        let dir = scratch(&[]);
+
+        for framerate in ["0", "-24", "fast", "inf"] {
+            assert!(
+                matches!(
+                    PlanPath::new_source(&format!("frames/%05d.png @fps {framerate}"), dir.clone()),
+                    Err(ParseError::InvalidFramerate { .. })
+                ),
+                "@fps {framerate}"
+            );
+        }
+    }
+
+    #[test]
+    fn image_sequence_switches_on_the_concat_filter() {
+        let dir = scratch(&["intro/001.png", "clip.mp4"]);
+        let (plans, _) = parse(
+            &dir,
+            "out.mp4:\n  intro/%03d.png @fps 30\n  clip.mp4\n",
+            ParseOptions::default(),
+        )
+        .unwrap();
+
+        assert!(plans[0].flags.contains(&Flag::ConcatFilter));
+    }
+
+    #[test]
+    fn image_sequence_without_frames() {
+        let dir = scratch(&[]);
+
+        assert!(matches!(
+            parse(&dir, "out.mp4:\n  frames/%05d.png @fps 24\n", ParseOptions::default()),
+            Err(ParseError::Validation { errors })
+                if matches!(errors.as_slice(), [ValidationError::EmptyImageSequence { .. }])
+        ));
+    }
+
+    #[test]
+    fn fade_seconds() {
+        assert_eq!(parse_fade("1.5").unwrap(), 1.5);
+        assert_eq!(parse_fade(" 2 ").unwrap(), 2.0);
+        for fade in ["0", "-1", "soon", "", "NaN"] {
+            assert!(
+                matches!(parse_fade(fade), Err(ParseError::InvalidFade { .. })),
+                "fade={fade}"
+            );
+        }
+    }
+
+    #[test]
+    fn fade_flag_switches_on_the_concat_filter() {
+        let dir = scratch(&["a.mp4", "b.mp4"]);
+        let (plans, _) = parse(
+            &dir,
+            "out.mp4: gen-pts, fade=1.5\n  a.mp4\n  b.mp4\n",
+            ParseOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(plans[0].fade, Some(1.5));
+        assert!(plans[0].flags.contains(&Flag::GenPts));
+        assert!(plans[0].flags.contains(&Flag::ConcatFilter));
+    }
+
+    #[test]
+    fn invalid_fade_flag() {
+        let dir = scratch(&["a.mp4"]);
+
+        assert!(matches!(
+            parse(&dir, "out.mp4: fade=long\n  a.mp4\n", ParseOptions::default()),
+            Err(ParseError::Lines { errors })
+                if matches!(errors.as_slice(), [LineError { line_number: 1, error }] if matches!(**error, ParseError::InvalidFade { .. }))
+        ));
+    }
+
+    #[test]
+    fn duplicate_flag_lint() {
+        let dir = scratch(&["a.mp4", "b.mp4"]);
+        let (_, lints) = parse(
+            &dir,
+            "out.mp4: catf, gen-pts, concat-filter, catf\n  a.mp4\n  b.mp4\n",
+            ParseOptions::default(),
+        )
+        .unwrap();
+
+        // Given three times, but only warned about once
+        assert!(matches!(
+            lints.as_slice(),
+            [Lint::DuplicateFlag { flag, target_name }] if flag == "concat-filter" && target_name == "out.mp4"
+        ));
+    }
+
+    #[test]
+    fn mixed_indentation_lint() {
+        let dir = scratch(&["a.mp4", "b.mp4", "c.mp4"]);
+        let (_, lints) = parse(
+            &dir,
+            "out.mp4:\n  a.mp4\n\tb.mp4\n  c.mp4\n",
+            ParseOptions::default(),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            lints.as_slice(),
+            [Lint::MixedIndentation { line_number: 3, found, expected }] if found == "tabs" && expected == "spaces"
+        ));
+    }
+
+    #[test]
+    fn clean_spec_has_no_lints() {
+        let dir = scratch(&["a.mp4", "b.mp4"]);
+        let (_, lints) = parse(
+            &dir,
+            "out.mp4: catf\n\ta.mp4\n\tb.mp4\n",
+            ParseOptions::default(),
+        )
+        .unwrap();
+
+        assert!(lints.is_empty(), "{lints:?}");
+    }
+
+    #[test]
+    fn strict_rejects_lints() {
+        let dir = scratch(&["a.mp4", "b.mp4"]);
+        let options = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+
+        assert!(matches!(
+            parse(&dir, "out.mp4: catf, catf\n  a.mp4\n  b.mp4\n", options),
+            Err(ParseError::Lints { lints }) if lints.len() == 1
+        ));
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+        assert_eq!(edit_distance("café", "cafe"), 1);
+    }
+
+    #[test]
+    fn suggestions_for_a_missing_source() {
+        let dir = scratch(&[
+            "clp.mp4",
+            "clip_001.mp4",
+            "clip_002.mp4",
+            "p.ndjson",
+            "unrelated.mov",
+        ]);
+        let source = PlanPath::new_source("clip.mp4", dir).unwrap();
+
+        // Closest first, and no more than MAX_SUGGESTIONS
+        assert_eq!(
+            suggest_sources(&source),
+            ["clp.mp4", "clip_001.mp4", "clip_002.mp4"]
+        );
+    }
+
+    #[test]
+    fn suggestions_keep_the_directory_the_spec_gave() {
+        let dir = scratch(&["day1/CLIP.MP4"]);
+        let source = PlanPath::new_source("day1/clip.mp4", dir).unwrap();
+
+        assert_eq!(suggest_sources(&source), ["day1/CLIP.MP4"]);
+    }
+
+    #[test]
+    fn missing_source_suggests_similar_files() {
+        let dir = scratch(&["intro.mp4"]);
+        let Err(ParseError::Validation { errors }) =
+            parse(&dir, "out.mp4:\n  intr.mp4\n", ParseOptions::default())
+        else {
+            panic!("expected a validation error");
+        };
+
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidationError::MissingSource { suggestions, .. }] if suggestions == &["intro.mp4"]
+        ));
+        assert!(
+            errors[0]
+                .to_string()
+                .ends_with(", did you mean \"intro.mp4\"?")
+        );
+    }
+
+    #[test]
+    fn target_overwriting_its_source() {
+        let dir = scratch(&["a.mp4", "b.mp4"]);
+
+        assert!(matches!(
+            parse(&dir, "a.mp4:\n  a.mp4\n  b.mp4\n", ParseOptions::default()),
+            Err(ParseError::Validation { errors })
+                if matches!(
+                    errors.as_slice(),
+                    [ValidationError::TargetOverwritesSource { source_name, target_name }]
+                        if source_name == "a.mp4" && target_name == "a.mp4"
+                )
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn target_overwriting_its_source_through_a_symlink() {
+        let dir = scratch(&["a.mp4", "b.mp4"]);
+        std::os::unix::fs::symlink(dir.join("a.mp4"), dir.join("link.mp4")).unwrap();
+
+        assert!(matches!(
+            parse(&dir, "link.mp4:\n  a.mp4\n  b.mp4\n", ParseOptions::default()),
+            Err(ParseError::Validation { errors })
+                if matches!(errors.as_slice(), [ValidationError::TargetOverwritesSource { .. }])
+        ));
+    }
+
+    #[test]
+    fn target_beside_its_sources() {
+        let dir = scratch(&["a.mp4", "b.mp4"]);
+
+        assert!(
+            parse(
+                &dir,
+                "out.mp4:\n  a.mp4\n  b.mp4\n",
+                ParseOptions::default()
+            )
+            .is_ok()
+        );
+    }
 }
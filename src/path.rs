@@ -1,7 +1,14 @@
-use std::{path::PathBuf, time::SystemTime};
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
 
 const PRODUCT_NAME: &str = "dev.thmsn.stitch";
 
+/// Run directories left behind for longer than this are removed when a run starts
+pub const DEFAULT_ORPHAN_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
 fn epoch() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -9,32 +16,259 @@ fn epoch() -> u64 {
         .as_secs()
 }
 
+static TMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Creates temp directories under `dir` instead of the system temp dir
+pub fn set_tmp_dir(dir: PathBuf) {
+    TMP_DIR.get_or_init(|| dir);
+}
+
 pub fn tmp_root() -> PathBuf {
-    std::env::temp_dir().join(PRODUCT_NAME)
+    TMP_DIR
+        .get()
+        .cloned()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(PRODUCT_NAME)
 }
 
+/// This run's temp directory, named after its run id so concurrent runs never share one
 pub fn run_tmp_root() -> PathBuf {
-    let dir = tmp_root().join(epoch().to_string());
+    let dir = tmp_root().join(run_id());
     if !dir.exists() {
         std::fs::create_dir_all(&dir).expect("Failed to create tmp root dir");
     }
     dir
 }
 
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(unix)]
+fn is_process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process exists
+    let exists = unsafe { libc::kill(pid, 0) } == 0;
+    // Someone else's process still exists
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_process_alive(_pid: u32) -> bool {
+    // Without a cheap check, assume it is so a running batch never loses its files
+    true
+}
+
+/// Whether the run that created the temp directory `name` is still going. Directories from before run ids had pids never are
+fn is_live_run(name: &str) -> bool {
+    if name == run_id() {
+        return true;
+    }
+
+    name.split('-')
+        .nth(1)
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(is_process_alive)
+}
+
+/// Removes run directories under the temp root left behind by runs that have exited, only those last modified
+/// longer than `older_than` ago when given. Returns how many were removed and their size
+pub fn remove_run_dirs(older_than: Option<Duration>) -> std::io::Result<(usize, u64)> {
+    let tmp_root = tmp_root();
+    if !tmp_root.exists() {
+        return Ok((0, 0));
+    }
+
+    let mut removed = 0usize;
+    let mut reclaimed = 0u64;
+    for entry in std::fs::read_dir(&tmp_root)? {
+        // One unreadable entry, e.g. a directory removed by another `stitch clean` part way through, shouldn't stop the rest
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!(path =% tmp_root.display(), error =% e, error_context =? e, "Failed to read run directory entry");
+                continue;
+            }
+        };
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                tracing::warn!(path =% entry.path().display(), error =% e, error_context =? e, "Failed to read run directory metadata");
+                continue;
+            }
+        };
+        if !meta.is_dir() {
+            continue;
+        }
+        if entry.file_name().to_str().is_some_and(is_live_run) {
+            tracing::debug!(path =% entry.path().display(), "Keeping run directory of a live run");
+            continue;
+        }
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or_default();
+        if older_than.is_some_and(|older_than| age < older_than) {
+            tracing::debug!(path =% entry.path().display(), "Keeping recent run directory");
+            continue;
+        }
+
+        let size = dir_size(&entry.path());
+        match std::fs::remove_dir_all(entry.path()) {
+            Ok(()) => {
+                tracing::debug!(path =% entry.path().display(), size = size, "Removed run directory");
+                removed += 1;
+                reclaimed += size;
+            }
+            Err(e) => {
+                tracing::warn!(path =% entry.path().display(), error =% e, error_context =? e, "Failed to remove run directory");
+            }
+        }
+    }
+
+    Ok((removed, reclaimed))
+}
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keeps logs and other data under `dir` instead of the platform data dir
+pub fn set_data_dir(dir: PathBuf) {
+    DATA_DIR.get_or_init(|| dir);
+}
+
 pub fn data_root() -> PathBuf {
+    if let Some(dir) = DATA_DIR.get() {
+        return dir.clone();
+    }
+
     dirs::data_local_dir()
         .expect("cant find data local dir")
         .join(PRODUCT_NAME)
 }
 
+static LOG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Writes logs to `dir` instead of under the data dir
+pub fn set_log_dir(dir: PathBuf) {
+    LOG_DIR.get_or_init(|| dir);
+}
+
 pub fn logs_root() -> PathBuf {
-    data_root().join("logs")
+    LOG_DIR
+        .get()
+        .cloned()
+        .unwrap_or_else(|| data_root().join("logs"))
 }
 
+static RUN_ID: OnceLock<String> = OnceLock::new();
+
+/// Identifies this run as `<epoch>-<pid>-<random>`, e.g. `1760600000-4242-1a2b3c4d`.
+/// Sorts by start time, and is the same for the lifetime of the process
+pub fn run_id() -> &'static str {
+    RUN_ID.get_or_init(|| {
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        format!("{}-{}-{}", epoch(), std::process::id(), &suffix[..8])
+    })
+}
+
+/// This run's log file. The directory may not exist yet
 pub fn logs_path() -> PathBuf {
-    let parent = logs_root();
-    if !parent.exists() {
-        std::fs::create_dir_all(&parent).expect("Failed to create logs root dir");
+    logs_root().join(format!("{}.json", run_id()))
+}
+
+/// Always points at the most recent run's log
+pub fn latest_log_path() -> PathBuf {
+    logs_root().join("latest.json")
+}
+
+/// Directory for this run's per-target logs, next to its log file. It may not exist yet
+pub fn run_logs_root() -> PathBuf {
+    logs_root().join(run_id())
+}
+
+/// The per-target logs directory belonging to the log file at `log_path`
+pub fn run_logs_root_of(log_path: &Path) -> Option<PathBuf> {
+    let stem = log_path.file_stem()?.to_str()?;
+    // Logs from before run ids were named `<epoch>_log.json`
+    let stem = stem.strip_suffix("_log").unwrap_or(stem);
+    Some(log_path.with_file_name(stem))
+}
+
+/// Every run's targets and outcomes, queried by `stitch history`
+pub fn runs_database_path() -> PathBuf {
+    data_root().join("runs.sqlite")
+}
+
+/// Encode throughput recorded by past runs
+pub fn throughput_history_path() -> PathBuf {
+    data_root().join("throughput.json")
+}
+
+/// Mezzanine intermediates kept between runs, named by a hash of their encode settings and their source's path, size
+/// and modification time
+pub fn intermediates_root() -> PathBuf {
+    data_root().join("intermediates")
+}
+
+/// Removes cached intermediates, only those last used longer than `older_than` ago when given.
+/// Returns how many were removed and their size
+pub fn remove_cached_intermediates(older_than: Option<Duration>) -> std::io::Result<(usize, u64)> {
+    let intermediates_root = intermediates_root();
+    if !intermediates_root.exists() {
+        return Ok((0, 0));
+    }
+
+    let mut removed = 0usize;
+    let mut reclaimed = 0u64;
+    for entry in std::fs::read_dir(&intermediates_root)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        // Partial intermediates are named after the run writing them
+        if entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.ends_with(".partial.mkv") && is_live_run(name))
+        {
+            tracing::debug!(path =% entry.path().display(), "Keeping intermediate a live run is writing");
+            continue;
+        }
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or_default();
+        if older_than.is_some_and(|older_than| age < older_than) {
+            continue;
+        }
+
+        match std::fs::remove_file(entry.path()) {
+            Ok(()) => {
+                tracing::debug!(path =% entry.path().display(), size = meta.len(), "Removed cached intermediate");
+                removed += 1;
+                reclaimed += meta.len();
+            }
+            Err(e) => {
+                tracing::warn!(path =% entry.path().display(), error =% e, error_context =? e, "Failed to remove cached intermediate");
+            }
+        }
     }
-    parent.join(format!("{}_log.json", epoch()))
+
+    Ok((removed, reclaimed))
 }
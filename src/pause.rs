@@ -0,0 +1,121 @@
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+use tokio_util::{future::FutureExt, sync::CancellationToken};
+
+lazy_static! {
+    static ref PAUSED: watch::Sender<bool> = watch::Sender::new(false);
+}
+
+pub fn is_paused() -> bool {
+    *PAUSED.borrow()
+}
+
+/// Pauses or resumes the batch: plans waiting to launch are held back, and running children are suspended
+pub fn set_paused(paused: bool) {
+    if !PAUSED.send_if_modified(|current| std::mem::replace(current, paused) != paused) {
+        return;
+    }
+
+    tracing::info!(
+        paused = paused,
+        "{}",
+        if paused { "Pausing" } else { "Resuming" }
+    );
+    signal_children(paused);
+}
+
+pub fn toggle() {
+    set_paused(!is_paused());
+}
+
+/// Waits until the batch isn't paused. Returns false if `token` is cancelled while waiting
+pub async fn wait_while_paused(token: &CancellationToken) -> bool {
+    let mut rx = PAUSED.subscribe();
+    rx.wait_for(|paused| !paused)
+        .with_cancellation_token(token)
+        .await
+        .is_some()
+}
+
+/// Toggles pause whenever SIGUSR1 is received
+pub fn toggle_on_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut signals = match signal(SignalKind::user_defined1()) {
+            Ok(signals) => signals,
+            Err(e) => {
+                tracing::warn!(error =% e, error_context =? e, "Failed to listen for SIGUSR1, pausing will be unavailable");
+                return;
+            }
+        };
+
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                toggle();
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+fn signal_children(paused: bool) {
+    let signal = if paused { libc::SIGSTOP } else { libc::SIGCONT };
+
+    for pid in child_pids() {
+        // SAFETY: kill has no memory safety requirements
+        if unsafe { libc::kill(pid, signal) } != 0 {
+            let e = std::io::Error::last_os_error();
+            tracing::warn!(pid = pid, error =% e, error_context =? e, "Failed to signal child process");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn signal_children(_paused: bool) {
+    tracing::warn!(
+        "Suspending running processes is unsupported on this platform, only new plans will wait"
+    );
+}
+
+/// Processes spawned by stitch, ffmpeg and ffprobe are spawned by libffmpeg so we never see their handles
+#[cfg(target_os = "linux")]
+fn child_pids() -> Vec<libc::pid_t> {
+    let own_pid = std::process::id().to_string();
+
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<libc::pid_t>().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            // The command name is parenthesized and may contain spaces, ppid is the 2nd field after it
+            let ppid = stat.rsplit_once(')')?.1.split_whitespace().nth(1)?;
+            (ppid == own_pid).then_some(pid)
+        })
+        .collect()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn child_pids() -> Vec<libc::pid_t> {
+    let output = match std::process::Command::new("pgrep")
+        .arg("-P")
+        .arg(std::process::id().to_string())
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            tracing::warn!(error =% e, error_context =? e, "Failed to list child processes");
+            return vec![];
+        }
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect()
+}
@@ -0,0 +1,517 @@
+use std::{collections::HashMap, path::PathBuf, sync::OnceLock, time::Duration};
+
+use libffmpeg::util::cmd::{self, CommandError};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::{Level, instrument};
+use valuable::Valuable;
+
+use crate::{env, limits, parse::PlanPath};
+
+/// How an ffmpeg binary was built, from `-version`
+#[derive(Debug, Clone, Serialize, Deserialize, Valuable, JsonSchema)]
+pub struct BuildInfo {
+    /// e.g. `ffmpeg version 7.1`
+    pub version: String,
+    /// The flags it was configured with, e.g. `--enable-gpl`
+    pub configuration: Vec<String>,
+    /// Versions of the libraries it's linked against, e.g. `libavcodec 61. 19.100`
+    pub libraries: Vec<String>,
+}
+
+/// ffmpeg's build, captured once the binaries are found
+static FFMPEG_BUILD: OnceLock<BuildInfo> = OnceLock::new();
+
+/// Captures ffmpeg's build for the log and reports. Not knowing it doesn't stop a run
+pub async fn record_ffmpeg_build(ct: CancellationToken) {
+    match build_info("ffmpeg", ct).await {
+        Ok(build) => {
+            tracing::info!(ffmpeg = build.as_value(), "Using {}", build.version);
+            let _ = FFMPEG_BUILD.set(build);
+        }
+        Err(e) => {
+            tracing::warn!(error =% e, error_context =? e, "Failed to read ffmpeg's version");
+        }
+    }
+}
+
+pub fn ffmpeg_build<'a>() -> Option<&'a BuildInfo> {
+    FFMPEG_BUILD.get()
+}
+
+/// Version, configuration and library versions of `bin`, from `bin -version`
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn build_info(bin: &str, ct: CancellationToken) -> Result<BuildInfo, CommandError> {
+    let results = cmd::run(bin, None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        cmd.arg("-hide_banner").arg("-version");
+    })
+    .await?;
+
+    let mut lines = results.stdout_lines.iter().map(|line| line.trim());
+    let version = lines
+        .next()
+        .map(|line| line.split(" Copyright").next().unwrap_or(line).to_string())
+        .unwrap_or_default();
+
+    let mut configuration = vec![];
+    let mut libraries = vec![];
+    for line in lines {
+        if let Some(flags) = line.strip_prefix("configuration:") {
+            configuration = flags.split_whitespace().map(str::to_string).collect();
+        } else if line.starts_with("lib") {
+            // e.g. `libavutil      59. 39.100 / 59. 39.100`, the version it was built against
+            let library = line.split('/').next().unwrap_or(line);
+            libraries.push(library.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+    }
+
+    Ok(BuildInfo {
+        version,
+        configuration,
+        libraries,
+    })
+}
+
+#[derive(Debug, Clone, Error, Serialize, Deserialize, Valuable)]
+pub enum DurationError {
+    #[error(transparent)]
+    Command {
+        #[from]
+        inner_error: CommandError,
+    },
+    #[error("ffprobe didn't report a duration for \"{path}\"")]
+    Missing { path: String },
+}
+
+/// How long `source` plays for. An image sequence lasts its frame count at its framerate
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn duration(source: &PlanPath, ct: CancellationToken) -> Result<Duration, DurationError> {
+    if let Some(framerate) = source.framerate {
+        return Ok(Duration::from_secs_f64(
+            source.count_frames() as f64 / framerate,
+        ));
+    }
+
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-show_entries").arg("format=duration");
+        cmd.arg("-of").arg("default=noprint_wrappers=1:nokey=1");
+        cmd.arg(&source.path);
+    })
+    .await?;
+
+    results
+        .stdout_lines
+        .first()
+        .and_then(|line| line.trim().parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| DurationError::Missing {
+            path: source.path.display().to_string(),
+        })
+}
+
+/// Whether `path` has at least one audio stream
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn has_audio(path: PathBuf, ct: CancellationToken) -> Result<bool, CommandError> {
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-select_streams").arg("a");
+        cmd.arg("-show_entries").arg("stream=codec_type");
+        cmd.arg("-of").arg("default=noprint_wrappers=1:nokey=1");
+        cmd.arg(path);
+    })
+    .await?;
+
+    let exited_normally = results
+        .exit_code
+        .map(|code| code.success)
+        .unwrap_or_default();
+    let has_stdout = !results.stdout_lines.is_empty();
+    let stdout_has_text = !results
+        .stdout_lines
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+        .is_empty();
+
+    Ok(exited_normally && has_stdout && stdout_has_text)
+}
+
+/// Time of the last video keyframe at or before `seconds` into `path`, from the flags of the packets up to it
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn keyframe_before(
+    path: PathBuf,
+    seconds: f64,
+    ct: CancellationToken,
+) -> Result<Option<f64>, CommandError> {
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-select_streams").arg("v:0");
+        cmd.arg("-show_entries").arg("packet=pts_time,flags");
+        cmd.arg("-of").arg("csv=p=0");
+        // Packets past the cut can't be the keyframe before it, so reading stops shortly after
+        cmd.arg("-read_intervals")
+            .arg(format!("%{}", seconds + 1.0));
+        cmd.arg(path);
+    })
+    .await?;
+
+    Ok(results
+        .stdout_lines
+        .iter()
+        .filter_map(|line| {
+            let (pts_time, flags) = line.trim().split_once(',')?;
+            let pts_time = pts_time.parse::<f64>().ok()?;
+            (flags.starts_with('K') && pts_time <= seconds).then_some(pts_time)
+        })
+        .reduce(f64::max))
+}
+
+/// `(codec_type, codec_name)` of every stream in `path`
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn streams(
+    path: PathBuf,
+    ct: CancellationToken,
+) -> Result<Vec<(String, String)>, CommandError> {
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-show_entries").arg("stream=codec_type,codec_name");
+        cmd.arg("-of").arg("csv=p=0");
+        cmd.arg(path);
+    })
+    .await?;
+
+    // ffprobe prints the entries in its own order, name first
+    Ok(results
+        .stdout_lines
+        .iter()
+        .filter_map(|line| line.trim().split_once(','))
+        .map(|(codec_name, codec_type)| (codec_type.to_string(), codec_name.to_string()))
+        .collect())
+}
+
+/// Width and height of the first video stream in `path`, if it has one
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn resolution(
+    path: PathBuf,
+    ct: CancellationToken,
+) -> Result<Option<(u32, u32)>, CommandError> {
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-select_streams").arg("v:0");
+        cmd.arg("-show_entries").arg("stream=width,height");
+        cmd.arg("-of").arg("csv=p=0:s=x");
+        cmd.arg(path);
+    })
+    .await?;
+
+    Ok(results
+        .stdout_lines
+        .first()
+        .and_then(|line| line.trim().split_once('x'))
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?))))
+}
+
+/// The `color_space` tag and height of the first video stream in `path`, if it has one. The tag is `None` when it's unset
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn color_space(
+    path: PathBuf,
+    ct: CancellationToken,
+) -> Result<Option<(Option<String>, u32)>, CommandError> {
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-select_streams").arg("v:0");
+        cmd.arg("-show_entries").arg("stream=height,color_space");
+        cmd.arg("-of").arg("default=noprint_wrappers=1");
+        cmd.arg(path);
+    })
+    .await?;
+
+    let value = |key: &str| {
+        results.stdout_lines.iter().find_map(|line| {
+            line.trim()
+                .strip_prefix(key)
+                .and_then(|line| line.strip_prefix('='))
+        })
+    };
+    let Some(height) = value("height").and_then(|height| height.parse().ok()) else {
+        return Ok(None);
+    };
+    let color_space = value("color_space")
+        .filter(|color_space| *color_space != "unknown")
+        .map(str::to_string);
+
+    Ok(Some((color_space, height)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioFormat {
+    /// Streams without a layout are named after their channel count, e.g. `2 channels`
+    pub channel_layout: String,
+    pub sample_rate: u32,
+}
+
+/// Channel layout and sample rate of the first audio stream in `path`, if it has one
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn audio_format(
+    path: PathBuf,
+    ct: CancellationToken,
+) -> Result<Option<AudioFormat>, CommandError> {
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-select_streams").arg("a:0");
+        cmd.arg("-show_entries")
+            .arg("stream=sample_rate,channels,channel_layout");
+        cmd.arg("-of").arg("default=noprint_wrappers=1");
+        cmd.arg(path);
+    })
+    .await?;
+
+    let value = |key: &str| {
+        results.stdout_lines.iter().find_map(|line| {
+            line.trim()
+                .strip_prefix(key)
+                .and_then(|line| line.strip_prefix('='))
+        })
+    };
+    let Some(channels) = value("channels") else {
+        return Ok(None);
+    };
+
+    Ok(Some(AudioFormat {
+        channel_layout: value("channel_layout")
+            .filter(|layout| !layout.is_empty() && *layout != "unknown")
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{channels} channels")),
+        sample_rate: value("sample_rate")
+            .and_then(|sample_rate| sample_rate.parse().ok())
+            .unwrap_or_default(),
+    }))
+}
+
+/// Shortest stretch of black video or silence [gaps] reports
+const MIN_GAP_SECONDS: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapKind {
+    Black,
+    Silence,
+}
+
+/// A stretch of black video or silence, in seconds from the start
+#[derive(Debug, Clone, Copy)]
+pub struct Gap {
+    pub kind: GapKind,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Stretches of black video and silence in `path`, which plays for `duration`. Decodes the whole file
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn gaps(
+    path: PathBuf,
+    video: bool,
+    audio: bool,
+    duration: f64,
+    ct: CancellationToken,
+) -> Result<Vec<Gap>, CommandError> {
+    let results = cmd::run("ffmpeg", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-nostats");
+        cmd.arg("-i").arg(path);
+        // The detectors tag frames with where each stretch starts and ends, printed to stdout
+        if video {
+            cmd.arg("-vf").arg(format!(
+                "blackdetect=d={MIN_GAP_SECONDS}:pix_th=0.10,metadata=mode=print:file=pipe\\:1"
+            ));
+        } else {
+            cmd.arg("-vn");
+        }
+        if audio {
+            cmd.arg("-af").arg(format!(
+                "silencedetect=n=-50dB:d={MIN_GAP_SECONDS},ametadata=mode=print:file=pipe\\:1"
+            ));
+        } else {
+            cmd.arg("-an");
+        }
+        cmd.arg("-f").arg("null");
+        cmd.arg("-");
+    })
+    .await?;
+
+    let mut gaps: Vec<Gap> = vec![];
+    for line in results.stdout_lines.iter() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let Ok(seconds) = value.parse::<f64>() else {
+            continue;
+        };
+        let kind = match key {
+            "lavfi.black_start" | "lavfi.black_end" => GapKind::Black,
+            "lavfi.silence_start" | "lavfi.silence_end" => GapKind::Silence,
+            _ => continue,
+        };
+
+        if key.ends_with("_start") {
+            gaps.push(Gap {
+                kind,
+                start: seconds,
+                end: duration,
+            });
+        } else if let Some(gap) = gaps.iter_mut().rev().find(|gap| gap.kind == kind) {
+            gap.end = seconds;
+        }
+    }
+
+    Ok(gaps)
+}
+
+/// Largest difference between where a packet was expected and where it's stamped before [timestamp_jumps] reports it
+const MAX_TIMESTAMP_GAP_SECONDS: f64 = 0.5;
+
+/// A packet stamped away from where the one before it ended, in seconds
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampJump {
+    pub stream_index: usize,
+    /// Where the packet was expected
+    pub at: f64,
+    /// Negative when the timestamps go backwards
+    pub gap: f64,
+}
+
+/// Discontinuities in the decode timestamps of each stream in `path`. Only reads packets, nothing's decoded
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn timestamp_jumps(
+    path: PathBuf,
+    ct: CancellationToken,
+) -> Result<Vec<TimestampJump>, CommandError> {
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-show_entries")
+            .arg("packet=stream_index,dts_time,duration_time");
+        cmd.arg("-of").arg("csv=p=0");
+        cmd.arg(path);
+    })
+    .await?;
+
+    let mut expected: HashMap<usize, f64> = HashMap::new();
+    let mut jumps = vec![];
+    for line in results.stdout_lines.iter() {
+        let mut fields = line.trim().split(',');
+        let (Some(Ok(stream_index)), Some(Ok(dts))) = (
+            fields.next().map(str::parse::<usize>),
+            fields.next().map(str::parse::<f64>),
+        ) else {
+            continue;
+        };
+        let duration = fields
+            .next()
+            .and_then(|duration| duration.parse::<f64>().ok())
+            .unwrap_or_default();
+
+        if let Some(at) = expected.insert(stream_index, dts + duration) {
+            let gap = dts - at;
+            if gap.abs() > MAX_TIMESTAMP_GAP_SECONDS {
+                jumps.push(TimestampJump {
+                    stream_index,
+                    at,
+                    gap,
+                });
+            }
+        }
+    }
+
+    Ok(jumps)
+}
+
+/// Bitrate of a source's video stream, in bits per second
+#[derive(Debug, Clone, Copy)]
+pub struct Bitrate {
+    pub average: f64,
+    /// Highest over any one second
+    pub peak: f64,
+}
+
+/// Average and peak bitrate of the first video stream in `path`, from the size of every packet. Only reads packets,
+/// nothing's decoded. `None` without video
+#[instrument(level = Level::DEBUG, skip(ct))]
+pub async fn bitrate(
+    path: PathBuf,
+    ct: CancellationToken,
+) -> Result<Option<Bitrate>, CommandError> {
+    let results = cmd::run("ffprobe", None, ct, |cmd| {
+        env::apply_wrappers(cmd);
+        limits::apply_priority(cmd);
+        cmd.arg("-v").arg("error");
+        cmd.arg("-select_streams").arg("v:0");
+        cmd.arg("-show_entries")
+            .arg("packet=dts_time,duration_time,size");
+        cmd.arg("-of").arg("csv=p=0");
+        cmd.arg(path);
+    })
+    .await?;
+
+    let mut bytes_per_second: HashMap<i64, u64> = HashMap::new();
+    let mut total_bytes = 0;
+    let mut span: Option<(f64, f64)> = None;
+    for line in results.stdout_lines.iter() {
+        let mut fields = line.trim().split(',');
+        let (Some(Ok(dts)), duration, Some(Ok(size))) = (
+            fields.next().map(str::parse::<f64>),
+            fields
+                .next()
+                .and_then(|duration| duration.parse::<f64>().ok()),
+            fields.next().map(str::parse::<u64>),
+        ) else {
+            continue;
+        };
+
+        *bytes_per_second.entry(dts.floor() as i64).or_default() += size;
+        total_bytes += size;
+        let end = dts + duration.unwrap_or_default();
+        span = Some(match span {
+            Some((start, last)) => (start.min(dts), last.max(end)),
+            None => (dts, end),
+        });
+    }
+
+    let Some((start, end)) = span.filter(|(start, end)| end > start) else {
+        return Ok(None);
+    };
+    Ok(Some(Bitrate {
+        average: total_bytes as f64 * 8.0 / (end - start),
+        peak: bytes_per_second.values().copied().max().unwrap_or_default() as f64 * 8.0,
+    }))
+}
+
+/// e.g. `4.2 Mb/s`
+pub fn format_bitrate(bits_per_second: f64) -> String {
+    if bits_per_second >= 1_000_000.0 {
+        format!("{:.1} Mb/s", bits_per_second / 1_000_000.0)
+    } else {
+        format!("{:.0} kb/s", bits_per_second / 1_000.0)
+    }
+}
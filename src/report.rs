@@ -0,0 +1,219 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::{sync::mpsc, task::JoinHandle};
+use uuid::Uuid;
+
+use crate::{
+    execute::{ExecuteError, ExecuteProgress, ExecuteProgressPayload},
+    parse::Plan,
+    probe::{self, BuildInfo},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetStatus {
+    Pending,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TargetReport {
+    pub target_name: String,
+    pub target_path: PathBuf,
+    pub sources: Vec<PathBuf>,
+    pub status: TargetStatus,
+    pub progress_pct: f64,
+    pub mode: Option<String>,
+    /// Wall clock time from the plan starting to it settling
+    pub elapsed_seconds: Option<f64>,
+    /// Combined duration of the sources
+    pub media_duration_seconds: Option<f64>,
+    pub output_size_bytes: Option<u64>,
+    pub command_line: Option<String>,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+    /// [ExecuteError::code] of `error`
+    pub error_code: Option<u16>,
+
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+/// Machine readable summary of a run, written with `--report`
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct RunReport {
+    pub version: &'static str,
+    /// Matches the name of the run's log file
+    pub run_id: &'static str,
+    pub specs: Vec<PathBuf>,
+    /// How the ffmpeg that ran was built, when it could be read
+    pub ffmpeg: Option<BuildInfo>,
+    /// Seconds since the unix epoch
+    pub started_at: u64,
+    pub elapsed_seconds: f64,
+    pub finished: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub targets: Vec<TargetReport>,
+
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    ids: HashMap<Uuid, usize>,
+}
+
+impl RunReport {
+    pub fn new(specs: Vec<PathBuf>, plans: &[Plan]) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            run_id: crate::path::run_id(),
+            specs,
+            ffmpeg: probe::ffmpeg_build().cloned(),
+            started_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default(),
+            elapsed_seconds: 0.0,
+            finished: 0,
+            failed: 0,
+            cancelled: 0,
+            targets: plans
+                .iter()
+                .map(|plan| TargetReport {
+                    target_name: plan.target_path.leaf.clone(),
+                    target_path: plan.target_path.path.clone(),
+                    sources: plan
+                        .sources
+                        .iter()
+                        .map(|source| source.path.clone())
+                        .collect(),
+                    status: TargetStatus::Pending,
+                    progress_pct: 0.0,
+                    mode: None,
+                    elapsed_seconds: None,
+                    media_duration_seconds: None,
+                    output_size_bytes: None,
+                    command_line: None,
+                    warnings: vec![],
+                    error: None,
+                    error_code: None,
+                    started: None,
+                })
+                .collect(),
+            started: Instant::now(),
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, delivery: &ExecuteProgress) {
+        if let ExecuteProgressPayload::Start { target_name } = &delivery.payload
+            && let Some(index) = self.targets.iter().position(|target| {
+                target.status == TargetStatus::Pending && &target.target_name == target_name
+            })
+        {
+            self.ids.insert(delivery.id, index);
+            self.targets[index].status = TargetStatus::Running;
+            self.targets[index].started = Some(Instant::now());
+            return;
+        }
+
+        let Some(target) = self
+            .ids
+            .get(&delivery.id)
+            .and_then(|index| self.targets.get_mut(*index))
+        else {
+            return;
+        };
+
+        match &delivery.payload {
+            ExecuteProgressPayload::Info {
+                total_duration_seconds,
+                mode,
+                ..
+            } => {
+                target.media_duration_seconds = Some(*total_duration_seconds);
+                target.mode = Some(mode.clone());
+            }
+            ExecuteProgressPayload::Command { command_line } => {
+                target.command_line = Some(command_line.clone());
+            }
+            ExecuteProgressPayload::Warning { message } => {
+                target.warnings.push(message.clone());
+            }
+            ExecuteProgressPayload::Progress {
+                total_seconds,
+                current_seconds,
+            } => {
+                target.progress_pct = (current_seconds / total_seconds * 100.0).min(100.0);
+            }
+            ExecuteProgressPayload::Finished(_) => {
+                target.status = TargetStatus::Finished;
+                target.progress_pct = 100.0;
+                target.elapsed_seconds = target.started.map(|s| s.elapsed().as_secs_f64());
+            }
+            ExecuteProgressPayload::Failed(error) => {
+                target.status = match error {
+                    ExecuteError::Cancelled => TargetStatus::Cancelled,
+                    _ => TargetStatus::Failed,
+                };
+                target.error = Some(error.to_string());
+                target.error_code = Some(error.code());
+                target.elapsed_seconds = target.started.map(|s| s.elapsed().as_secs_f64());
+            }
+            _ => {}
+        }
+    }
+
+    /// Settles the totals once every plan has finished. `cancelled` are targets the run cancelled after they failed
+    pub fn finish(&mut self, cancelled: &[String]) {
+        self.elapsed_seconds = self.started.elapsed().as_secs_f64();
+
+        for target in self.targets.iter_mut() {
+            if matches!(target.status, TargetStatus::Pending | TargetStatus::Running)
+                || cancelled.contains(&target.target_name)
+            {
+                target.status = TargetStatus::Cancelled;
+            }
+
+            if target.status == TargetStatus::Finished {
+                target.output_size_bytes = std::fs::metadata(&target.target_path)
+                    .map(|meta| meta.len())
+                    .ok();
+            }
+        }
+
+        let count = |status| {
+            self.targets
+                .iter()
+                .filter(|target| target.status == status)
+                .count()
+        };
+        self.finished = count(TargetStatus::Finished);
+        self.failed = count(TargetStatus::Failed);
+        self.cancelled = count(TargetStatus::Cancelled);
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let content = serde_json::to_string_pretty(self).context("Failed to serialize report")?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write report to {}", path.display()))
+    }
+}
+
+/// Records every delivery into `report` on the way to the returned receiver. The report is returned once the channel closes
+pub fn record(
+    report: RunReport,
+    rx: mpsc::Receiver<ExecuteProgress>,
+) -> (mpsc::Receiver<ExecuteProgress>, JoinHandle<RunReport>) {
+    crate::monitor::tap_with(rx, report, RunReport::record)
+}
@@ -0,0 +1,251 @@
+//! Every run's specs, targets and outcomes, kept in a SQLite database under the data directory
+
+use std::path::Path;
+
+use anyhow::Context;
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{path, report::RunReport};
+
+/// Applied in order, the database's `user_version` is how many have been applied
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE runs (
+        run_id TEXT PRIMARY KEY NOT NULL,
+        started_at INTEGER NOT NULL,
+        elapsed_seconds REAL NOT NULL,
+        stitch_version TEXT NOT NULL,
+        ffmpeg_version TEXT,
+        finished INTEGER NOT NULL,
+        failed INTEGER NOT NULL,
+        cancelled INTEGER NOT NULL
+    );
+    CREATE TABLE specs (
+        run_id TEXT NOT NULL REFERENCES runs (run_id) ON DELETE CASCADE,
+        path TEXT NOT NULL,
+        sha256 TEXT
+    );
+    CREATE TABLE targets (
+        run_id TEXT NOT NULL REFERENCES runs (run_id) ON DELETE CASCADE,
+        target_name TEXT NOT NULL,
+        target_path TEXT NOT NULL,
+        sources TEXT NOT NULL,
+        status TEXT NOT NULL,
+        mode TEXT,
+        elapsed_seconds REAL,
+        media_duration_seconds REAL,
+        output_size_bytes INTEGER,
+        command_line TEXT,
+        error TEXT
+    );
+    CREATE INDEX targets_by_path ON targets (target_path);
+",
+    "ALTER TABLE targets ADD COLUMN error_code INTEGER;",
+];
+
+/// Opens the run database, creating it or bringing its schema up to date as needed
+pub fn open() -> anyhow::Result<Connection> {
+    let path = path::runs_database_path();
+    std::fs::create_dir_all(path::data_root())
+        .with_context(|| format!("Failed to create {}", path::data_root().display()))?;
+    let mut connection = Connection::open(&path)
+        .with_context(|| format!("Failed to open run database {}", path.display()))?;
+    // Concurrent runs each write once they finish
+    connection.busy_timeout(std::time::Duration::from_secs(5))?;
+    connection.pragma_update(None, "foreign_keys", true)?;
+
+    let applied: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if applied < MIGRATIONS.len() {
+        let transaction = connection.transaction()?;
+        for migration in MIGRATIONS[applied..].iter() {
+            transaction.execute_batch(migration)?;
+        }
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        transaction
+            .commit()
+            .context("Failed to update run database schema")?;
+    }
+
+    Ok(connection)
+}
+
+/// Hex encoded SHA-256 of the spec's contents, `None` if it can't be read anymore
+fn spec_hash(spec: &Path) -> Option<String> {
+    let contents = std::fs::read(spec).ok()?;
+    Some(
+        Sha256::digest(contents)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect(),
+    )
+}
+
+/// Adds a finished run to the database under `run_id`, which differs from the report's for each of a server's jobs
+pub fn record(run_id: &str, report: &RunReport) -> anyhow::Result<()> {
+    let mut connection = open()?;
+    let transaction = connection.transaction()?;
+
+    transaction.execute(
+        "INSERT INTO runs (run_id, started_at, elapsed_seconds, stitch_version, ffmpeg_version, finished, failed, cancelled)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            run_id,
+            report.started_at,
+            report.elapsed_seconds,
+            report.version,
+            report.ffmpeg.as_ref().map(|build| &build.version),
+            report.finished,
+            report.failed,
+            report.cancelled,
+        ],
+    )?;
+
+    for spec in report.specs.iter() {
+        let spec_path = std::path::absolute(spec).unwrap_or_else(|_| spec.clone());
+        transaction.execute(
+            "INSERT INTO specs (run_id, path, sha256) VALUES (?1, ?2, ?3)",
+            params![run_id, spec_path.display().to_string(), spec_hash(spec)],
+        )?;
+    }
+
+    for target in report.targets.iter() {
+        transaction.execute(
+            "INSERT INTO targets (run_id, target_name, target_path, sources, status, mode, elapsed_seconds, media_duration_seconds, output_size_bytes, command_line, error, error_code)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                run_id,
+                target.target_name,
+                target.target_path.display().to_string(),
+                serde_json::to_string(&target.sources)?,
+                serde_json::to_value(target.status)?.as_str(),
+                target.mode,
+                target.elapsed_seconds,
+                target.media_duration_seconds,
+                target.output_size_bytes,
+                target.command_line,
+                target.error,
+                target.error_code,
+            ],
+        )?;
+    }
+
+    transaction
+        .commit()
+        .context("Failed to record run in the run database")
+}
+
+/// A run as `stitch history` lists it
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub run_id: String,
+    /// Seconds since the unix epoch
+    pub started_at: i64,
+    pub elapsed_seconds: f64,
+    pub stitch_version: String,
+    pub ffmpeg_version: Option<String>,
+    pub specs: Vec<String>,
+    pub finished: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+}
+
+/// One build of a target, from the run it was part of
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetRun {
+    pub run_id: String,
+    /// Seconds since the unix epoch the run started at
+    pub started_at: i64,
+    pub target_name: String,
+    pub target_path: String,
+    pub sources: Vec<String>,
+    pub status: String,
+    pub mode: Option<String>,
+    pub elapsed_seconds: Option<f64>,
+    pub media_duration_seconds: Option<f64>,
+    pub output_size_bytes: Option<u64>,
+    pub error: Option<String>,
+    pub error_code: Option<u16>,
+}
+
+/// The most recent `limit` runs, newest first. With `failed_only`, only runs where a target failed
+pub fn recent_runs(
+    connection: &Connection,
+    limit: usize,
+    failed_only: bool,
+) -> anyhow::Result<Vec<RunSummary>> {
+    let mut statement = connection.prepare(
+        "SELECT run_id, started_at, elapsed_seconds, stitch_version, ffmpeg_version, finished, failed, cancelled
+         FROM runs
+         WHERE NOT ?1 OR failed > 0
+         ORDER BY started_at DESC, rowid DESC
+         LIMIT ?2",
+    )?;
+    let mut runs = statement
+        .query_map(params![failed_only, limit], |row| {
+            Ok(RunSummary {
+                run_id: row.get(0)?,
+                started_at: row.get(1)?,
+                elapsed_seconds: row.get(2)?,
+                stitch_version: row.get(3)?,
+                ffmpeg_version: row.get(4)?,
+                specs: vec![],
+                finished: row.get(5)?,
+                failed: row.get(6)?,
+                cancelled: row.get(7)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut statement = connection.prepare("SELECT path FROM specs WHERE run_id = ?1")?;
+    for run in runs.iter_mut() {
+        run.specs = statement
+            .query_map(params![run.run_id], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+
+    Ok(runs)
+}
+
+/// The most recent `limit` builds of targets named `target`, or written to it when it's a path, newest first. With
+/// `failed_only`, only the ones that failed
+pub fn target_runs(
+    connection: &Connection,
+    target: &str,
+    limit: usize,
+    failed_only: bool,
+) -> anyhow::Result<Vec<TargetRun>> {
+    let target_path = std::path::absolute(target)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| target.to_string());
+    let mut statement = connection.prepare(
+        "SELECT targets.run_id, runs.started_at, target_name, target_path, sources, status, mode,
+                targets.elapsed_seconds, media_duration_seconds, output_size_bytes, error, error_code
+         FROM targets JOIN runs ON runs.run_id = targets.run_id
+         WHERE (target_name = ?1 OR target_path = ?2) AND (NOT ?3 OR status = 'failed')
+         ORDER BY runs.started_at DESC, targets.rowid DESC
+         LIMIT ?4",
+    )?;
+    let runs = statement
+        .query_map(params![target, target_path, failed_only, limit], |row| {
+            let sources: String = row.get(4)?;
+            Ok(TargetRun {
+                run_id: row.get(0)?,
+                started_at: row.get(1)?,
+                target_name: row.get(2)?,
+                target_path: row.get(3)?,
+                sources: serde_json::from_str(&sources).unwrap_or_default(),
+                status: row.get(5)?,
+                mode: row.get(6)?,
+                elapsed_seconds: row.get(7)?,
+                media_duration_seconds: row.get(8)?,
+                output_size_bytes: row.get(9)?,
+                error: row.get(10)?,
+                error_code: row.get(11)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(runs)
+}
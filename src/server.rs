@@ -0,0 +1,348 @@
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Response, Sse,
+        sse::{Event, KeepAlive},
+    },
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, broadcast};
+use tokio_stream::{StreamExt, wrappers::BroadcastStream};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    execute::{ExecuteOptions, ExecuteProgress, PlanExecutions, execute_plans},
+    metrics,
+    parse::{Flag, ParseOptions, Plan, PlanBuilder, parse_spec, validate_plans},
+    path,
+    report::RunReport,
+    runs, throughput,
+};
+
+/// Deliveries buffered for each event stream before a slow client starts missing some
+const EVENT_BUFFER: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+struct Job {
+    id: Uuid,
+    status: JobStatus,
+    report: RunReport,
+    token: CancellationToken,
+    /// Each plan's token by target name, so plans can be cancelled on their own
+    target_tokens: HashMap<String, CancellationToken>,
+    /// Closed once every plan has settled
+    events: Option<broadcast::Sender<ExecuteProgress>>,
+}
+
+#[derive(Serialize)]
+struct JobView<'a> {
+    id: Uuid,
+    status: JobStatus,
+    #[serde(flatten)]
+    report: &'a RunReport,
+}
+impl<'a> From<&'a Job> for JobView<'a> {
+    fn from(job: &'a Job) -> Self {
+        Self {
+            id: job.id,
+            status: job.status,
+            report: &job.report,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SubmitPlan {
+    target: String,
+    sources: Vec<String>,
+    #[serde(default)]
+    flags: Vec<Flag>,
+    fade: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubmitJob {
+    /// Path to a spec on this machine
+    spec_path: Option<PathBuf>,
+    /// Contents of a spec, written to the temp directory
+    spec: Option<String>,
+    /// Plans to run without a spec
+    plans: Option<Vec<SubmitPlan>>,
+    /// Defaults to the server's working directory
+    target_dir: Option<PathBuf>,
+    /// Defaults to the server's working directory
+    sources_dir: Option<PathBuf>,
+}
+
+struct ServerState {
+    jobs: Mutex<Vec<Job>>,
+    token: CancellationToken,
+}
+
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Response {
+    (
+        status,
+        Json(serde_json::json!({ "error": message.to_string() })),
+    )
+        .into_response()
+}
+
+fn job_not_found(id: Uuid) -> Response {
+    error(StatusCode::NOT_FOUND, format!("No job {id}"))
+}
+
+impl ServerState {
+    /// Starts every plan in the background, returning the job's id
+    async fn start(self: &Arc<Self>, spec: PathBuf, plans: Vec<Plan>) -> Uuid {
+        let id = Uuid::new_v4();
+        let token = self.token.child_token();
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let report = RunReport::new(vec![spec], &plans);
+
+        // Jobs can share target names, so each keeps its intermediates apart
+        let tmp_root = path::run_tmp_root().join(id.to_string());
+        if let Err(e) = std::fs::create_dir_all(&tmp_root) {
+            tracing::error!(job_id =% id, path =% tmp_root.display(), error =% e, error_context =? e, "Failed to create job temp directory");
+        }
+
+        let PlanExecutions {
+            tokens: target_tokens,
+            mut progress,
+        } = execute_plans(plans, tmp_root, ExecuteOptions::default(), token.clone());
+
+        tracing::info!(job_id =% id, targets = target_tokens.len(), "Started job");
+        self.jobs.lock().await.push(Job {
+            id,
+            status: JobStatus::Running,
+            report,
+            token,
+            target_tokens,
+            events: Some(events),
+        });
+
+        let state = self.clone();
+        tokio::spawn(async move {
+            while let Some(delivery) = progress.next().await {
+                metrics::record(&delivery);
+                throughput::record(&delivery);
+                let mut jobs = state.jobs.lock().await;
+                if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                    job.report.record(&delivery);
+                    if let Some(events) = &job.events {
+                        // Nobody listening isn't an error
+                        let _ = events.send(delivery);
+                    }
+                }
+            }
+
+            let mut jobs = state.jobs.lock().await;
+            if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                job.report.finish(&[]);
+                job.status = if job.report.failed > 0 {
+                    JobStatus::Failed
+                } else if job.report.cancelled > 0 {
+                    JobStatus::Cancelled
+                } else {
+                    JobStatus::Finished
+                };
+                job.events = None;
+                tracing::info!(job_id =% id, status =? job.status, "Job settled");
+
+                let run_id = format!("{}/{id}", path::run_id());
+                if let Err(e) = runs::record(&run_id, &job.report) {
+                    tracing::warn!(job_id =% id, error =% e, error_context =? e, "Failed to record job in the run database");
+                }
+            }
+        });
+
+        id
+    }
+}
+
+async fn submit_job(
+    State(state): State<Arc<ServerState>>,
+    Json(body): Json<SubmitJob>,
+) -> Response {
+    let cwd = match std::env::current_dir() {
+        Ok(cwd) => cwd,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    let target_dir = body.target_dir.unwrap_or(cwd.clone());
+    let sources_dir = body.sources_dir.unwrap_or(cwd);
+    if let Err(e) = std::fs::create_dir_all(&target_dir) {
+        return error(StatusCode::UNPROCESSABLE_ENTITY, e);
+    }
+
+    let (spec_path, plans) = match (body.spec_path, body.spec, body.plans) {
+        (Some(spec_path), None, None) => (
+            spec_path.clone(),
+            parse_spec(spec_path, target_dir, sources_dir, ParseOptions::default())
+                .map(|(plans, _)| plans),
+        ),
+        (None, Some(spec), None) => {
+            let spec_path = path::run_tmp_root().join(format!("{}.stitchspec", Uuid::new_v4()));
+            if let Err(e) = std::fs::write(&spec_path, spec) {
+                tracing::error!(path =% spec_path.display(), error =% e, error_context =? e, "Failed to write submitted spec");
+                return error(StatusCode::INTERNAL_SERVER_ERROR, e);
+            }
+            (
+                spec_path.clone(),
+                parse_spec(spec_path, target_dir, sources_dir, ParseOptions::default())
+                    .map(|(plans, _)| plans),
+            )
+        }
+        (None, None, Some(submitted)) => {
+            let plans = submitted
+                .into_iter()
+                .map(|submitted| {
+                    let builder = submitted
+                        .flags
+                        .into_iter()
+                        .fold(PlanBuilder::new(submitted.target), PlanBuilder::flag);
+                    match submitted.fade {
+                        Some(fade) => builder.fade(fade),
+                        None => builder,
+                    }
+                    .target_dir(&target_dir)
+                    .sources_dir(&sources_dir)
+                    .sources(submitted.sources)
+                    .build()
+                })
+                .collect::<Result<Vec<_>, _>>()
+                // Each plan is valid on its own, but their targets can still collide
+                .and_then(|plans| validate_plans(&plans).map(|()| plans));
+            (PathBuf::new(), plans)
+        }
+        _ => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "Expected exactly one of spec_path, spec or plans",
+            );
+        }
+    };
+
+    let plans = match plans {
+        Ok(plans) => plans,
+        Err(e) => return (StatusCode::UNPROCESSABLE_ENTITY, Json(e)).into_response(),
+    };
+
+    let id = state.start(spec_path, plans).await;
+    (StatusCode::CREATED, Json(serde_json::json!({ "id": id }))).into_response()
+}
+
+async fn list_jobs(State(state): State<Arc<ServerState>>) -> Response {
+    let jobs = state.jobs.lock().await;
+    Json(jobs.iter().map(JobView::from).collect::<Vec<_>>()).into_response()
+}
+
+async fn get_job(State(state): State<Arc<ServerState>>, Path(id): Path<Uuid>) -> Response {
+    let jobs = state.jobs.lock().await;
+    match jobs.iter().find(|job| job.id == id) {
+        Some(job) => Json(JobView::from(job)).into_response(),
+        None => job_not_found(id),
+    }
+}
+
+/// Server sent events, one per delivery, until the job settles
+async fn job_events(State(state): State<Arc<ServerState>>, Path(id): Path<Uuid>) -> Response {
+    let rx = {
+        let jobs = state.jobs.lock().await;
+        let Some(job) = jobs.iter().find(|job| job.id == id) else {
+            return job_not_found(id);
+        };
+        match &job.events {
+            Some(events) => events.subscribe(),
+            // Settled jobs get a stream that ends straight away
+            None => broadcast::channel(1).1,
+        }
+    };
+
+    let stream = BroadcastStream::new(rx).filter_map(|delivery| {
+        let delivery = delivery.ok()?;
+        Event::default()
+            .json_data(&delivery)
+            .ok()
+            .map(Ok::<_, Infallible>)
+    });
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn cancel_job(State(state): State<Arc<ServerState>>, Path(id): Path<Uuid>) -> Response {
+    let jobs = state.jobs.lock().await;
+    let Some(job) = jobs.iter().find(|job| job.id == id) else {
+        return job_not_found(id);
+    };
+
+    tracing::info!(job_id =% id, "Cancelling job");
+    job.token.cancel();
+    StatusCode::ACCEPTED.into_response()
+}
+
+async fn cancel_target(
+    State(state): State<Arc<ServerState>>,
+    Path((id, target_name)): Path<(Uuid, String)>,
+) -> Response {
+    let jobs = state.jobs.lock().await;
+    let Some(job) = jobs.iter().find(|job| job.id == id) else {
+        return job_not_found(id);
+    };
+    let Some(token) = job.target_tokens.get(&target_name) else {
+        return error(
+            StatusCode::NOT_FOUND,
+            format!("No target \"{target_name}\" in job {id}"),
+        );
+    };
+
+    tracing::info!(job_id =% id, target_name = target_name, "Cancelling target");
+    token.cancel();
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Accepts jobs over HTTP until `token` is cancelled
+pub async fn serve(listen: SocketAddr, token: CancellationToken) -> anyhow::Result<()> {
+    let state = Arc::new(ServerState {
+        jobs: Mutex::new(vec![]),
+        token: token.clone(),
+    });
+
+    let router = Router::new()
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/{id}", get(get_job))
+        .route("/jobs/{id}/events", get(job_events))
+        .route("/jobs/{id}/cancel", post(cancel_job))
+        .route("/jobs/{id}/targets/{target}/cancel", post(cancel_target))
+        .with_state(state)
+        .merge(metrics::router());
+
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {listen}"))?;
+
+    if !listen.ip().is_loopback() {
+        tracing::warn!(listen =% listen, "The API has no authentication, anyone who can reach {listen} can submit and cancel jobs");
+    }
+    println!("Listening on http://{listen}");
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move { token.cancelled().await })
+        .await
+        .context("Server failed")
+}
@@ -0,0 +1,112 @@
+use std::{io::IsTerminal, sync::OnceLock};
+
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+/// When output is colored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Only on a terminal, and only when NO_COLOR isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Colors used for each kind of status
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+    #[default]
+    Default,
+    /// Bright, bold colors that stay readable on dim or low contrast displays
+    HighContrast,
+}
+
+/// What a piece of output says about a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    Failure,
+    Warning,
+    Progress,
+}
+
+/// `None` when output isn't colored
+static THEME: OnceLock<Option<Theme>> = OnceLock::new();
+
+/// Settles whether output is colored and how, before anything is printed
+pub fn set(choice: ColorChoice, theme: Theme) {
+    let colored = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        // https://no-color.org asks for any non-empty value to turn color off
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::io::stdout().is_terminal()
+        }
+    };
+    let _ = THEME.set(colored.then_some(theme));
+}
+
+fn theme() -> Option<Theme> {
+    THEME.get().copied().flatten()
+}
+
+pub fn is_colored() -> bool {
+    theme().is_some()
+}
+
+/// Style for text showing `status`, plain when output isn't colored
+pub fn style(status: Status) -> Style {
+    let Some(theme) = theme() else {
+        return Style::new();
+    };
+
+    match theme {
+        Theme::Default => Style::new().fg(match status {
+            Status::Success => Color::Green,
+            Status::Failure => Color::Red,
+            Status::Warning => Color::Yellow,
+            Status::Progress => Color::Cyan,
+        }),
+        Theme::HighContrast => Style::new()
+            .fg(match status {
+                Status::Success => Color::LightGreen,
+                Status::Failure => Color::LightRed,
+                Status::Warning => Color::LightYellow,
+                Status::Progress => Color::LightCyan,
+            })
+            .add_modifier(Modifier::BOLD),
+    }
+}
+
+/// `text` wrapped in the escape codes for `status`, for output printed outside the TUI
+pub fn paint(status: Status, text: &str) -> String {
+    use crossterm::style::{Attribute, Color, Stylize};
+
+    let Some(theme) = theme() else {
+        return text.to_string();
+    };
+
+    match theme {
+        Theme::Default => text
+            .with(match status {
+                Status::Success => Color::DarkGreen,
+                Status::Failure => Color::DarkRed,
+                Status::Warning => Color::DarkYellow,
+                Status::Progress => Color::DarkCyan,
+            })
+            .to_string(),
+        Theme::HighContrast => text
+            .with(match status {
+                Status::Success => Color::Green,
+                Status::Failure => Color::Red,
+                Status::Warning => Color::Yellow,
+                Status::Progress => Color::Cyan,
+            })
+            .attribute(Attribute::Bold)
+            .to_string(),
+    }
+}
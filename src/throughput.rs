@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    execute::{ExecuteProgress, ExecuteProgressPayload},
+    path,
+};
+
+/// Plans shorter than this say more about ffmpeg's startup than its throughput
+const MIN_SAMPLE: Duration = Duration::from_secs(2);
+
+/// Past samples count for less once this many have been recorded, so the history follows hardware and ffmpeg changes
+const MAX_SAMPLES: u64 = 50;
+
+/// Seconds of media encoded in total over how many seconds of wall clock time
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Throughput {
+    samples: u64,
+    media_seconds: f64,
+    wall_seconds: f64,
+}
+
+impl Throughput {
+    fn speed(&self) -> Option<f64> {
+        (self.wall_seconds > 0.0).then(|| self.media_seconds / self.wall_seconds)
+    }
+
+    fn add(&mut self, media_seconds: f64, wall_seconds: f64) {
+        if self.samples >= MAX_SAMPLES {
+            let keep = (MAX_SAMPLES - 1) as f64 / self.samples as f64;
+            self.media_seconds *= keep;
+            self.wall_seconds *= keep;
+            self.samples = MAX_SAMPLES - 1;
+        }
+        self.samples += 1;
+        self.media_seconds += media_seconds;
+        self.wall_seconds += wall_seconds;
+    }
+}
+
+/// Throughput is kept per mode, and per resolution for modes that encode
+fn key(mode: &str, resolution: Option<(u32, u32)>) -> String {
+    match resolution {
+        Some((_, height)) => format!("{mode}@{height}p"),
+        None => mode.to_string(),
+    }
+}
+
+fn load() -> HashMap<String, Throughput> {
+    std::fs::read_to_string(path::throughput_history_path())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save(history: &HashMap<String, Throughput>) {
+    let path = path::throughput_history_path();
+    let result = std::fs::create_dir_all(path::data_root()).and_then(|()| {
+        std::fs::write(
+            &path,
+            serde_json::to_string_pretty(history).unwrap_or_default(),
+        )
+    });
+    if let Err(e) = result {
+        tracing::warn!(path =% path.display(), error =% e, error_context =? e, "Failed to save throughput history");
+    }
+}
+
+/// Throughput past runs managed, read once up front rather than for every plan
+#[derive(Debug, Clone, Default)]
+pub struct ThroughputHistory(HashMap<String, Throughput>);
+
+impl ThroughputHistory {
+    pub fn load() -> Self {
+        Self(load())
+    }
+
+    /// Seconds of media past runs encoded per wall clock second in `mode` at `resolution`, falling back to every resolution of `mode`
+    pub fn predict_speed(&self, mode: &str, resolution: Option<(u32, u32)>) -> Option<f64> {
+        if let Some(speed) = self
+            .0
+            .get(&key(mode, resolution))
+            .and_then(Throughput::speed)
+        {
+            return Some(speed);
+        }
+
+        let prefix = format!("{mode}@");
+        self.0
+            .iter()
+            .filter(|(key, _)| key.as_str() == mode || key.starts_with(&prefix))
+            .fold(None, |total: Option<Throughput>, (_, throughput)| {
+                let mut total = total.unwrap_or_default();
+                total.media_seconds += throughput.media_seconds;
+                total.wall_seconds += throughput.wall_seconds;
+                Some(total)
+            })
+            .and_then(|total| total.speed())
+    }
+}
+
+struct RunningPlan {
+    key: String,
+    media_seconds: f64,
+    encoding_since: Option<Instant>,
+    /// Set once ffmpeg has exited, so checks run on the finished target don't count as encoding
+    encoded_for: Option<Duration>,
+}
+
+lazy_static! {
+    static ref RUNNING: Mutex<HashMap<Uuid, RunningPlan>> = Mutex::new(HashMap::new());
+}
+
+/// Times each plan's encode from a delivery, saving its throughput once it finishes
+pub fn record(delivery: &ExecuteProgress) {
+    let Ok(mut running) = RUNNING.lock() else {
+        return;
+    };
+
+    match &delivery.payload {
+        ExecuteProgressPayload::Info {
+            total_duration_seconds,
+            mode,
+            resolution,
+            ..
+        } => {
+            running.insert(
+                delivery.id,
+                RunningPlan {
+                    key: key(mode, *resolution),
+                    media_seconds: *total_duration_seconds,
+                    encoding_since: None,
+                    encoded_for: None,
+                },
+            );
+        }
+        // The command is sent as ffmpeg starts, probing and waiting for a slot aren't part of the encode
+        ExecuteProgressPayload::Command { .. } => {
+            if let Some(plan) = running.get_mut(&delivery.id) {
+                plan.encoding_since = Some(Instant::now());
+            }
+        }
+        // Phases after the command has started come once it's done
+        ExecuteProgressPayload::Phase { .. } => {
+            if let Some(plan) = running.get_mut(&delivery.id)
+                && let Some(encoding_since) = plan.encoding_since
+            {
+                plan.encoded_for.get_or_insert(encoding_since.elapsed());
+            }
+        }
+        ExecuteProgressPayload::Finished(_) => {
+            let Some(RunningPlan {
+                key,
+                media_seconds,
+                encoding_since: Some(encoding_since),
+                encoded_for,
+            }) = running.remove(&delivery.id)
+            else {
+                return;
+            };
+            let elapsed = encoded_for.unwrap_or_else(|| encoding_since.elapsed());
+            if elapsed < MIN_SAMPLE || media_seconds <= 0.0 {
+                return;
+            }
+
+            let mut history = load();
+            history
+                .entry(key)
+                .or_default()
+                .add(media_seconds, elapsed.as_secs_f64());
+            save(&history);
+        }
+        ExecuteProgressPayload::Failed(_) => {
+            running.remove(&delivery.id);
+        }
+        _ => {}
+    }
+}
+
+/// Records every delivery on the way to the returned receiver
+pub fn record_all(rx: mpsc::Receiver<ExecuteProgress>) -> mpsc::Receiver<ExecuteProgress> {
+    crate::monitor::tap(rx, record)
+}
@@ -0,0 +1,251 @@
+//! Notifications posted to webhooks as targets start, finish and fail
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{sync::mpsc, task::JoinHandle, task::JoinSet};
+
+use crate::{
+    commands::estimate::format_duration,
+    execute::{ExecuteError, ExecuteProgress, ExecuteProgressPayload},
+    path,
+};
+
+/// Longest a webhook gets to answer before the notification is given up on
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a notification's body is shaped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// An object with the event's fields, or the `templates` entry for the event with its placeholders filled in
+    #[default]
+    Json,
+    /// A Slack incoming webhook message
+    Slack,
+    /// A Discord webhook message
+    Discord,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Start,
+    Finish,
+    Failure,
+}
+
+impl WebhookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::Start => "start",
+            WebhookEvent::Finish => "finish",
+            WebhookEvent::Failure => "failure",
+        }
+    }
+
+    fn default_template(&self) -> &'static str {
+        match self {
+            WebhookEvent::Start => "Started {target}",
+            WebhookEvent::Finish => "Finished {target} in {duration}",
+            WebhookEvent::Failure => "{target} failed after {duration}: {error}",
+        }
+    }
+}
+
+/// Message for each event, with `{event}`, `{target}`, `{duration}`, `{error}` and `{run_id}` filled in
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookTemplates {
+    pub start: Option<String>,
+    pub finish: Option<String>,
+    pub failure: Option<String>,
+}
+
+impl WebhookTemplates {
+    fn get(&self, event: WebhookEvent) -> Option<&str> {
+        match event {
+            WebhookEvent::Start => self.start.as_deref(),
+            WebhookEvent::Finish => self.finish.as_deref(),
+            WebhookEvent::Failure => self.failure.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Events posted, every one when empty
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    #[serde(default)]
+    pub templates: WebhookTemplates,
+}
+
+impl Webhook {
+    /// A webhook given with `--webhook`, posted every event as JSON
+    pub fn from_url(url: &str) -> Result<Self, String> {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(format!("\"{url}\" isn't an http or https URL"));
+        }
+        Ok(Self {
+            url: url.to_string(),
+            format: WebhookFormat::Json,
+            events: vec![],
+            templates: WebhookTemplates::default(),
+        })
+    }
+
+    fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+
+    fn body(&self, notification: &Notification) -> serde_json::Value {
+        let template = self.templates.get(notification.event);
+        match self.format {
+            WebhookFormat::Slack => json!({
+                "text": notification.render(template.unwrap_or(notification.event.default_template()), false),
+            }),
+            WebhookFormat::Discord => json!({
+                "content": notification.render(template.unwrap_or(notification.event.default_template()), false),
+            }),
+            // A template is the whole body, so values are escaped to sit inside its JSON strings
+            WebhookFormat::Json => match template {
+                Some(template) => {
+                    let rendered = notification.render(template, true);
+                    serde_json::from_str(&rendered).unwrap_or_else(|e| {
+                        tracing::warn!(url = self.url, error =% e, error_context =? e, "Webhook template isn't valid JSON once filled in, sending it as a string");
+                        serde_json::Value::String(rendered)
+                    })
+                }
+                None => json!({
+                    "event": notification.event.name(),
+                    "run_id": path::run_id(),
+                    "target_name": notification.target_name,
+                    "elapsed_seconds": notification.elapsed.map(|elapsed| elapsed.as_secs_f64()),
+                    "error": notification.error,
+                    "error_code": notification.error_code,
+                    "message": notification.render(notification.event.default_template(), false),
+                }),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Notification {
+    event: WebhookEvent,
+    target_name: String,
+    elapsed: Option<Duration>,
+    error: Option<String>,
+    error_code: Option<u16>,
+}
+
+impl Notification {
+    fn render(&self, template: &str, escape_json: bool) -> String {
+        let escape = |value: &str| {
+            if escape_json {
+                let quoted = serde_json::Value::String(value.to_string()).to_string();
+                quoted[1..quoted.len() - 1].to_string()
+            } else {
+                value.to_string()
+            }
+        };
+
+        template
+            .replace("{event}", self.event.name())
+            .replace("{target}", &escape(&self.target_name))
+            .replace(
+                "{duration}",
+                &self
+                    .elapsed
+                    .map(|elapsed| format_duration(elapsed.as_secs_f64()))
+                    .unwrap_or_default(),
+            )
+            .replace(
+                "{error}",
+                &escape(self.error.as_deref().unwrap_or_default()),
+            )
+            .replace("{run_id}", path::run_id())
+    }
+}
+
+async fn post(client: reqwest::Client, webhook: Webhook, notification: Notification) {
+    let result = client
+        .post(&webhook.url)
+        .json(&webhook.body(&notification))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = result {
+        tracing::warn!(url = webhook.url, event = notification.event.name(), target_name = notification.target_name, error =% e, error_context =? e, "Failed to post webhook");
+    }
+}
+
+/// Posts a notification to each of `webhooks` interested in a delivery on the way to the returned receiver. The handle
+/// settles once the channel closes and every notification has been sent
+pub fn notify(
+    webhooks: Vec<Webhook>,
+    rx: mpsc::Receiver<ExecuteProgress>,
+) -> (mpsc::Receiver<ExecuteProgress>, JoinHandle<()>) {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let mut targets = HashMap::new();
+
+    // A slow webhook shouldn't hold up the monitor, so requests are only waited on once the channel closes
+    let (forwarded, requests) =
+        crate::monitor::tap_with(rx, JoinSet::new(), move |requests, delivery| {
+            let notification = match &delivery.payload {
+                ExecuteProgressPayload::Start { target_name } => {
+                    targets.insert(delivery.id, (target_name.clone(), Instant::now()));
+                    Some((WebhookEvent::Start, None, None))
+                }
+                ExecuteProgressPayload::Finished(_) => Some((WebhookEvent::Finish, None, None)),
+                // Cancelling is asked for, it isn't worth a notification per target
+                ExecuteProgressPayload::Failed(ExecuteError::Cancelled) => None,
+                ExecuteProgressPayload::Failed(error) => Some((
+                    WebhookEvent::Failure,
+                    Some(error.to_string()),
+                    delivery.error_code,
+                )),
+                _ => None,
+            };
+
+            if let Some((event, error, error_code)) = notification
+                && let Some((target_name, started)) = targets.get(&delivery.id)
+            {
+                let notification = Notification {
+                    event,
+                    target_name: target_name.clone(),
+                    elapsed: (event != WebhookEvent::Start).then(|| started.elapsed()),
+                    error,
+                    error_code,
+                };
+                for webhook in webhooks.iter().filter(|webhook| webhook.wants(event)) {
+                    requests.spawn(post(client.clone(), webhook.clone(), notification.clone()));
+                }
+            }
+        });
+
+    let handle = tokio::spawn(async move {
+        match requests.await {
+            Ok(requests) => {
+                requests.join_all().await;
+            }
+            Err(join_error) => {
+                tracing::error!(error =% join_error, error_context =? join_error, "Failed to join webhook notifier");
+            }
+        }
+    });
+
+    (forwarded, handle)
+}